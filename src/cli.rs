@@ -0,0 +1,1941 @@
+//! The command line interface of the `dpt` binary, see [`main_with`].
+//!
+//! Builds with feature `cli` only, which also enables every other feature but `serde`.
+
+use crate::concat::align::{AlignReport, AlignSource};
+use crate::concat::dedupe::DedupeKey;
+use crate::convert::EncodeType;
+use crate::iter::{FilePathEntries, SortOrder};
+use crate::st::aggregator::{Aggregator, Registry};
+use crate::Logger;
+use crate::{CsvReader, CsvReaderOptions, Error, ErrorKind, Result};
+
+use admerge::{Newline, RsMerger, Skip};
+use chrono::NaiveDate;
+use clap::{load_yaml, App, ArgMatches};
+use lazy_static::lazy_static;
+use toml::Value;
+
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+macro_rules! error {
+    ($msg:tt) => {
+        LOGGER.lock().unwrap().error(&format!("{}", $msg));
+        LOGGER
+            .lock()
+            .unwrap()
+            .error("process didn't exit successfully");
+        process::exit(0);
+    };
+    ($format_str:literal, $($msg:tt)*) => {
+        LOGGER.lock().unwrap().error(&format!($format_str, $($msg)*));
+        LOGGER
+            .lock()
+            .unwrap()
+            .error("process didn't exit successfully");
+        process::exit(0);
+    };
+}
+
+macro_rules! warn {
+    ($msg:tt) => {
+        LOGGER.lock().unwrap().warn(&format!("{}", $msg));
+    };
+    ($format_str:literal, $($msg:tt)*) => {
+        LOGGER.lock().unwrap().warn(&format!($format_str, $($msg)*));
+    };
+}
+
+macro_rules! info {
+    ($msg:tt) => {
+        LOGGER.lock().unwrap().info(&format!("{}", $msg));
+    };
+    ($format_str:literal, $($msg:tt)*) => {
+        LOGGER.lock().unwrap().info(&format!($format_str, $($msg)*));
+    };
+}
+
+lazy_static! {
+    // The path of the executable.
+    static ref DIR: PathBuf = {
+        PathBuf::from(env::args().next().unwrap()).parent().unwrap().to_path_buf()
+    };
+
+    // Global logger.
+    static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::open(DIR.join("log.txt")));
+
+    // User configurations.
+    static ref CONFIG: Value = {
+        let contents = fs::read(DIR.join("config.toml"));
+
+        match contents {
+            Err(_) => {
+                error!("cannot locate and read config.toml");
+            }
+            Ok(contents) => {
+                let string = String::from_utf8_lossy(&contents);
+                let config = string.as_ref().parse::<Value>();
+                if config.is_err() {
+                    error!("cannot parse config.toml correctly");
+                }
+                config.unwrap()
+            }
+        }
+    };
+}
+
+/// Runs the `dpt` command line interface with the arguments of the current process.
+///
+/// `dpt st` selects its aggregators from the given registry by name, so a binary can register
+/// its own aggregators besides the built-in ones before calling this:
+///
+/// ```no_run
+/// use dpt::st::aggregator::{Materials, Registry};
+///
+/// let mut registry = Registry::new();
+/// registry.register("sku-copy", || Box::new(Materials::new()));
+/// dpt::cli::main_with(registry);
+/// ```
+///
+/// Exits the process if a subcommand fails.
+#[tokio::main]
+pub async fn main_with(registry: Registry) {
+    let yaml = load_yaml!("../cli.yml");
+    let matches = App::from_yaml(yaml).get_matches();
+
+    if let Err(e) = run(&matches, &registry).await {
+        error!(e);
+    }
+}
+
+async fn run(matches: &ArgMatches<'_>, registry: &Registry) -> Result<()> {
+    // run subcommand `st`.
+    if let Some(m) = matches.subcommand_matches("st") {
+        use crate::st::aggregate::aggregate_with;
+
+        if let Some(t) = m.subcommand_matches("trace") {
+            return trace_st(m, t);
+        }
+        if m.is_present("incremental") {
+            return st_incremental(m);
+        }
+
+        let mut aggregators = create_aggregators(m, registry)?;
+
+        let paths = st_inputs(m)?;
+        let encoding = parse_encoding(m);
+        let (file, should_cleanup, options) = match parse_dedupe_key(m) {
+            // Deduplicated records are rewritten as comma separated records.
+            Some(key) => (
+                open_deduped(m, paths, encoding, key)?,
+                true,
+                CsvReaderOptions::default(),
+            ),
+            None => {
+                let (mut file, should_cleanup) = open_merged(paths, encoding)?;
+                let options = parse_reader_options(m, &mut file, encoding)?;
+                (file, should_cleanup, options)
+            }
+        };
+        let out_dir = output_dir(m, "st")?;
+
+        let strict = m.is_present("strict");
+
+        info!("start aggregating data");
+
+        aggregate_with(
+            file,
+            encoding,
+            &CONFIG.clone(),
+            options,
+            strict,
+            &mut aggregators,
+        )?;
+
+        // remove temp file
+        if should_cleanup {
+            fs::remove_file(TEMP_FILE)?;
+        }
+
+        for aggregator in aggregators.iter() {
+            aggregator.write(out_dir.to_str().unwrap())?;
+        }
+
+        info!("aggregation process has finished");
+        info!({
+            format!(
+                "result files can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    };
+
+    // run subcommand `ios`.
+    if let Some(m) = matches.subcommand_matches("ios") {
+        use crate::ios::aggregate::{aggregate_ios, join_st};
+        use crate::ios::writer::write_ios_result;
+        use crate::st::aggregate::aggregate_with;
+        use crate::st::aggregator::Materials;
+
+        let encoding = parse_encoding(m);
+        let out_dir = output_dir(m, "ios")?;
+        let strict = m.is_present("strict");
+
+        info!("start aggregating IOS report");
+
+        let file = File::open(m.value_of_os("input").unwrap())?;
+        let options = CsvReaderOptions::default();
+        let (mut imap, wmap) = aggregate_ios(file, encoding, &CONFIG.clone(), options, strict)?;
+
+        if let Some(paths) = m.values_of_os("st") {
+            info!("start aggregating ST records");
+
+            let paths = paths.map(|s| s.to_os_string()).collect();
+            let (file, should_cleanup) = open_merged(paths, encoding)?;
+            // Only the statistics per material are joined.
+            let mut materials = Materials::new();
+            aggregate_with(
+                file,
+                encoding,
+                &CONFIG.clone(),
+                options,
+                strict,
+                std::slice::from_mut(&mut materials),
+            )?;
+            if should_cleanup {
+                fs::remove_file(TEMP_FILE)?;
+            }
+
+            join_st(&mut imap, materials.mmap());
+        }
+
+        write_ios_result((imap, wmap), out_dir.to_str().unwrap())?;
+
+        info!("aggregation process has finished");
+        info!({
+            format!(
+                "result files can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    }
+
+    // run subcommand `ie`.
+    if let Some(m) = matches.subcommand_matches("ie") {
+        use crate::ie::reconcile::{reconcile, Filter};
+        use crate::ie::writer::write_mismatches;
+
+        let encoding = parse_encoding(m);
+        let out_dir = output_dir(m, "ie")?;
+        let strict = m.is_present("strict");
+
+        let date = match m.value_of("date") {
+            Some(date) => Some(
+                NaiveDate::parse_from_str(date, "%Y/%m/%d")
+                    .map_err(|_| Error::new(ErrorKind::FromStr(date.to_owned(), "date")))?,
+            ),
+            None => None,
+        };
+        let warehouses = m
+            .values_of("warehouse")
+            .unwrap()
+            .map(|w| {
+                w.trim()
+                    .parse::<u16>()
+                    .map_err(|_| Error::new(ErrorKind::FromStr(w.to_owned(), "u16")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let filter = Filter { date, warehouses };
+
+        info!("start reconciling IE report");
+
+        let ie = File::open(m.value_of_os("input").unwrap())?;
+        let paths = m
+            .values_of_os("st")
+            .unwrap()
+            .map(|s| s.to_os_string())
+            .collect();
+        let (st, should_cleanup) = open_merged(paths, encoding)?;
+        let options = CsvReaderOptions::default();
+        let mismatches = reconcile(ie, st, encoding, &CONFIG.clone(), options, &filter, strict)?;
+        if should_cleanup {
+            fs::remove_file(TEMP_FILE)?;
+        }
+
+        write_mismatches(&mismatches, out_dir.to_str().unwrap())?;
+
+        info!(
+            "reconciliation process has finished, {} mismatches found",
+            mismatches.len()
+        );
+        info!({
+            format!(
+                "result files can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    }
+
+    // run subcommand `db`.
+    if let Some(m) = matches.subcommand_matches("db") {
+        use crate::db::Database;
+
+        if let Some(m) = m.subcommand_matches("import") {
+            let mut db = Database::open(db_path(m))?;
+            let paths = st_inputs(m)?;
+            if paths.is_empty() {
+                return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+                    "no input files were given",
+                ))));
+            }
+            let encoding = parse_encoding(m);
+            let strict = m.is_present("strict");
+
+            for path in paths.iter().map(PathBuf::from) {
+                let rdr = open_st_file(m, &path, encoding)?;
+                let source = fs::canonicalize(&path)?;
+                let report = db.import(rdr, &source.to_string_lossy(), &CONFIG.clone(), strict)?;
+                info!(
+                    "`{}`: {} records, {} new, {} imported before, {} rows skipped",
+                    path.display(),
+                    report.records,
+                    report.inserted,
+                    report.existing,
+                    report.skipped
+                );
+            }
+
+            if let Some((min, max)) = db.date_range()? {
+                info!(
+                    "the database holds {} records from {} to {}",
+                    db.count(None, None)?,
+                    min,
+                    max
+                );
+            }
+        }
+
+        if let Some(m) = m.subcommand_matches("st") {
+            let mut aggregators = create_aggregators(m, registry)?;
+
+            let db = Database::open(db_path(m))?;
+            let since = parse_date_of(m, "from")?;
+            let until = parse_date_of(m, "to")?;
+            let out_dir = output_dir(m, "st")?;
+
+            info!("start aggregating data");
+
+            let n = db.aggregate(since, until, &CONFIG.clone(), &mut aggregators)?;
+            if n == 0 {
+                return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+                    "no records found in the given date range",
+                ))));
+            }
+            info!("{} records read from the database", n);
+
+            for aggregator in aggregators.iter() {
+                aggregator.write(out_dir.to_str().unwrap())?;
+            }
+
+            info!("aggregation process has finished");
+            info!({
+                format!(
+                    "result files can be found in path `{}`",
+                    fs::canonicalize(out_dir)?.display()
+                )
+            });
+        }
+    }
+
+    // run subcommand `query`.
+    if let Some(m) = matches.subcommand_matches("query") {
+        use crate::query::writer::write_query_result;
+        use crate::query::{query, Executor, Query};
+
+        let q = Query::parse(
+            m.value_of("where"),
+            m.value_of("group-by"),
+            m.value_of("agg"),
+        )?;
+        let out_dir = output_dir(m, "query")?;
+        let strict = m.is_present("strict");
+
+        info!("start querying data");
+
+        let result = if m.is_present("db") {
+            let db = crate::db::Database::open(db_path(m))?;
+            let mut executor = Executor::new(&q, &CONFIG.clone())?;
+            db.for_each_record(None, None, |record| {
+                executor.observe(&record);
+                Ok(())
+            })?;
+            executor.finish()
+        } else {
+            let encoding = parse_encoding(m);
+            let (mut file, should_cleanup) = open_merged(st_inputs(m)?, encoding)?;
+            let options = parse_reader_options(m, &mut file, encoding)?;
+            let result = query(file, encoding, &CONFIG.clone(), options, &q, strict)?;
+            if should_cleanup {
+                fs::remove_file(TEMP_FILE)?;
+            }
+            result
+        };
+
+        write_query_result(&result, out_dir.to_str().unwrap())?;
+
+        info!("query has finished, {} rows written", result.rows.len());
+        info!({
+            format!(
+                "result files can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    }
+
+    // run subcommand `jde`.
+    if let Some(m) = matches.subcommand_matches("jde") {
+        use crate::jde::{self, *};
+        use jde::jobs::*;
+        use jde::time::*;
+
+        let jde = jde::parse_config_jde_table(&CONFIG.clone())?;
+        let locators = jde::parse_config_locator_table(&CONFIG.clone())?;
+        let driver_port = m.value_of("port").unwrap().parse::<u32>().unwrap();
+
+        // Spawns a server thread that runs the webdriver.
+        let _driver_handle = startup_driver(driver_port, &jde.browser_path)?;
+        info!("webdriver listens at port {}", driver_port);
+
+        // Logins to JDE.
+        let client = login(driver_port, &jde, &locators).await?;
+        info!("login success");
+
+        // Creates a JDE client.
+        let c = JdeClient::new(client);
+
+        // Time related
+        let yyyymm = yyyymm();
+        let today = today();
+        let yestoday = yestoday();
+        let nextday = nextday();
+        let century = century();
+        let short_year = short_year();
+        let month = month();
+
+        // NOTE: We must keep a handle to the client otherwise the browser will be closed when
+        // jobs were finished.
+        let _c = if matches.is_present("pm") {
+            info!("start calculating IOS report");
+            let c = calculate_ios_report(c, &yyyymm, &yyyymm, &locators).await?;
+
+            info!("start downloading ST records (request date: {})", nextday);
+            let c = download_st_records(c, &nextday, "*", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!("start downloading IOS report");
+            let c = download_ios_report(c, &century, &short_year, &month, &locators).await?;
+            info!("finish downloading IOS report");
+
+            c
+        } else if matches.is_present("am") {
+            info!("start downloading ST records (request date: {})", nextday);
+            let c = download_st_records(c, &nextday, "*", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!(
+                "start downloading ST records (request date: {}, repository: 11751)",
+                today
+            );
+            let c = download_st_records(c, &today, "11751", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!(
+                "start calculating IE report (request date: {}, repository: 11751, 11759)",
+                yestoday
+            );
+            let c = calculate_ie_report(c, &yestoday, &locators).await?;
+
+            let c = open_report_menu(c, &locators).await?;
+
+            c
+        } else {
+            info!("start calculating IOS report");
+            let c = calculate_ios_report(c, &yyyymm, &yyyymm, &locators).await?;
+
+            info!(
+                "start calculating IE report (request date: {}, repository: 11751, 11759)",
+                yestoday
+            );
+            let c = calculate_ie_report(c, &yestoday, &locators).await?;
+
+            info!("start downloading ST records (request date: {})", nextday);
+            let c = download_st_records(c, &nextday, "*", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!("start downloading ST records (request date: {})", today);
+            let c = download_st_records(c, &today, "*", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!(
+                "start downloading ST records (request date: {}, repository: 11752)",
+                today
+            );
+            let c = download_st_records(c, &today, "11752", &locators).await?;
+            info!("finish downloading ST records");
+
+            info!(
+                "start downloading ST records (request date: {}, repository: 11751)",
+                today
+            );
+            let c = download_st_records_advanced(c, &today, &locators).await?;
+            info!("finish downloading ST records");
+
+            info!("start downloading IOS report");
+            let c = download_ios_report(c, &century, &short_year, &month, &locators).await?;
+            info!("finish downloading IOS report");
+
+            // Downloads last week's ST records if today is Sunday.
+            //
+            // let c = if time::today_is_monday() {
+            //     info!(
+            //         "start downloading ST records (request date: >={}, repository: 11751)",
+            //         &time::today_pred(6)
+            //     );
+            //     let c = download_st_records_from(c, &time::today_pred(6), "*", &locators).await?;
+            //     info!("finish downloading ST records");
+            //     c
+            // } else {
+            //     c
+            // };
+
+            let c = open_report_menu(c, &locators).await?;
+
+            c
+        };
+
+        // let user manually exit this program when running command `jde`.
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        ctrlc::set_handler(move || {
+            r.store(false, Ordering::SeqCst);
+        })
+        .expect("error setting ctrl-c handler");
+
+        info!("job done! use ctrl-c to exit process");
+        while running.load(Ordering::SeqCst) {}
+        info!("process exited successfully");
+
+        // driver_handle.kill()?;
+    }
+
+    // run subcommand `concat`.
+    if let Some(m) = matches.subcommand_matches("concat") {
+        use crate::convert::{csv2csv, is_spreadsheet, transcode, Bom, TranscodeOptions};
+
+        let input: Vec<PathBuf> = if let Some(file_paths) = m.values_of("input") {
+            let mut entries = file_paths.map(PathBuf::from).collect::<FilePathEntries>();
+            if let Some(order) = parse_sort_order(m) {
+                entries.sort(order)?;
+            }
+            entries.into_iter().collect()
+        } else if let Some(dir) = m.value_of("directory") {
+            discover(m, dir, &[])?
+        } else {
+            vec![]
+        };
+
+        let out_dir = m.value_of("output").unwrap();
+        let skip_start = if let Some(n) = m.value_of("skip-start") {
+            n.parse::<usize>().map_err(|_| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "skip-start only accepts number, but found `{}`",
+                    n
+                ))))
+            })?
+        } else {
+            0
+        };
+        let skip_end = if let Some(n) = m.value_of("skip-end") {
+            n.parse::<usize>().map_err(|_| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "skip-end only accepts number, but found `{}`",
+                    n
+                ))))
+            })?
+        } else {
+            0
+        };
+        let headless = m.is_present("headless");
+        let headonce = m.is_present("headonce");
+        let newline = m.is_present("newline");
+
+        let mut merger = RsMerger::new();
+        let mut writer = OpenOptions::new().write(true).create(true).open(out_dir)?;
+        if skip_start > 0 {
+            merger.skip_head(Skip::Lines(skip_start));
+        } else if headless {
+            merger.skip_head(Skip::Lines(1));
+        } else if headonce {
+            merger.skip_head(Skip::LinesOnce(1));
+        }
+        if skip_end > 0 {
+            merger.skip_tail(Skip::Lines(skip_end));
+        }
+        if newline {
+            merger.force_ending_newline(Newline::Crlf);
+        }
+
+        let encodings = parse_encodings(m, input.len())?;
+        // Sources of mixed or detected encodings are transcoded into a single encoding.
+        let to = match m.value_of("to") {
+            Some(_) => Some(parse_encoding_of(m, "to")),
+            None if encodings.contains(&EncodeType::Auto)
+                || encodings.windows(2).any(|w| w[0] != w[1]) =>
+            {
+                Some(EncodeType::UTF8)
+            }
+            None => None,
+        };
+
+        if m.is_present("align") || m.is_present("dedupe") {
+            use crate::concat::align::{concat_aligned, AlignOptions};
+
+            let options = AlignOptions {
+                output_encoding: match to {
+                    Some(to) => to,
+                    None => encodings.first().copied().unwrap_or(EncodeType::UTF8),
+                },
+                skip_end,
+                strict: m.is_present("strict-schema"),
+                dedupe: parse_dedupe_key(m),
+            };
+            let sources = align_sources(m, &input, &encodings)?;
+            let report = concat_aligned(sources, BufWriter::new(File::create(out_dir)?), &options)?;
+            log_align_report(&report);
+
+            info!("The given files have been successfully merged");
+            info!({
+                format!(
+                    "result file can be found in path `{}`",
+                    fs::canonicalize(out_dir)?.display()
+                )
+            });
+            return Ok(());
+        }
+
+        let mut sources: Vec<Box<dyn ReadSeek>> = Vec::with_capacity(input.len());
+        for (path, &encoding) in input.iter().zip(encodings.iter()) {
+            if is_spreadsheet(path) {
+                // Spreadsheets are converted into the output encoding right away.
+                let encoding = to.map_or(encoding, |_| EncodeType::UTF8);
+                sources.push(Box::new(read_spreadsheet(path, encoding)?));
+                continue;
+            }
+
+            let mut file = File::open(path)?;
+            let source: Box<dyn ReadSeek> = if m.is_present("delimiter") {
+                // Rewrites the source as comma separated records before merging.
+                let options = parse_reader_options(m, &mut file, encoding)?;
+                let mut buf = Vec::new();
+                csv2csv(BufReader::new(file), encoding, options, &mut buf)?;
+                Box::new(Cursor::new(buf))
+            } else {
+                Box::new(file)
+            };
+            match to {
+                // Sources are merged as UTF-8, which is transcoded into the output encoding after.
+                Some(_) => {
+                    // `csv2csv` writes detected encodings as UTF-8.
+                    let from = match encoding {
+                        EncodeType::Auto if m.is_present("delimiter") => EncodeType::UTF8,
+                        encoding => encoding,
+                    };
+                    sources.push(Box::new(transcode_source(path, source, from)?));
+                }
+                None => sources.push(source),
+            }
+        }
+        match to {
+            Some(to) if to != EncodeType::UTF8 => {
+                let mut buf = Vec::new();
+                merger
+                    .merge_sources_into(sources, &mut buf)
+                    .map_err(merge_error)?;
+                let options = TranscodeOptions {
+                    from: EncodeType::UTF8,
+                    to,
+                    bom: if to.is_utf16() { Bom::Add } else { Bom::Remove },
+                };
+                transcode(Cursor::new(buf), &mut writer, options)?;
+            }
+            _ => merger
+                .merge_sources_into(sources, &mut writer)
+                .map_err(merge_error)?,
+        }
+
+        info!("The given files have been successfully merged");
+        info!({
+            format!(
+                "result file can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    }
+
+    // run subcommand `convert`.
+    if let Some(m) = matches.subcommand_matches("convert") {
+        use crate::convert::*;
+        use rayon::prelude::*;
+
+        let is_encoding = m.value_of("type") == Some("encoding");
+        let is_xlsx = m.value_of("type") == Some("xlsx");
+
+        // Pairs each input file with its path relative to the output directory.
+        let input: Vec<(PathBuf, PathBuf)> = if let Some(file_paths) = m.values_of("file") {
+            let mut input = Vec::new();
+            for p in file_paths {
+                let path = PathBuf::from(p);
+                let rel = match path.file_name() {
+                    Some(name) => PathBuf::from(name),
+                    None => {
+                        return Err(Error::new(ErrorKind::Access(format!("{}", path.display()))))
+                    }
+                };
+                input.push((path, rel));
+            }
+            input
+        } else if let Some(dir) = m.value_of("dir") {
+            let globs = if is_xlsx {
+                vec!["*.csv".to_owned()]
+            } else if !is_encoding {
+                SPREADSHEET_EXTENSIONS
+                    .iter()
+                    .map(|ext| format!("*.{}", ext))
+                    .collect()
+            } else {
+                vec![]
+            };
+            discover(m, dir, &globs)?
+                .into_iter()
+                .map(|path| {
+                    let rel = path.strip_prefix(dir).unwrap().to_path_buf();
+                    (path, rel)
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        if input.is_empty() {
+            return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+                "no input files were given",
+            ))));
+        }
+
+        if m.is_present("list-sheets") {
+            for (path, _) in input.iter() {
+                info!("`{}`: {}", path.display(), list_sheets(path)?.join(", "));
+            }
+            return Ok(());
+        }
+
+        let mode = if is_encoding {
+            ConvertMode::Encoding(TranscodeOptions {
+                from: parse_encoding_of(m, "from"),
+                to: match m.value_of("to") {
+                    Some(_) => parse_encoding_of(m, "to"),
+                    None => EncodeType::UTF8,
+                },
+                bom: match m.value_of("bom") {
+                    Some("add") => Bom::Add,
+                    Some("remove") => Bom::Remove,
+                    _ => Bom::Keep,
+                },
+            })
+        } else if is_xlsx {
+            ConvertMode::Xlsx(Csv2XlsxOptions {
+                encoding: parse_encoding_of(m, "from"),
+                reader: None,
+            })
+        } else {
+            let sheets = if m.is_present("all-sheets") {
+                SheetSelector::All
+            } else if let Some(name) = m.value_of("sheet") {
+                SheetSelector::Name(name.to_owned())
+            } else if let Some(n) = m.value_of("sheet-index") {
+                match n.parse::<usize>() {
+                    Ok(n) if n > 0 => SheetSelector::Index(n - 1),
+                    _ => {
+                        return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                            "sheet-index only accepts positive numbers, but found `{}`",
+                            n
+                        )))))
+                    }
+                }
+            } else {
+                SheetSelector::default()
+            };
+
+            let default = Xlsx2CsvOptions::default();
+            ConvertMode::Filetype(Xlsx2CsvOptions {
+                sheets,
+                encoding: parse_encoding_of(m, "to"),
+                date_format: m
+                    .value_of("date-format")
+                    .map_or(default.date_format, |s| s.to_owned()),
+                datetime_format: m
+                    .value_of("datetime-format")
+                    .map_or(default.datetime_format, |s| s.to_owned()),
+                time_format: default.time_format,
+            })
+        };
+
+        let out_dir = m
+            .value_of_os("output")
+            .map_or_else(|| PathBuf::from("."), PathBuf::from);
+        let force = m.is_present("force");
+        let jobs = plan_conversions(input, &out_dir, &mode)?;
+
+        info!("start converting {} files", jobs.len());
+
+        let results = jobs
+            .par_iter()
+            .map(|job| convert_file(job, &mode, force))
+            .collect::<Vec<_>>();
+
+        let mut skipped = 0;
+        let mut failed = 0;
+        for (ConvertJob { path, .. }, result) in jobs.iter().zip(results) {
+            match result {
+                Ok(Converted::Skipped) => skipped += 1,
+                Ok(Converted::Files(outputs, report)) => {
+                    for out_path in outputs {
+                        info!(
+                            "`{}` has been converted to `{}`",
+                            path.display(),
+                            out_path.display()
+                        );
+                    }
+                    if let Some(report) = report.filter(|r| r.malformed > 0) {
+                        warn!(
+                            "`{}` contains {} undecodable byte sequences (decoded as {:?}), which have been replaced",
+                            path.display(),
+                            report.malformed,
+                            report.encoding
+                        );
+                    }
+                }
+                Err(e) => {
+                    failed += 1;
+                    warn!("failed to convert `{}`: {}", path.display(), e);
+                }
+            }
+        }
+
+        if skipped > 0 {
+            info!("skipped {} files whose outputs are up to date", skipped);
+        }
+        if failed > 0 {
+            return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                "{} of {} files failed to convert",
+                failed,
+                jobs.len()
+            )))));
+        }
+        info!("conversion process has finished");
+        info!({
+            format!(
+                "converted files can be found in path `{}`",
+                fs::canonicalize(out_dir)?.display()
+            )
+        });
+    }
+
+    Ok(())
+}
+
+/// What subcommand `convert` converts files to.
+enum ConvertMode {
+    Encoding(crate::convert::TranscodeOptions),
+    Filetype(crate::convert::Xlsx2CsvOptions),
+    Xlsx(crate::convert::Csv2XlsxOptions),
+}
+
+/// The outcome of converting a single file.
+enum Converted {
+    /// The outputs are newer than the input.
+    Skipped,
+    Files(Vec<PathBuf>, Option<crate::convert::TranscodeReport>),
+}
+
+/// A file of subcommand `convert` with the files it is converted to.
+struct ConvertJob {
+    path: PathBuf,
+    out_dir: PathBuf,
+    outputs: Vec<PathBuf>,
+}
+
+/// Computes the outputs of each input file, given with its path relative to `out_dir`, before
+/// anything is converted.
+///
+/// Files are converted in parallel, so two inputs writing the same output, such as `foo.xlsx`
+/// and `foo.ods`, would overwrite each other and are rejected instead.
+fn plan_conversions(
+    input: Vec<(PathBuf, PathBuf)>,
+    out_dir: &Path,
+    mode: &ConvertMode,
+) -> Result<Vec<ConvertJob>> {
+    use crate::convert::xlsx2csv_outputs;
+    use std::collections::HashMap;
+
+    let mut jobs = Vec::with_capacity(input.len());
+    // Output paths are compared case-insensitively, as file systems on Windows are.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (path, rel) in input {
+        let out_dir = match rel.parent() {
+            Some(parent) => out_dir.join(parent),
+            None => out_dir.to_path_buf(),
+        };
+        let filename = match rel.file_stem() {
+            Some(stem) => stem.to_string_lossy(),
+            None => return Err(Error::new(ErrorKind::Access(format!("{}", path.display())))),
+        };
+        let outputs = match mode {
+            ConvertMode::Encoding(_) => vec![out_dir.join(rel.file_name().unwrap())],
+            ConvertMode::Filetype(options) => xlsx2csv_outputs(&path, &out_dir, &options.sheets)?,
+            ConvertMode::Xlsx(_) => vec![out_dir.join(format!("{}.xlsx", filename))],
+        };
+
+        for output in outputs.iter() {
+            let key = output.to_string_lossy().to_lowercase();
+            if let Some(&i) = seen.get(&key) {
+                let other: &ConvertJob = &jobs[i];
+                return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "`{}` and `{}` would both be converted to `{}`, please convert them separately",
+                    other.path.display(),
+                    path.display(),
+                    output.display()
+                )))));
+            }
+            seen.insert(key, jobs.len());
+        }
+        jobs.push(ConvertJob {
+            path,
+            out_dir,
+            outputs,
+        });
+    }
+    Ok(jobs)
+}
+
+/// Converts a single file of subcommand `convert`, unless all of its outputs are newer than the
+/// file itself.
+fn convert_file(job: &ConvertJob, mode: &ConvertMode, force: bool) -> Result<Converted> {
+    use crate::convert::*;
+
+    let ConvertJob {
+        path,
+        out_dir,
+        outputs,
+    } = job;
+    if !force && is_up_to_date(path, outputs)? {
+        return Ok(Converted::Skipped);
+    }
+
+    fs::create_dir_all(out_dir)?;
+    match mode {
+        ConvertMode::Encoding(options) => {
+            let out_path = &outputs[0];
+            if out_path.exists() && fs::canonicalize(out_path)? == fs::canonicalize(path)? {
+                return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "refusing to overwrite input file `{}`",
+                    path.display()
+                )))));
+            }
+
+            let rdr = File::open(path)?;
+            let wtr = BufWriter::new(File::create(out_path)?);
+            let report = transcode(rdr, wtr, *options)?;
+            Ok(Converted::Files(outputs.clone(), Some(report)))
+        }
+        ConvertMode::Filetype(options) => {
+            Ok(Converted::Files(xlsx2csv(path, out_dir, options)?, None))
+        }
+        ConvertMode::Xlsx(options) => Ok(Converted::Files(
+            vec![csv2xlsx(path, out_dir, options)?],
+            None,
+        )),
+    }
+}
+
+/// Returns `true` if all outputs exist and were modified after the input.
+fn is_up_to_date(input: &Path, outputs: &[PathBuf]) -> Result<bool> {
+    let modified = fs::metadata(input)?.modified()?;
+    for output in outputs {
+        match fs::metadata(output) {
+            Ok(meta) if meta.modified()? >= modified => (),
+            _ => return Ok(false),
+        }
+    }
+    Ok(!outputs.is_empty())
+}
+
+// The temporary file used to hold merged inputs.
+const TEMP_FILE: &str = "./_dpt_temp_.csv";
+
+/// Opens the given files as a single file, returning whether the opened file is a temporary
+/// file that should be removed afterwards.
+///
+/// If more than one path or a spreadsheet is given, the files are merged into a temporary file,
+/// preserving the header line of the first file and skipping the tail line of each file.
+///
+/// Each file is decoded with its own encoding before merging, detected per file with
+/// [`EncodeType::Auto`], so that lines are skipped by decoded lines rather than raw bytes. The
+/// temporary file is written in `encoding`, or as UTF-8 with a BOM if it is `Auto`, so it can
+/// be read with `encoding` like a single file.
+fn open_merged(paths: Vec<OsString>, encoding: EncodeType) -> Result<(File, bool)> {
+    use crate::convert::{encode, is_spreadsheet};
+    use crate::iter::MultiFileReader;
+    use std::io::Write;
+
+    if paths.is_empty() {
+        return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+            "no input files were given",
+        ))));
+    }
+    if paths.len() == 1 && !is_spreadsheet(&paths[0]) {
+        return Ok((File::open(&paths[0])?, false));
+    }
+
+    let mut temp = BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(TEMP_FILE)?,
+    );
+    if encoding == EncodeType::Auto {
+        temp.write_all(EncodeType::UTF8.bom().unwrap())?;
+    }
+
+    let mut buf = Vec::new();
+    for (i, path) in paths.iter().enumerate() {
+        let mut rdr: Box<dyn BufRead> = if is_spreadsheet(path) {
+            Box::new(read_spreadsheet(path, EncodeType::UTF8)?)
+        } else {
+            let mut rdr = MultiFileReader::new(std::iter::once(path))?;
+            rdr.encoding(encoding);
+            Box::new(rdr)
+        };
+
+        // A line is written once the next one has been read, so the tail line is never written.
+        let mut held: Option<String> = None;
+        let mut line = String::new();
+        let mut n = 0;
+        while rdr.read_line(&mut line)? > 0 {
+            n += 1;
+            if !line.ends_with('\n') {
+                line.push_str("\r\n");
+            }
+            if i > 0 && n == 1 {
+                line.clear();
+                continue;
+            }
+            if let Some(prev) = held.replace(std::mem::take(&mut line)) {
+                encode(&prev, encoding, &mut buf)?;
+                temp.write_all(&buf)?;
+            }
+        }
+    }
+    temp.flush()?;
+
+    Ok((File::open(TEMP_FILE)?, true))
+}
+
+/// Returns the input files of subcommand `st`, given by option `input` or `directory`.
+fn st_inputs(m: &ArgMatches<'_>) -> Result<Vec<OsString>> {
+    let paths = if m.is_present("input") {
+        let mut entries = m
+            .values_of_os("input")
+            .unwrap()
+            .map(PathBuf::from)
+            .collect::<FilePathEntries>();
+        if let Some(order) = parse_sort_order(m) {
+            entries.sort(order)?;
+        }
+        entries.into_iter().map(|p| p.into_os_string()).collect()
+    } else if m.is_present("directory") {
+        let dir = m.value_of("directory").unwrap();
+        let mut globs = vec!["*.csv".to_owned()];
+        globs.extend(
+            crate::convert::SPREADSHEET_EXTENSIONS
+                .iter()
+                .map(|ext| format!("*.{}", ext)),
+        );
+        discover(m, dir, &globs)?
+            .into_iter()
+            .map(|p| p.into_os_string())
+            .collect()
+    } else {
+        Default::default()
+    };
+    Ok(paths)
+}
+
+/// Runs subcommand `st` with flag `incremental`, folding the input files that have not been
+/// ingested yet into the persisted aggregation state, then writing the results of the whole
+/// state.
+fn st_incremental(m: &ArgMatches<'_>) -> Result<()> {
+    use crate::st::aggregate::State;
+    use crate::st::aggregator::Aggregator;
+    use crate::st::state::file_hash;
+
+    // Only the built-in aggregators are persisted in the state.
+    const BUILT_IN: [&str; 3] = ["sku", "store", "brand"];
+    let names = match m.values_of("aggregators") {
+        Some(names) => names.map(|name| name.trim()).collect::<Vec<_>>(),
+        None => BUILT_IN.to_vec(),
+    };
+    if let Some(name) = names.iter().find(|name| !BUILT_IN.contains(name)) {
+        return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+            "aggregator `{}` cannot be used with --incremental, expected one of {}",
+            name,
+            BUILT_IN.join(", ")
+        )))));
+    }
+
+    let state_dir = match m.value_of_os("state") {
+        Some(dir) => PathBuf::from(dir),
+        None => DIR.join("st_state"),
+    };
+    // A save that failed part-way may have left the state moved aside.
+    State::recover(&state_dir)?;
+    let mut state = if state_dir.is_dir() {
+        info!({ format!("loading aggregation state from `{}`", state_dir.display()) });
+        State::load(&state_dir)?
+    } else {
+        info!("no aggregation state found, starting a new one");
+        State::new()
+    };
+
+    let paths = st_inputs(m)?;
+    if paths.is_empty() {
+        return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+            "no input files were given",
+        ))));
+    }
+    let encoding = parse_encoding(m);
+    let strict = m.is_present("strict");
+    let out_dir = output_dir(m, "st")?;
+
+    let mut ingested = 0;
+    for path in paths.iter().map(PathBuf::from) {
+        let hash = file_hash(&path)?;
+        if let Some(prev) = state.ingested(&hash) {
+            info!({
+                format!(
+                    "skipping `{}`, already ingested as `{}`",
+                    path.display(),
+                    prev.display()
+                )
+            });
+            continue;
+        }
+        let canonical = fs::canonicalize(&path)?;
+        if state.ledger().any(|(_, p)| p == canonical) {
+            return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                "`{}` has changed since it was ingested, rebuild the aggregation state",
+                path.display()
+            )))));
+        }
+
+        info!({ format!("ingesting `{}`", path.display()) });
+        state.fold(open_st_file(m, &path, encoding)?, &CONFIG.clone(), strict)?;
+        state.mark_ingested(canonical, hash);
+        ingested += 1;
+    }
+
+    state.save(&state_dir)?;
+    info!({
+        format!(
+            "{} new file(s) ingested, {} file(s) in total",
+            ingested,
+            state.ledger().count()
+        )
+    });
+
+    let (materials, stores, brands) = state.into_aggregators();
+    let aggregators: [&dyn Aggregator; 3] = [&materials, &stores, &brands];
+    for (name, aggregator) in BUILT_IN.iter().zip(aggregators.iter()) {
+        if names.contains(name) {
+            aggregator.write(out_dir.to_str().unwrap())?;
+        }
+    }
+
+    info!("aggregation process has finished");
+    info!({
+        format!(
+            "result files can be found in path `{}`",
+            fs::canonicalize(out_dir)?.display()
+        )
+    });
+
+    Ok(())
+}
+
+/// Opens a single ST file for reading, converting it to CSV first if it is a spreadsheet.
+fn open_st_file(
+    m: &ArgMatches<'_>,
+    path: &Path,
+    encoding: EncodeType,
+) -> Result<CsvReader<Box<dyn BufRead>>> {
+    use crate::convert::is_spreadsheet;
+
+    if is_spreadsheet(path) {
+        let mut src = read_spreadsheet(path, EncodeType::UTF8)?;
+        let options = parse_reader_options(m, &mut src, EncodeType::UTF8)?;
+        Ok(CsvReader::with_options(
+            Box::new(src),
+            EncodeType::UTF8,
+            options,
+        ))
+    } else {
+        let mut file = File::open(path)?;
+        let options = parse_reader_options(m, &mut file, encoding)?;
+        Ok(CsvReader::with_options(
+            Box::new(BufReader::new(file)),
+            encoding,
+            options,
+        ))
+    }
+}
+
+/// Returns the value of option `db`, or `st.db` next to the executable.
+fn db_path(m: &ArgMatches<'_>) -> PathBuf {
+    match m.value_of_os("db") {
+        Some(path) => PathBuf::from(path),
+        None => DIR.join("st.db"),
+    }
+}
+
+/// Runs subcommand `st trace`, printing the raw records a material or a store was aggregated
+/// from, prefixed with their files and line numbers.
+fn trace_st(st: &ArgMatches<'_>, m: &ArgMatches<'_>) -> Result<()> {
+    use crate::convert::is_spreadsheet;
+    use crate::iter::MultiFileReader;
+    use crate::st::aggregate::aggregate_traced;
+
+    // Input options may be given either before or after `trace`.
+    let inputs = if m.is_present("input") || m.is_present("directory") {
+        m
+    } else {
+        st
+    };
+    let paths = st_inputs(inputs)?;
+    if paths.is_empty() {
+        return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+            "no input files were given",
+        ))));
+    }
+    if let Some(path) = paths.iter().find(|p| is_spreadsheet(p)) {
+        return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+            "cannot trace records of spreadsheet `{}`, please convert it to .csv first",
+            Path::new(path).display()
+        )))));
+    }
+
+    let parse_id = |name: &str| -> Result<Option<u32>> {
+        match m.value_of(name) {
+            Some(id) => id
+                .trim()
+                .parse::<u32>()
+                .map(Some)
+                .map_err(|_| Error::new(ErrorKind::FromStr(id.to_owned(), "u32"))),
+            None => Ok(None),
+        }
+    };
+    let material = parse_id("material")?;
+    let store = parse_id("store")?;
+
+    let encoding = parse_encoding(inputs);
+    let options = parse_reader_options(inputs, &mut File::open(&paths[0])?, encoding)?;
+    let mut rdr = MultiFileReader::new(&paths)?;
+    // Reads the files like `open_merged` merges them.
+    rdr.encoding(encoding).skip_head(1).head_once(true);
+    if paths.len() > 1 {
+        rdr.skip_tail(1);
+    }
+
+    info!("start tracing records");
+
+    let ((mmap, smap, _), trace) =
+        aggregate_traced(rdr, &CONFIG.clone(), options, inputs.is_present("strict"))?;
+    let provenance = match (material, store) {
+        (Some(mid), _) => {
+            let material = mmap.get(&mid).ok_or_else(|| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "material {} not found in the given files",
+                    mid
+                ))))
+            })?;
+            info!(
+                "material {} ({}): quantity {}, amount {}",
+                mid,
+                material.mname,
+                material.quantity.sum(),
+                material.amount.sum()
+            );
+            trace.materials.get(&mid).ok_or_else(|| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "no input lines were recorded for material {}",
+                    mid
+                ))))
+            })?
+        }
+        (None, Some(sid)) => {
+            let store = smap.get(&sid).ok_or_else(|| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "store {} not found in the given files",
+                    sid
+                ))))
+            })?;
+            info!(
+                "store {} ({}): amount {}, {} materials",
+                sid, store.sname, store.amount, store.sku_in_use
+            );
+            trace.stores.get(&sid).ok_or_else(|| {
+                Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "no input lines were recorded for store {}",
+                    sid
+                ))))
+            })?
+        }
+        (None, None) => unreachable!("clap requires either option `material` or `store`"),
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (path, _) in provenance.iter() {
+        let mut rdr = MultiFileReader::new([path])?;
+        rdr.encoding(encoding);
+
+        let mut line = String::new();
+        while rdr.read_line(&mut line)? > 0 {
+            let (_, n) = rdr.provenance().unwrap();
+            if provenance.contains(path, n) {
+                writeln!(out, "{}:{}: {}", path.display(), n, line.trim_end())?;
+            }
+            line.clear();
+        }
+    }
+
+    info!(
+        "{} records from {} files have been traced",
+        provenance.len(),
+        provenance.iter().count()
+    );
+    Ok(())
+}
+
+/// Merges the given files into the temporary file like [`open_merged`] does, dropping duplicate
+/// records by the given key and rewriting the records as comma separated records.
+fn open_deduped(
+    m: &ArgMatches<'_>,
+    paths: Vec<OsString>,
+    encoding: EncodeType,
+    key: DedupeKey,
+) -> Result<File> {
+    use crate::concat::align::{concat_aligned, AlignOptions};
+    use crate::convert::is_spreadsheet;
+
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    let options = AlignOptions {
+        output_encoding: match encoding {
+            EncodeType::Auto => EncodeType::UTF8,
+            encoding => encoding,
+        },
+        // A single file is aggregated as is, otherwise the tail line of each file is skipped.
+        skip_end: if paths.len() == 1 && !is_spreadsheet(&paths[0]) {
+            0
+        } else {
+            1
+        },
+        dedupe: Some(key),
+        ..Default::default()
+    };
+    let sources = align_sources(m, &paths, &vec![encoding; paths.len()])?;
+    let report = concat_aligned(sources, BufWriter::new(File::create(TEMP_FILE)?), &options)?;
+    log_align_report(&report);
+
+    Ok(File::open(TEMP_FILE)?)
+}
+
+/// Opens the given files as sources of [`concat_aligned`], each decoded with its own encoding,
+/// reading CSV files with the dialect given by option `delimiter` and converting spreadsheets in
+/// memory.
+///
+/// [`concat_aligned`]: crate::concat::align::concat_aligned
+fn align_sources(
+    m: &ArgMatches<'_>,
+    paths: &[PathBuf],
+    encodings: &[EncodeType],
+) -> Result<Vec<AlignSource<Box<dyn BufRead>>>> {
+    use crate::convert::is_spreadsheet;
+
+    let mut sources: Vec<AlignSource<Box<dyn BufRead>>> = Vec::with_capacity(paths.len());
+    for (path, &encoding) in paths.iter().zip(encodings.iter()) {
+        let name = path.display().to_string();
+        if is_spreadsheet(path) {
+            let rdr = Box::new(read_spreadsheet(path, encoding)?);
+            let options = CsvReaderOptions::default();
+            sources.push(AlignSource {
+                name,
+                rdr,
+                encoding,
+                options,
+            });
+        } else {
+            let mut file = File::open(path)?;
+            let options = parse_reader_options(m, &mut file, encoding)?;
+            let rdr = Box::new(BufReader::new(file));
+            sources.push(AlignSource {
+                name,
+                rdr,
+                encoding,
+                options,
+            });
+        }
+    }
+    Ok(sources)
+}
+
+/// Logs how the columns of each source were aligned and how many duplicates were dropped.
+fn log_align_report(report: &AlignReport) {
+    let mut duplicates = 0;
+    for source in report.sources.iter() {
+        if !source.missing.is_empty() {
+            warn!(
+                "`{}` lacks columns {}, which are left empty",
+                source.name,
+                source.missing.join(", ")
+            );
+        }
+        if source.reordered {
+            info!("columns of `{}` have been reordered", source.name);
+        }
+        if source.duplicates > 0 {
+            info!(
+                "dropped {} duplicate records of `{}`",
+                source.duplicates, source.name
+            );
+        }
+        duplicates += source.duplicates;
+    }
+    if duplicates > 0 {
+        info!("dropped {} duplicate records in total", duplicates);
+    }
+}
+
+/// Parses option `dedupe`, returning `None` if it is absent.
+///
+/// The key is made of the columns given by option `key`, or of all columns if there are none.
+fn parse_dedupe_key(m: &ArgMatches<'_>) -> Option<DedupeKey> {
+    if !m.is_present("dedupe") {
+        return None;
+    }
+    match m.values_of("key") {
+        Some(columns) => Some(DedupeKey::Columns(
+            columns.map(|c| c.trim().to_owned()).collect(),
+        )),
+        None => Some(DedupeKey::All),
+    }
+}
+
+/// A merge source.
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Converts the first worksheet of a spreadsheet to CSV in memory.
+fn read_spreadsheet<P: AsRef<Path>>(path: P, encoding: EncodeType) -> Result<Cursor<Vec<u8>>> {
+    use crate::convert::{xlsx2csv_into, Xlsx2CsvOptions};
+
+    // Dates are written the way ST, IE and IOS records are exported as CSV.
+    let options = Xlsx2CsvOptions {
+        encoding,
+        date_format: "%Y/%m/%d".to_owned(),
+        datetime_format: "%Y/%m/%d %H:%M:%S".to_owned(),
+        ..Default::default()
+    };
+    let mut buf = Vec::new();
+    xlsx2csv_into(path, &mut buf, &options)?;
+    Ok(Cursor::new(buf))
+}
+
+/// Converts an error of crate `admerge` into an [`Error`].
+fn merge_error(err: admerge::ErrorKind) -> Error {
+    Error::new(ErrorKind::Other(Cow::Owned(err.to_string())))
+}
+
+/// Creates the aggregators named by option `aggregators`, defaults to all registered ones.
+fn create_aggregators(m: &ArgMatches<'_>, registry: &Registry) -> Result<Vec<Box<dyn Aggregator>>> {
+    match m.values_of("aggregators") {
+        Some(names) => names.map(|name| registry.create(name.trim())).collect(),
+        None => registry.names().map(|name| registry.create(name)).collect(),
+    }
+}
+
+/// Parses the value of option `encoding`, defaults to `GB18030`.
+fn parse_encoding(m: &ArgMatches<'_>) -> EncodeType {
+    parse_encoding_of(m, "encoding")
+}
+
+/// Parses the values of option `encoding`, one for each of the given number of inputs.
+///
+/// A single value applies to all inputs, otherwise there must be as many values as inputs.
+fn parse_encodings(m: &ArgMatches<'_>, inputs: usize) -> Result<Vec<EncodeType>> {
+    let values = match m.values_of("encoding") {
+        Some(values) => values.collect::<Vec<_>>(),
+        None => return Ok(vec![EncodeType::GB18030; inputs]),
+    };
+    if values.len() != 1 && values.len() != inputs {
+        return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+            "expected one encoding or one for each of the {} input files, but found {}",
+            inputs,
+            values.len()
+        )))));
+    }
+
+    let encodings = values
+        .iter()
+        .map(|value| match *value {
+            "GBK" => EncodeType::GBK,
+            "UTF8" => EncodeType::UTF8,
+            "UTF16LE" => EncodeType::UTF16LE,
+            "UTF16BE" => EncodeType::UTF16BE,
+            "AUTO" => EncodeType::Auto,
+            _ => EncodeType::GB18030,
+        })
+        .collect::<Vec<_>>();
+    Ok(encodings.into_iter().cycle().take(inputs).collect())
+}
+
+/// Transcodes a source of the given encoding into UTF-8 in memory, warning about undecodable
+/// bytes, which are replaced.
+fn transcode_source<R: Read>(path: &Path, rdr: R, from: EncodeType) -> Result<Cursor<Vec<u8>>> {
+    use crate::convert::{transcode, Bom, TranscodeOptions};
+
+    let options = TranscodeOptions {
+        from,
+        to: EncodeType::UTF8,
+        bom: Bom::Remove,
+    };
+    let mut buf = Vec::new();
+    let report = transcode(rdr, &mut buf, options)?;
+    if report.malformed > 0 {
+        warn!(
+            "`{}` contains {} undecodable byte sequences (decoded as {:?}), which have been replaced",
+            path.display(),
+            report.malformed,
+            report.encoding
+        );
+    }
+    Ok(Cursor::new(buf))
+}
+
+/// Collects the files under the given directory as filtered by options `recursive`, `glob`,
+/// `since` and `until`, in the order given by option `sort`.
+///
+/// Files matching one of `default_globs` are collected if no `glob` is given, or all files if
+/// there are no default globs either.
+fn discover(m: &ArgMatches<'_>, dir: &str, default_globs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut builder = FilePathEntries::builder(dir);
+    builder.recursive(m.is_present("recursive"));
+    match m.values_of("glob") {
+        Some(globs) => {
+            for glob in globs {
+                builder.include(glob)?;
+            }
+        }
+        None => {
+            for glob in default_globs.iter() {
+                builder.include(glob)?;
+            }
+        }
+    }
+    if let Some(date) = parse_date_of(m, "since")? {
+        builder.modified_since(local_midnight(date)?);
+    }
+    if let Some(date) = parse_date_of(m, "until")? {
+        // Files modified on the given day are included.
+        builder.modified_until(local_midnight(date.succ_opt().unwrap_or(date))?);
+    }
+    builder.order(parse_sort_order(m).unwrap_or(SortOrder::Name));
+
+    Ok(builder.build()?.into_iter().collect())
+}
+
+/// Parses the value of the given date option, accepting `yyyy-mm-dd` and `yyyy/mm/dd`.
+fn parse_date_of(m: &ArgMatches<'_>, name: &str) -> Result<Option<NaiveDate>> {
+    match m.value_of(name) {
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(date, "%Y/%m/%d"))
+            .map(Some)
+            .map_err(|_| Error::new(ErrorKind::FromStr(date.to_owned(), "date"))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the start of the given day in local time.
+fn local_midnight(date: NaiveDate) -> Result<SystemTime> {
+    use chrono::{Local, TimeZone};
+
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    match Local.from_local_datetime(&midnight).earliest() {
+        Some(time) => Ok(SystemTime::from(time)),
+        None => Err(Error::new(ErrorKind::FromStr(
+            date.to_string(),
+            "local time",
+        ))),
+    }
+}
+
+/// Parses the value of option `sort`, returning `None` if it is absent.
+fn parse_sort_order(m: &ArgMatches<'_>) -> Option<SortOrder> {
+    match m.value_of("sort") {
+        Some("natural") => Some(SortOrder::Natural),
+        Some("mtime") => Some(SortOrder::Modified),
+        Some("date") => Some(SortOrder::Date),
+        Some(_) => Some(SortOrder::Name),
+        None => None,
+    }
+}
+
+/// Parses the value of the given encoding option, defaults to `GB18030`.
+fn parse_encoding_of(m: &ArgMatches<'_>, name: &str) -> EncodeType {
+    match m.value_of(name) {
+        Some("GBK") => EncodeType::GBK,
+        Some("UTF8") => EncodeType::UTF8,
+        Some("UTF16LE") => EncodeType::UTF16LE,
+        Some("UTF16BE") => EncodeType::UTF16BE,
+        Some("AUTO") => EncodeType::Auto,
+        _ => EncodeType::GB18030,
+    }
+}
+
+/// Parses the value of option `delimiter` into reader options, sniffing the dialect from the
+/// given source if the value is `auto`.
+fn parse_reader_options<R: Read + Seek>(
+    m: &ArgMatches<'_>,
+    src: &mut R,
+    encoding: EncodeType,
+) -> Result<CsvReaderOptions> {
+    let delimiter = match m.value_of("delimiter") {
+        None => return Ok(CsvReaderOptions::default()),
+        Some("auto") => return CsvReaderOptions::sniff_reader(src, encoding),
+        Some("tab") | Some("\\t") => '\t',
+        Some(s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+        Some(s) => {
+            return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                "delimiter only accepts `auto`, `tab` or a single character, but found `{}`",
+                s
+            )))))
+        }
+    };
+
+    Ok(CsvReaderOptions {
+        delimiter,
+        ..Default::default()
+    })
+}
+
+/// Returns the value of option `output`, or a directory named `default` next to the executable,
+/// creating the directory if it does not exist yet.
+fn output_dir(m: &ArgMatches<'_>, default: &str) -> Result<OsString> {
+    let dir = match m.value_of_os("output") {
+        Some(dir) => PathBuf::from(dir),
+        None => DIR.join(default),
+    };
+
+    if !dir.is_dir() {
+        fs::create_dir(&dir)?;
+    }
+
+    Ok(dir.into_os_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{Bom, TranscodeOptions, Xlsx2CsvOptions};
+    use std::time::Duration;
+
+    /// Returns an empty directory under the system temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("dpt-main-{}-{}", name, process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn to_utf8() -> ConvertMode {
+        ConvertMode::Encoding(TranscodeOptions {
+            from: EncodeType::GB18030,
+            to: EncodeType::UTF8,
+            bom: Bom::Remove,
+        })
+    }
+
+    fn input(pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|(path, rel)| (PathBuf::from(path), PathBuf::from(rel)))
+            .collect()
+    }
+
+    #[test]
+    fn plan_keeps_relative_paths() {
+        let input = input(&[("in/a/x.csv", "a/x.csv"), ("in/b/x.csv", "b/x.csv")]);
+        let jobs = plan_conversions(input, Path::new("out"), &to_utf8()).unwrap();
+        let outputs = jobs.iter().map(|job| &job.outputs[..]).collect::<Vec<_>>();
+        assert_eq!(
+            outputs,
+            [
+                &[PathBuf::from("out/a/x.csv")][..],
+                &[PathBuf::from("out/b/x.csv")][..]
+            ]
+        );
+        assert_eq!(jobs[1].out_dir, Path::new("out/b"));
+    }
+
+    #[test]
+    fn plan_rejects_clashing_outputs() {
+        let to_csv = ConvertMode::Filetype(Xlsx2CsvOptions::default());
+        let out = Path::new("out");
+
+        // Files given with `-i` are written by their file names.
+        let files = input(&[("a/foo.xlsx", "foo.xlsx"), ("b/foo.xls", "foo.xls")]);
+        assert!(plan_conversions(files, out, &to_csv).is_err());
+        // Files found with `-d` in the same folder.
+        let found = input(&[("d/foo.xlsx", "foo.xlsx"), ("d/foo.ods", "foo.ods")]);
+        assert!(plan_conversions(found, out, &to_csv).is_err());
+        let found = input(&[("d/Foo.csv", "Foo.csv"), ("d/foo.csv", "foo.csv")]);
+        assert!(plan_conversions(found, out, &to_utf8()).is_err());
+
+        let found = input(&[("d/a/foo.xlsx", "a/foo.xlsx"), ("d/foo.ods", "foo.ods")]);
+        assert!(plan_conversions(found, out, &to_csv).is_ok());
+    }
+
+    #[test]
+    fn converts_a_batch_into_relative_paths() {
+        let root = temp_dir("batch");
+        for (rel, data) in [
+            ("a/x.csv", b"\xD6\xD0,1\r\n"),
+            ("b/x.csv", b"\xCE\xC4,2\r\n"),
+        ]
+        .iter()
+        {
+            let path = root.join("in").join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, data).unwrap();
+        }
+        let input = discover_relative(&root.join("in"));
+        let jobs = plan_conversions(input, &root.join("out"), &to_utf8()).unwrap();
+
+        for job in jobs.iter() {
+            assert!(matches!(
+                convert_file(job, &to_utf8(), false),
+                Ok(Converted::Files(_, Some(_)))
+            ));
+        }
+        let read = |rel: &str| fs::read_to_string(root.join("out").join(rel)).unwrap();
+        assert_eq!(read("a/x.csv"), "中,1\r\n");
+        assert_eq!(read("b/x.csv"), "文,2\r\n");
+
+        // Outputs newer than their inputs are only converted again with `--force`.
+        assert!(matches!(
+            convert_file(&jobs[0], &to_utf8(), false),
+            Ok(Converted::Skipped)
+        ));
+        assert!(matches!(
+            convert_file(&jobs[0], &to_utf8(), true),
+            Ok(Converted::Files(_, _))
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Lists the files under `dir` with their paths relative to it, like `convert -d`.
+    fn discover_relative(dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+        let mut paths = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .map(|entry| entry.unwrap().into_path())
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+            .into_iter()
+            .map(|path| {
+                let rel = path.strip_prefix(dir).unwrap().to_path_buf();
+                (path, rel)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn up_to_date_only_if_all_outputs_are_newer() {
+        let root = temp_dir("up-to-date");
+        let (input, a, b) = (root.join("in.csv"), root.join("a.csv"), root.join("b.csv"));
+        for path in [&input, &a, &b].iter() {
+            fs::write(path, "").unwrap();
+        }
+        let now = SystemTime::now();
+        let set_modified = |path: &Path, time: SystemTime| {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        set_modified(&input, now - Duration::from_secs(60));
+
+        assert!(is_up_to_date(&input, &[a.clone(), b.clone()]).unwrap());
+        assert!(!is_up_to_date(&input, &[]).unwrap());
+        assert!(!is_up_to_date(&input, &[a.clone(), root.join("c.csv")]).unwrap());
+        set_modified(&b, now - Duration::from_secs(120));
+        assert!(!is_up_to_date(&input, &[a, b]).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reads_spreadsheets_like_exported_records() {
+        use chrono::NaiveDate;
+        use rust_xlsxwriter::{Format, Workbook};
+
+        let dir = temp_dir("spreadsheet");
+        let path = dir.join("st.xlsx");
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet();
+        let format = Format::new().set_num_format("yyyy-mm-dd");
+        worksheet.write_string(0, 0, "说明 1|").unwrap();
+        worksheet.write_string(0, 1, "请求|日期").unwrap();
+        worksheet.write_string(1, 0, "Salt, fine").unwrap();
+        let date = NaiveDate::from_ymd_opt(2021, 3, 1).unwrap();
+        worksheet
+            .write_datetime_with_format(1, 1, date, &format)
+            .unwrap();
+        workbook.save(&path).unwrap();
+
+        let csv = read_spreadsheet(&path, EncodeType::UTF8)
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "说明 1|,请求|日期\r\n\"Salt, fine\",2021/03/01\r\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Encodes the given text, with a BOM if the encoding is UTF-16LE.
+    fn encoded(text: &str, encoding: EncodeType) -> Vec<u8> {
+        use crate::convert::encode;
+
+        let mut buf = Vec::new();
+        encode(text, encoding, &mut buf).unwrap();
+        if encoding == EncodeType::UTF16LE {
+            buf.splice(0..0, [0xFF, 0xFE].iter().copied());
+        }
+        buf
+    }
+
+    #[test]
+    fn parses_one_encoding_or_one_for_each_input() {
+        let yaml = load_yaml!("../cli.yml");
+        let app = App::from_yaml(yaml);
+        let parse = |encodings: &[&str], inputs: usize| {
+            let mut args = vec!["dpt", "concat", "-i", "a.csv", "-o", "out.csv"];
+            for encoding in encodings.iter() {
+                args.extend(["--encoding", encoding].iter());
+            }
+            let matches = app.clone().get_matches_from(args);
+            parse_encodings(matches.subcommand_matches("concat").unwrap(), inputs)
+        };
+
+        assert_eq!(parse(&[], 2).unwrap(), [EncodeType::GB18030; 2]);
+        assert_eq!(parse(&["UTF8"], 3).unwrap(), [EncodeType::UTF8; 3]);
+        assert_eq!(
+            parse(&["AUTO", "UTF16LE"], 2).unwrap(),
+            [EncodeType::Auto, EncodeType::UTF16LE]
+        );
+        assert!(parse(&["UTF8", "GBK"], 3).is_err());
+    }
+
+    #[test]
+    fn transcodes_sources_into_utf8() {
+        let path = Path::new("a.csv");
+        let text = "名称,数量\r\n盐,1\r\n";
+        for &encoding in [EncodeType::GB18030, EncodeType::UTF16LE, EncodeType::Auto].iter() {
+            let from = encoded(text, encoding);
+            let utf8 = transcode_source(path, &from[..], encoding).unwrap();
+            assert_eq!(
+                String::from_utf8(utf8.into_inner()).unwrap(),
+                text,
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn concats_sources_of_mixed_encodings() {
+        let dir = temp_dir("concat-mixed");
+        let (a, b, out) = (dir.join("a.csv"), dir.join("b.csv"), dir.join("out.csv"));
+        let a_text = "名称,数量\r\n盐,1\r\n合计,1\r\n";
+        fs::write(&a, encoded(a_text, EncodeType::GB18030)).unwrap();
+        let b_text = "名称,数量\r\n米,2\r\n合计,2\r\n";
+        fs::write(&b, encoded(b_text, EncodeType::UTF16LE)).unwrap();
+        let (a, b, out) = (
+            a.to_str().unwrap(),
+            b.to_str().unwrap(),
+            out.to_str().unwrap(),
+        );
+        let args = [
+            "dpt",
+            "concat",
+            "-O",
+            "-e",
+            "1",
+            "-i",
+            a,
+            b,
+            "--encoding",
+            "GB18030,UTF16LE",
+            "-o",
+            out,
+        ];
+        let yaml = load_yaml!("../cli.yml");
+        let matches = App::from_yaml(yaml).get_matches_from(args);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(run(&matches, &Registry::new())).unwrap();
+
+        // Sources are transcoded into UTF-8 without the BOM of the UTF-16LE one.
+        assert_eq!(
+            fs::read_to_string(out).unwrap(),
+            "名称,数量\r\n盐,1\r\n米,2\r\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+//! Aligns the columns of several CSV sources by header name.
+use super::dedupe::{DedupeKey, Deduper};
+use crate::convert::EncodeType;
+use crate::{CsvReader, CsvReaderOptions, CsvWriter, CsvWriterOptions};
+use crate::{Error, ErrorKind, Result};
+
+use fxhash::FxHashMap;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+/// A named CSV source to be concatenated.
+pub struct AlignSource<R> {
+    /// The name of this source used in reports and errors, usually its path.
+    pub name: String,
+    pub rdr: R,
+    /// The encoding of this source, [`EncodeType::Auto`] detects it from its first bytes.
+    pub encoding: EncodeType,
+    pub options: CsvReaderOptions,
+}
+
+/// Options of [`concat_aligned`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlignOptions {
+    /// The encoding of the output, which may differ from those of the sources.
+    pub output_encoding: EncodeType,
+    /// The number of trailing records of each source to drop, such as total lines.
+    pub skip_end: usize,
+    /// Requires all sources to have the same set of columns, in any order.
+    pub strict: bool,
+    /// Drops records whose key equals that of an earlier record, if any.
+    pub dedupe: Option<DedupeKey>,
+}
+
+impl Default for AlignOptions {
+    fn default() -> AlignOptions {
+        AlignOptions {
+            output_encoding: EncodeType::UTF8,
+            skip_end: 0,
+            strict: false,
+            dedupe: None,
+        }
+    }
+}
+
+/// How the columns of a source relate to the output columns.
+#[derive(Debug, Clone, Default)]
+pub struct SourceSchema {
+    pub name: String,
+    /// Output columns this source does not have, which are left empty.
+    pub missing: Vec<String>,
+    /// Whether the columns of this source are in a different order than the output columns.
+    pub reordered: bool,
+    /// The number of records written from this source.
+    pub records: u64,
+    /// The number of records dropped as duplicates of earlier records.
+    pub duplicates: u64,
+}
+
+/// Summary of a [`concat_aligned`] run.
+#[derive(Debug, Clone, Default)]
+pub struct AlignReport {
+    /// The output columns, in order of first appearance.
+    pub columns: Vec<String>,
+    pub sources: Vec<SourceSchema>,
+}
+
+/// Concatenates CSV sources into a single CSV output, aligning their columns by header name.
+///
+/// The output columns are the union of all header columns, in order of first appearance.
+/// Columns missing from a source are left empty, and columns in a different order are moved
+/// into place. Header names are compared after trimming whitespace. Each source is decoded with
+/// its own encoding, so sources of different encodings can be combined.
+///
+/// With [`AlignOptions::dedupe`], a record is dropped if an earlier record of any source has the
+/// same key. Key fields are compared after trimming whitespace.
+///
+/// # Errors
+///
+/// Besides reading and writing errors, returns an error if a header contains the same column
+/// twice, if a source shares no column with the sources before it, if a record has more fields
+/// than its header, if a source lacks a column of the dedupe key, or in strict mode, if the
+/// sources do not all have the same columns.
+pub fn concat_aligned<R, W>(
+    sources: Vec<AlignSource<R>>,
+    wtr: W,
+    options: &AlignOptions,
+) -> Result<AlignReport>
+where
+    R: BufRead,
+    W: Write,
+{
+    // Read all headers first to build the output columns.
+    let mut readers = Vec::with_capacity(sources.len());
+    let mut headers = Vec::with_capacity(sources.len());
+    for source in sources {
+        let mut rdr = CsvReader::with_options(source.rdr, source.encoding, source.options);
+        let header = rdr
+            .read_header()?
+            .iter()
+            .map(|s| s.trim().to_owned())
+            .collect::<Vec<_>>();
+        readers.push((source.name, rdr));
+        headers.push(header);
+    }
+
+    let mut columns: Vec<String> = Vec::new();
+    let mut index: FxHashMap<String, usize> = FxHashMap::default();
+    for ((name, _), header) in readers.iter().zip(headers.iter()) {
+        let mut seen = FxHashMap::default();
+        for column in header.iter() {
+            if seen.insert(column, ()).is_some() {
+                return Err(conflict(format!(
+                    "column `{}` appears more than once in the header of `{}`",
+                    column, name
+                )));
+            }
+        }
+
+        if !columns.is_empty() && header.iter().all(|c| !index.contains_key(c)) {
+            return Err(conflict(format!(
+                "`{}` shares no column with the previous files",
+                name
+            )));
+        }
+        if options.strict && !columns.is_empty() {
+            let same =
+                header.len() == columns.len() && header.iter().all(|c| index.contains_key(c));
+            if !same {
+                return Err(conflict(format!(
+                    "the columns of `{}` differ from those of the previous files",
+                    name
+                )));
+            }
+        }
+
+        for column in header.iter() {
+            if !index.contains_key(column) {
+                index.insert(column.clone(), columns.len());
+                columns.push(column.clone());
+            }
+        }
+    }
+
+    let mut deduper = match options.dedupe {
+        Some(DedupeKey::Columns(ref key)) => {
+            for ((name, _), header) in readers.iter().zip(headers.iter()) {
+                if let Some(column) = key.iter().find(|c| !header.contains(c)) {
+                    return Err(conflict(format!(
+                        "`{}` lacks column `{}` of the dedupe key",
+                        name, column
+                    )));
+                }
+            }
+            Some(Deduper::new(Some(key.iter().map(|c| index[c]).collect())))
+        }
+        Some(DedupeKey::All) => Some(Deduper::new(None)),
+        None => None,
+    };
+
+    let mut wtr =
+        CsvWriter::with_options(wtr, options.output_encoding, CsvWriterOptions::default());
+    wtr.write_row(&columns)?;
+
+    let mut report = AlignReport {
+        columns: columns.clone(),
+        sources: Vec::with_capacity(readers.len()),
+    };
+    let mut fields = vec![String::new(); columns.len()];
+    for ((name, mut rdr), header) in readers.into_iter().zip(headers.iter()) {
+        // Maps each field of a record to its output column.
+        let targets = header.iter().map(|c| index[c]).collect::<Vec<_>>();
+        let mut schema = SourceSchema {
+            name: name.clone(),
+            missing: columns
+                .iter()
+                .filter(|c| !header.contains(c))
+                .cloned()
+                .collect(),
+            reordered: targets.windows(2).any(|w| w[0] > w[1]),
+            records: 0,
+            duplicates: 0,
+        };
+
+        // Holds back the last `skip_end` records until the end of the source is known.
+        let mut pending = VecDeque::with_capacity(options.skip_end + 1);
+        while let Some(row) = rdr.read_row()? {
+            if row.len() > header.len() {
+                return Err(Error::new(ErrorKind::Csv(
+                    format!(
+                        "record of `{}` has {} fields, but its header has {}",
+                        name,
+                        row.len(),
+                        header.len()
+                    ),
+                    row.position(),
+                )));
+            }
+
+            pending.push_back(row.iter().map(|s| s.to_owned()).collect::<Vec<_>>());
+            if pending.len() <= options.skip_end {
+                continue;
+            }
+            let record = pending.pop_front().unwrap();
+
+            fields.iter_mut().for_each(|f| f.clear());
+            for (value, &target) in record.into_iter().zip(targets.iter()) {
+                fields[target] = value;
+            }
+            if let Some(ref mut deduper) = deduper {
+                if deduper.is_duplicate(&fields) {
+                    schema.duplicates += 1;
+                    continue;
+                }
+            }
+            wtr.write_row(&fields)?;
+            schema.records += 1;
+        }
+
+        report.sources.push(schema);
+    }
+    wtr.flush()?;
+
+    Ok(report)
+}
+
+fn conflict(msg: String) -> Error {
+    Error::new(ErrorKind::Other(Cow::Owned(msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str, data: &'static str) -> AlignSource<&'static [u8]> {
+        AlignSource {
+            name: name.to_owned(),
+            rdr: data.as_bytes(),
+            encoding: EncodeType::UTF8,
+            options: Default::default(),
+        }
+    }
+
+    fn concat(
+        sources: Vec<AlignSource<&'static [u8]>>,
+        options: &AlignOptions,
+    ) -> Result<(String, AlignReport)> {
+        let mut output = Vec::new();
+        let report = concat_aligned(sources, &mut output, options)?;
+        Ok((String::from_utf8(output).unwrap(), report))
+    }
+
+    #[test]
+    fn aligns_columns_by_name() {
+        let sources = vec![
+            source("a", "id,name\r\n1,x\r\n"),
+            source("b", " name ,id,qty\r\ny,2,5\r\n"),
+        ];
+        let (output, report) = concat(sources, &Default::default()).unwrap();
+        assert_eq!(output, "id,name,qty\r\n1,x,\r\n2,y,5\r\n");
+        assert_eq!(report.columns, vec!["id", "name", "qty"]);
+        assert_eq!(report.sources[0].missing, vec!["qty"]);
+        assert!(!report.sources[0].reordered);
+        assert!(report.sources[1].missing.is_empty());
+        assert!(report.sources[1].reordered);
+    }
+
+    #[test]
+    fn skips_trailing_records() {
+        let sources = vec![
+            source("a", "id\r\n1\r\ntotal\r\n"),
+            source("b", "id\r\n2\r\ntotal\r\n"),
+        ];
+        let options = AlignOptions {
+            skip_end: 1,
+            ..Default::default()
+        };
+        let (output, report) = concat(sources, &options).unwrap();
+        assert_eq!(output, "id\r\n1\r\n2\r\n");
+        assert_eq!(report.sources[1].records, 1);
+    }
+
+    #[test]
+    fn dedupes_across_sources() {
+        let sources = vec![
+            source("a", "order,line,qty\r\n1,1,5\r\n1,2,6\r\n"),
+            source("b", "line,order,qty\r\n2,1,7\r\n1,2,8\r\n"),
+        ];
+        let options = AlignOptions {
+            dedupe: Some(DedupeKey::Columns(vec!["order".into(), "line".into()])),
+            ..Default::default()
+        };
+        let (output, report) = concat(sources, &options).unwrap();
+        assert_eq!(output, "order,line,qty\r\n1,1,5\r\n1,2,6\r\n2,1,8\r\n");
+        assert_eq!(report.sources[1].records, 1);
+        assert_eq!(report.sources[1].duplicates, 1);
+    }
+
+    #[test]
+    fn rejects_conflicting_headers() {
+        let options = AlignOptions::default();
+        assert!(concat(vec![source("a", "id,id\r\n")], &options).is_err());
+        assert!(concat(
+            vec![source("a", "id\r\n"), source("b", "qty\r\n")],
+            &options
+        )
+        .is_err());
+        assert!(concat(vec![source("a", "id\r\n1,2\r\n")], &options).is_err());
+
+        let strict = AlignOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let sources = vec![source("a", "id,qty\r\n"), source("b", "qty,id\r\n")];
+        assert!(concat(sources, &strict).is_ok());
+        let sources = vec![source("a", "id,qty\r\n"), source("b", "id\r\n")];
+        assert!(concat(sources, &strict).is_err());
+
+        let dedupe = AlignOptions {
+            dedupe: Some(DedupeKey::Columns(vec!["qty".into()])),
+            ..Default::default()
+        };
+        let sources = vec![source("a", "id,qty\r\n"), source("b", "id\r\n")];
+        assert!(concat(sources, &dedupe).is_err());
+    }
+}
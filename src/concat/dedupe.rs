@@ -0,0 +1,105 @@
+//! Drops duplicate records by a key made of some or all of their columns.
+use fxhash::FxHashSet;
+
+/// The columns that identify a record when removing duplicates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DedupeKey {
+    /// All columns, i.e. only exact duplicates are dropped.
+    All,
+    /// The columns with the given header names, such as order number and line number.
+    Columns(Vec<String>),
+}
+
+/// Remembers the keys of the records seen so far.
+#[derive(Debug, Clone, Default)]
+pub struct Deduper {
+    /// The indices of the key fields, or `None` if all fields make up the key.
+    indices: Option<Vec<usize>>,
+    seen: FxHashSet<String>,
+    buf: String,
+}
+
+impl Deduper {
+    /// Creates a deduper keyed by the fields at the given indices, or by all fields if `None`.
+    pub fn new(indices: Option<Vec<usize>>) -> Deduper {
+        Deduper {
+            indices,
+            ..Default::default()
+        }
+    }
+
+    /// Returns `true` if a record with the same key has been seen before, remembering the key of
+    /// the given record otherwise.
+    pub fn is_duplicate<S: AsRef<str>>(&mut self, fields: &[S]) -> bool {
+        self.buf.clear();
+        match self.indices {
+            Some(ref indices) => {
+                for &i in indices.iter() {
+                    push_field(&mut self.buf, fields.get(i).map_or("", |s| s.as_ref()));
+                }
+            }
+            None => {
+                for field in fields.iter() {
+                    push_field(&mut self.buf, field.as_ref());
+                }
+            }
+        }
+
+        if self.seen.contains(&self.buf) {
+            true
+        } else {
+            self.seen.insert(self.buf.clone());
+            false
+        }
+    }
+
+    /// Returns the number of distinct keys seen so far.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns `true` if no record has been seen yet.
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+fn push_field(buf: &mut String, field: &str) {
+    // Separates fields with a control character that does not occur in the data, so that
+    // `a,bc` and `ab,c` have different keys.
+    buf.push_str(field.trim());
+    buf.push('\u{1F}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedupes_by_all_fields() {
+        let mut deduper = Deduper::new(None);
+        assert!(deduper.is_empty());
+        assert!(!deduper.is_duplicate(&["a", "b"]));
+        assert!(deduper.is_duplicate(&[" a", "b "]));
+        assert!(!deduper.is_duplicate(&["a", "c"]));
+        assert_eq!(deduper.len(), 2);
+    }
+
+    #[test]
+    fn dedupes_by_key_fields() {
+        let mut deduper = Deduper::new(Some(vec![2, 0]));
+        assert!(!deduper.is_duplicate(&["1", "x", "10"]));
+        assert!(deduper.is_duplicate(&["1", "y", "10"]));
+        assert!(!deduper.is_duplicate(&["10", "x", "1"]));
+        // Missing key fields are taken as empty.
+        assert!(!deduper.is_duplicate(&["1"]));
+        assert!(deduper.is_duplicate(&["1", "", ""]));
+    }
+
+    #[test]
+    fn field_boundaries_are_part_of_the_key() {
+        let mut deduper = Deduper::new(None);
+        assert!(!deduper.is_duplicate(&["a", "bc"]));
+        assert!(!deduper.is_duplicate(&["ab", "c"]));
+    }
+}
@@ -0,0 +1,4 @@
+//! Concatenates CSV files whose columns are aligned by header name, optionally dropping
+//! duplicate records.
+pub mod align;
+pub mod dedupe;
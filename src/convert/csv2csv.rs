@@ -0,0 +1,23 @@
+use crate::convert::EncodeType;
+use crate::{CsvReader, CsvReaderOptions, CsvWriter, CsvWriterOptions, Result};
+
+use std::io::{BufRead, Write};
+
+/// Rewrites CSV data of the given dialect as comma separated, CRLF terminated records, keeping
+/// the original encoding.
+pub fn csv2csv<R: BufRead, W: Write>(
+    rdr: R,
+    encoding: EncodeType,
+    options: CsvReaderOptions,
+    wtr: W,
+) -> Result<()> {
+    let mut rdr = CsvReader::with_options(rdr, encoding, options);
+    let mut wtr = CsvWriter::with_options(wtr, encoding, CsvWriterOptions::default());
+
+    while let Some(row) = rdr.read_row()? {
+        wtr.write_row(row)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use super::EncodeType;
+use crate::{CsvReader, CsvReaderOptions, Error, ErrorKind, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_xlsxwriter::{Format, Workbook};
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Date formats recognized in date columns.
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"];
+
+/// Date and time formats recognized in date columns.
+const DATETIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y/%m/%d %H:%M",
+];
+
+/// Options of [`csv2xlsx`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csv2XlsxOptions {
+    /// The encoding of the CSV file, [`EncodeType::Auto`] detects it.
+    pub encoding: EncodeType,
+    /// The dialect of the CSV file, sniffed from the file if `None`.
+    pub reader: Option<CsvReaderOptions>,
+}
+
+impl Default for Csv2XlsxOptions {
+    fn default() -> Csv2XlsxOptions {
+        Csv2XlsxOptions {
+            encoding: EncodeType::Auto,
+            reader: None,
+        }
+    }
+}
+
+/// The inferred type of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    Text,
+    Number,
+    Date,
+    DateTime,
+}
+
+/// Converts a CSV file to an Excel workbook `<file>.xlsx` under `out_dir`, returning the path
+/// of the written workbook.
+///
+/// A column is written as numbers or dates if all of its non-empty values below the header row
+/// parse as such. Numbers with leading zeros, such as material codes, keep the column as text.
+/// The header row is bold and frozen, and columns are sized to fit their contents.
+pub fn csv2xlsx<P, Q>(file: P, out_dir: Q, options: &Csv2XlsxOptions) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let path = file.as_ref();
+    let filename = path.file_stem().unwrap().to_string_lossy();
+    let out_path = out_dir.as_ref().join(format!("{}.xlsx", filename));
+
+    let mut file = File::open(path)?;
+    let reader_options = match options.reader {
+        Some(reader_options) => reader_options,
+        None => CsvReaderOptions::sniff_reader(&mut file, options.encoding)?,
+    };
+
+    let mut rdr = CsvReader::with_options(BufReader::new(file), options.encoding, reader_options);
+    let mut rows = Vec::new();
+    while let Some(row) = rdr.read_row()? {
+        rows.push(row.iter().map(|s| s.to_owned()).collect::<Vec<_>>());
+    }
+
+    let body = if reader_options.has_header && !rows.is_empty() {
+        &rows[1..]
+    } else {
+        &rows[..]
+    };
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let types = (0..width)
+        .map(|i| infer_type(body.iter().filter_map(|row| row.get(i))))
+        .collect::<Vec<_>>();
+
+    let header_format = Format::new().set_bold();
+    let date_format = Format::new().set_num_format("yyyy-mm-dd");
+    let datetime_format = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    for (r, row) in rows.iter().enumerate() {
+        let is_header = reader_options.has_header && r == 0;
+        let r = u32::try_from(r).map_err(|_| too_large(path))?;
+        for (c, value) in row.iter().enumerate() {
+            let c = u16::try_from(c).map_err(|_| too_large(path))?;
+            if value.is_empty() {
+                continue;
+            }
+
+            let result = match types[c as usize] {
+                _ if is_header => worksheet.write_string_with_format(r, c, value, &header_format),
+                ColumnType::Number => worksheet.write_number(r, c, parse_number(value).unwrap()),
+                ColumnType::Date => {
+                    let date = parse_date(value).unwrap();
+                    worksheet.write_datetime_with_format(r, c, date, &date_format)
+                }
+                ColumnType::DateTime => {
+                    let datetime = parse_datetime(value).unwrap();
+                    worksheet.write_datetime_with_format(r, c, datetime, &datetime_format)
+                }
+                ColumnType::Text => worksheet.write_string(r, c, value),
+            };
+            result?;
+        }
+    }
+
+    if reader_options.has_header && !rows.is_empty() {
+        worksheet.set_freeze_panes(1, 0)?;
+    }
+    worksheet.autofit();
+    workbook.save(&out_path)?;
+
+    Ok(out_path)
+}
+
+/// Picks the narrowest type all non-empty values conform to.
+fn infer_type<'a, I: Iterator<Item = &'a String>>(values: I) -> ColumnType {
+    let mut candidates = [ColumnType::Number, ColumnType::Date, ColumnType::DateTime].to_vec();
+    let mut is_empty = true;
+
+    for value in values.filter(|v| !v.is_empty()) {
+        is_empty = false;
+        candidates.retain(|ty| match ty {
+            ColumnType::Number => parse_number(value).is_some(),
+            // Dates may be mixed with date times in the same column.
+            ColumnType::DateTime => parse_datetime(value).is_some(),
+            ColumnType::Date => parse_date(value).is_some(),
+            ColumnType::Text => true,
+        });
+        if candidates.is_empty() {
+            return ColumnType::Text;
+        }
+    }
+
+    if is_empty {
+        ColumnType::Text
+    } else {
+        candidates[0]
+    }
+}
+
+/// Parses a number, allowing thousands separators but rejecting leading zeros.
+fn parse_number(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let digits = value.trim_start_matches('-');
+    if digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.") {
+        return None;
+    }
+    value
+        .replace(',', "")
+        .parse::<f64>()
+        .ok()
+        .filter(|f| f.is_finite())
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let value = value.trim();
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| parse_date(value).and_then(|date| date.and_hms_opt(0, 0, 0)))
+}
+
+fn too_large(path: &Path) -> Error {
+    Error::new(ErrorKind::Other(Cow::Owned(format!(
+        "`{}` has too many rows or columns for a worksheet",
+        path.display()
+    ))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calamine::{open_workbook_auto, Data, Reader};
+
+    use std::fs;
+
+    fn infer(values: &[&str]) -> ColumnType {
+        let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        infer_type(values.iter())
+    }
+
+    #[test]
+    fn parses_numbers_with_thousands_separators() {
+        assert_eq!(parse_number("1,234.5"), Some(1234.5));
+        assert_eq!(parse_number("-0.5"), Some(-0.5));
+        assert_eq!(parse_number(" 42 "), Some(42.0));
+        assert_eq!(parse_number("0"), Some(0.0));
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("1e400"), None);
+    }
+
+    #[test]
+    fn leading_zeros_keep_a_column_as_text() {
+        assert_eq!(parse_number("00123"), None);
+        assert_eq!(parse_number("-012"), None);
+        assert_eq!(infer(&["123", "00123"]), ColumnType::Text);
+        assert_eq!(infer(&["123", "1,000", ""]), ColumnType::Number);
+    }
+
+    #[test]
+    fn infers_dates_and_date_times() {
+        assert_eq!(
+            infer(&["2021-03-01", "2021/03/02", "20210303"]),
+            ColumnType::Date
+        );
+        assert_eq!(
+            infer(&["2021-03-01", "2021/03/02 08:30"]),
+            ColumnType::DateTime
+        );
+        assert_eq!(infer(&["2021-03-01", "tomorrow"]), ColumnType::Text);
+        assert_eq!(infer(&["", ""]), ColumnType::Text);
+    }
+
+    #[test]
+    fn writes_typed_cells() {
+        let dir = std::env::temp_dir().join(format!("dpt-csv2xlsx-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("typed.csv");
+        fs::write(
+            &path,
+            "code,quantity,date\n00123,\"1,000\",2021/03/01\n00456,2.5,2021/03/02\n",
+        )
+        .unwrap();
+
+        let options = Csv2XlsxOptions {
+            encoding: EncodeType::UTF8,
+            reader: Some(Default::default()),
+        };
+        let out_path = csv2xlsx(&path, &dir, &options).unwrap();
+        assert_eq!(out_path, dir.join("typed.xlsx"));
+
+        let mut workbook = open_workbook_auto(&out_path).unwrap();
+        let range = workbook.worksheet_range_at(0).unwrap().unwrap();
+        assert_eq!(
+            range.get((0, 1)),
+            Some(&Data::String("quantity".to_owned()))
+        );
+        assert_eq!(range.get((1, 0)), Some(&Data::String("00123".to_owned())));
+        assert_eq!(range.get((1, 1)), Some(&Data::Float(1000.0)));
+        match range.get((2, 2)) {
+            Some(Data::DateTime(dt)) => {
+                assert_eq!(dt.as_datetime(), parse_datetime("2021-03-02"))
+            }
+            cell => panic!("expected a date, found {:?}", cell),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{Error, ErrorKind, Result};
-use encoding::all::{GB18030, GBK, UTF_8};
+use encoding::all::{GB18030, GBK, UTF_16BE, UTF_16LE, UTF_8};
 use encoding::{DecoderTrap, Encoding};
 
 /// Supported character encodings.
@@ -8,20 +8,179 @@ pub enum EncodeType {
     UTF8,
     GBK,
     GB18030,
+    UTF16LE,
+    UTF16BE,
+    /// Detects the encoding from the data itself, see [`detect`].
+    Auto,
 }
 
-/// Decodes a sequence of bytes encoded with either GBK, GB18030 or UTF-8.
+impl EncodeType {
+    /// Returns the byte order mark of this encoding, if it has one.
+    pub fn bom(self) -> Option<&'static [u8]> {
+        match self {
+            EncodeType::UTF8 => Some(b"\xEF\xBB\xBF"),
+            EncodeType::UTF16LE => Some(b"\xFF\xFE"),
+            EncodeType::UTF16BE => Some(b"\xFE\xFF"),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this is a UTF-16 encoding.
+    pub fn is_utf16(self) -> bool {
+        matches!(self, EncodeType::UTF16LE | EncodeType::UTF16BE)
+    }
+}
+
+/// Detects the encoding of the given sample.
+///
+/// A byte order mark determines the encoding if present. Otherwise the sample is taken as
+/// UTF-8 if it is valid UTF-8 (ignoring a character truncated at the end of the sample), and
+/// as GB18030 if it is not.
+///
+/// As the first non-ASCII characters of a GB18030 stream may come after the sample, readers of
+/// streams detected as UTF-8 without a byte order mark fall back to GB18030 once the stream
+/// turns out not to be valid UTF-8.
+pub fn detect(sample: &[u8]) -> EncodeType {
+    for &encoding in [EncodeType::UTF8, EncodeType::UTF16LE, EncodeType::UTF16BE].iter() {
+        if sample.starts_with(encoding.bom().unwrap()) {
+            return encoding;
+        }
+    }
+
+    match std::str::from_utf8(sample) {
+        Ok(_) => EncodeType::UTF8,
+        // `error_len` is `None` if the sample merely ends in the middle of a character.
+        Err(e) if e.error_len().is_none() => EncodeType::UTF8,
+        Err(_) => EncodeType::GB18030,
+    }
+}
+
+/// Checks, chunk by chunk, that a stream detected as UTF-8 from its first bytes is valid UTF-8
+/// throughout, see [`detect`].
+#[derive(Debug, Default)]
+pub(crate) struct Utf8Check {
+    /// The bytes of a character truncated at the end of the chunks checked so far.
+    partial: Vec<u8>,
+}
+
+impl Utf8Check {
+    /// Checks the next chunk of the stream before it is decoded, `last` if the stream ends
+    /// with it.
+    ///
+    /// Returns `None` as long as the stream is valid UTF-8. Otherwise returns the bytes to be
+    /// decoded with the fallback encoding instead of `chunk`, which start with the bytes of a
+    /// character truncated at the end of the previous chunk, if any.
+    pub(crate) fn check(&mut self, chunk: &[u8], last: bool) -> Option<Vec<u8>> {
+        let partial = std::mem::take(&mut self.partial);
+        let joined;
+        let src = if partial.is_empty() {
+            chunk
+        } else {
+            joined = [&partial[..], chunk].concat();
+            &joined[..]
+        };
+
+        match std::str::from_utf8(src) {
+            Ok(_) => None,
+            Err(e) if e.error_len().is_none() && !last => {
+                self.partial = src[e.valid_up_to()..].to_vec();
+                None
+            }
+            Err(_) => Some(src.to_vec()),
+        }
+    }
+}
+
+/// Decodes a sequence of bytes with the given encoding.
+///
+/// With [`EncodeType::Auto`], the encoding is detected from `src` alone and a leading byte order
+/// mark is dropped. Streams should rather be detected once from their first bytes, as
+/// [`CsvReader`](crate::CsvReader) does.
 pub fn decode(src: &[u8], encoding: EncodeType, dst: &mut String) -> Result<()> {
     dst.clear();
     match encoding {
         EncodeType::GB18030 => GB18030
             .decode_to(src, DecoderTrap::Strict, dst)
-            .map_err(|e| Error::new(ErrorKind::Decode(e.to_owned().to_string()))),
+            .map_err(|e| Error::new(ErrorKind::Decode(e.to_string()))),
         EncodeType::GBK => GBK
             .decode_to(src, DecoderTrap::Strict, dst)
-            .map_err(|e| Error::new(ErrorKind::Decode(e.to_owned().to_string()))),
+            .map_err(|e| Error::new(ErrorKind::Decode(e.to_string()))),
         EncodeType::UTF8 => UTF_8
             .decode_to(src, DecoderTrap::Strict, dst)
-            .map_err(|e| Error::new(ErrorKind::Decode(e.to_owned().to_string()))),
+            .map_err(|e| Error::new(ErrorKind::Decode(e.to_string()))),
+        EncodeType::UTF16LE => UTF_16LE
+            .decode_to(src, DecoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Decode(e.to_string()))),
+        EncodeType::UTF16BE => UTF_16BE
+            .decode_to(src, DecoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Decode(e.to_string()))),
+        EncodeType::Auto => {
+            let encoding = detect(src);
+            let src = match encoding.bom() {
+                Some(bom) if src.starts_with(bom) => &src[bom.len()..],
+                _ => src,
+            };
+            decode(src, encoding, dst)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_byte_order_marks() {
+        assert_eq!(detect(b"\xEF\xBB\xBFa"), EncodeType::UTF8);
+        assert_eq!(detect(b"\xFF\xFEa\x00"), EncodeType::UTF16LE);
+        assert_eq!(detect(b"\xFE\xFF\x00a"), EncodeType::UTF16BE);
+    }
+
+    #[test]
+    fn detects_utf8_and_gb18030() {
+        assert_eq!(detect(b""), EncodeType::UTF8);
+        assert_eq!(detect("中文,a".as_bytes()), EncodeType::UTF8);
+        // `中` truncated after its second byte.
+        assert_eq!(detect(b"a,\xE4\xB8"), EncodeType::UTF8);
+        // `中文` in GB18030.
+        assert_eq!(detect(b"\xD6\xD0\xCE\xC4"), EncodeType::GB18030);
+    }
+
+    #[test]
+    fn decodes_with_detected_encoding() {
+        let mut s = String::new();
+        decode(b"\xFF\xFE\x2D\x4E\x87\x65", EncodeType::Auto, &mut s).unwrap();
+        assert_eq!(s, "中文");
+        decode(b"\xD6\xD0\xCE\xC4", EncodeType::Auto, &mut s).unwrap();
+        assert_eq!(s, "中文");
+    }
+
+    #[test]
+    fn utf8_check_carries_truncated_characters() {
+        let mut check = Utf8Check::default();
+        // `中` split between two chunks.
+        assert_eq!(check.check(b"a,\xE4\xB8", false), None);
+        assert_eq!(check.check(b"\xAD,b", false), None);
+        // A truncated character at the end of the stream is not UTF-8.
+        assert_eq!(check.check(b"\xE4", false), None);
+        assert_eq!(check.check(b"", true), Some(b"\xE4".to_vec()));
+    }
+
+    #[test]
+    fn utf8_check_returns_the_bytes_to_fall_back_with() {
+        let mut check = Utf8Check::default();
+        assert_eq!(check.check(b"a,\xD6", false), None);
+        // `中文` in GB18030, whose first byte ended the previous chunk.
+        assert_eq!(
+            check.check(b"\xD0\xCE\xC4", false),
+            Some(b"\xD6\xD0\xCE\xC4".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let mut s = String::new();
+        assert!(decode(b"\xD6\xD0", EncodeType::UTF8, &mut s).is_err());
+        assert!(decode(b"a", EncodeType::UTF16LE, &mut s).is_err());
     }
 }
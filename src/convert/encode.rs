@@ -0,0 +1,28 @@
+use crate::convert::EncodeType;
+use crate::{Error, ErrorKind, Result};
+use encoding::all::{GB18030, GBK, UTF_16BE, UTF_16LE, UTF_8};
+use encoding::{EncoderTrap, Encoding};
+
+/// Encodes a string with the given encoding.
+///
+/// [`EncodeType::Auto`] has nothing to detect from when encoding, and encodes as UTF-8.
+pub fn encode(src: &str, encoding: EncodeType, dst: &mut Vec<u8>) -> Result<()> {
+    dst.clear();
+    match encoding {
+        EncodeType::GB18030 => GB18030
+            .encode_to(src, EncoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Encode(e.into_owned()))),
+        EncodeType::GBK => GBK
+            .encode_to(src, EncoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Encode(e.into_owned()))),
+        EncodeType::UTF8 | EncodeType::Auto => UTF_8
+            .encode_to(src, EncoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Encode(e.into_owned()))),
+        EncodeType::UTF16LE => UTF_16LE
+            .encode_to(src, EncoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Encode(e.into_owned()))),
+        EncodeType::UTF16BE => UTF_16BE
+            .encode_to(src, EncoderTrap::Strict, dst)
+            .map_err(|e| Error::new(ErrorKind::Encode(e.into_owned()))),
+    }
+}
@@ -1,7 +1,27 @@
 //! Filetype conversions and character encodings.
+#[cfg(feature = "convert-xlsx")]
 mod xlsx2csv;
-pub use self::xlsx2csv::xlsx2csv;
+#[cfg(feature = "convert-xlsx")]
+pub use self::xlsx2csv::{is_spreadsheet, list_sheets, xlsx2csv, xlsx2csv_into, xlsx2csv_outputs};
+#[cfg(feature = "convert-xlsx")]
+pub use self::xlsx2csv::{SheetSelector, Xlsx2CsvOptions, SPREADSHEET_EXTENSIONS};
+
+mod csv2csv;
+pub use self::csv2csv::csv2csv;
+
+#[cfg(feature = "convert-xlsx")]
+mod csv2xlsx;
+#[cfg(feature = "convert-xlsx")]
+pub use self::csv2xlsx::{csv2xlsx, Csv2XlsxOptions};
 
 mod decode;
-pub use self::decode::decode;
 pub use self::decode::EncodeType;
+pub use self::decode::{decode, detect};
+pub(crate) use self::decode::Utf8Check;
+
+mod encode;
+pub use self::encode::encode;
+
+mod transcode;
+pub(crate) use self::transcode::encoding_rs;
+pub use self::transcode::{transcode, Bom, TranscodeOptions, TranscodeReport};
@@ -0,0 +1,265 @@
+use super::{detect, encode, EncodeType, Utf8Check};
+use crate::Result;
+use encoding_rs::{DecoderResult, Encoding, GB18030, GBK, UTF_16BE, UTF_16LE, UTF_8};
+
+use std::io::{Read, Write};
+
+/// The number of bytes read from the source at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// What to do with byte order marks when transcoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bom {
+    /// Writes a byte order mark only if the source has one.
+    Keep,
+    /// Always writes a byte order mark, if the target encoding has one.
+    Add,
+    /// Never writes a byte order mark.
+    Remove,
+}
+
+/// Options of [`transcode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranscodeOptions {
+    /// The encoding of the source, [`EncodeType::Auto`] detects it from the first bytes.
+    pub from: EncodeType,
+    pub to: EncodeType,
+    pub bom: Bom,
+}
+
+impl Default for TranscodeOptions {
+    fn default() -> TranscodeOptions {
+        TranscodeOptions {
+            from: EncodeType::Auto,
+            to: EncodeType::UTF8,
+            bom: Bom::Keep,
+        }
+    }
+}
+
+/// Summary of a [`transcode`] run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranscodeReport {
+    /// The encoding the source was decoded with.
+    pub encoding: EncodeType,
+    /// The number of undecodable byte sequences, each of which was replaced.
+    pub malformed: usize,
+}
+
+/// Re-encodes the contents of a reader into a writer, chunk by chunk.
+///
+/// Undecodable byte sequences are replaced with `U+FFFD` (or `?` when encoding as GBK, which
+/// cannot represent it) and counted in the returned report instead of failing the conversion.
+///
+/// # Errors
+///
+/// Returns an error if reading or writing fails, or if a character cannot be represented in
+/// the target encoding.
+pub fn transcode<R: Read, W: Write>(
+    mut rdr: R,
+    mut wtr: W,
+    options: TranscodeOptions,
+) -> Result<TranscodeReport> {
+    let mut input = vec![0u8; CHUNK_SIZE];
+    let mut len = read_chunk(&mut rdr, &mut input)?;
+
+    let from = match options.from {
+        EncodeType::Auto => detect(&input[..len]),
+        encoding => encoding,
+    };
+    let mut start = 0;
+    if let Some(bom) = from.bom() {
+        if input[..len].starts_with(bom) {
+            start = bom.len();
+        }
+    }
+    let write_bom = match options.bom {
+        Bom::Keep => start > 0,
+        Bom::Add => true,
+        Bom::Remove => false,
+    };
+    if let (true, Some(bom)) = (write_bom, options.to.bom()) {
+        wtr.write_all(bom)?;
+    }
+
+    let replacement = match options.to {
+        EncodeType::GBK => '?',
+        _ => '\u{FFFD}',
+    };
+    let mut decoder = encoding_rs(from).new_decoder_without_bom_handling();
+    let mut report = TranscodeReport {
+        encoding: from,
+        malformed: 0,
+    };
+    let mut check = (options.from == EncodeType::Auto && from == EncodeType::UTF8 && start == 0)
+        .then(Utf8Check::default);
+    let mut chunk = String::with_capacity(CHUNK_SIZE * 2);
+    let mut output = Vec::with_capacity(CHUNK_SIZE * 2);
+
+    loop {
+        let last = len == 0;
+        let mut src = &input[start..len];
+        let fallback;
+        if let Some(bytes) = check.as_mut().and_then(|check| check.check(src, last)) {
+            check = None;
+            fallback = bytes;
+            src = &fallback;
+            decoder = encoding_rs(EncodeType::GB18030).new_decoder_without_bom_handling();
+            report.encoding = EncodeType::GB18030;
+        }
+
+        loop {
+            chunk.clear();
+            if let Some(n) = decoder.max_utf8_buffer_length_without_replacement(src.len()) {
+                chunk.reserve(n);
+            }
+            let (result, read) =
+                decoder.decode_to_string_without_replacement(src, &mut chunk, last);
+            src = &src[read..];
+
+            let done = match result {
+                DecoderResult::InputEmpty => true,
+                DecoderResult::OutputFull => false,
+                DecoderResult::Malformed(_, _) => {
+                    report.malformed += 1;
+                    chunk.push(replacement);
+                    false
+                }
+            };
+
+            encode(&chunk, options.to, &mut output)?;
+            wtr.write_all(&output)?;
+            if done {
+                break;
+            }
+        }
+
+        if last {
+            break;
+        }
+        len = read_chunk(&mut rdr, &mut input)?;
+        start = 0;
+    }
+
+    wtr.flush()?;
+    Ok(report)
+}
+
+/// Fills the buffer as far as possible, returning the number of bytes read.
+fn read_chunk<R: Read>(rdr: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match rdr.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// Returns the `encoding_rs` counterpart of the given encoding, UTF-8 for [`EncodeType::Auto`].
+pub(crate) fn encoding_rs(encoding: EncodeType) -> &'static Encoding {
+    match encoding {
+        EncodeType::GBK => GBK,
+        EncodeType::GB18030 => GB18030,
+        EncodeType::UTF16LE => UTF_16LE,
+        EncodeType::UTF16BE => UTF_16BE,
+        EncodeType::UTF8 | EncodeType::Auto => UTF_8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &[u8], options: TranscodeOptions) -> (Vec<u8>, TranscodeReport) {
+        let mut output = Vec::new();
+        let report = transcode(input, &mut output, options).unwrap();
+        (output, report)
+    }
+
+    #[test]
+    fn converts_gb18030_to_utf8() {
+        let (output, report) = run(b"\xD6\xD0\xCE\xC4,a\r\n", Default::default());
+        assert_eq!(output, "中文,a\r\n".as_bytes());
+        assert_eq!(report.encoding, EncodeType::GB18030);
+        assert_eq!(report.malformed, 0);
+    }
+
+    #[test]
+    fn handles_byte_order_marks() {
+        let input = b"\xFF\xFE\x2D\x4E";
+        let options = |to, bom| TranscodeOptions {
+            from: EncodeType::Auto,
+            to,
+            bom,
+        };
+        assert_eq!(
+            run(input, options(EncodeType::UTF8, Bom::Keep)).0,
+            b"\xEF\xBB\xBF\xE4\xB8\xAD"
+        );
+        assert_eq!(
+            run(input, options(EncodeType::UTF8, Bom::Remove)).0,
+            b"\xE4\xB8\xAD"
+        );
+        assert_eq!(
+            run(input, options(EncodeType::UTF16BE, Bom::Keep)).0,
+            b"\xFE\xFF\x4E\x2D"
+        );
+        assert_eq!(
+            run(b"a", options(EncodeType::UTF16LE, Bom::Add)).0,
+            b"\xFF\xFEa\x00"
+        );
+        assert_eq!(run(b"a", options(EncodeType::GBK, Bom::Add)).0, b"a");
+    }
+
+    #[test]
+    fn characters_spanning_chunks_are_kept() {
+        // Puts a three-byte character across the first chunk boundary.
+        let text = format!("{}中", "a".repeat(CHUNK_SIZE - 1));
+        let options = TranscodeOptions {
+            to: EncodeType::GB18030,
+            ..Default::default()
+        };
+        let (output, report) = run(text.as_bytes(), options);
+        assert_eq!(&output[CHUNK_SIZE - 1..], b"\xD6\xD0");
+        assert_eq!(report.malformed, 0);
+    }
+
+    #[test]
+    fn falls_back_to_gb18030_after_the_first_chunk() {
+        // The first chunk is ASCII only, and so detected as UTF-8.
+        let mut input = vec![b'a'; CHUNK_SIZE - 1];
+        input.extend_from_slice(b"\xD6\xD0\xCE\xC4");
+        let (output, report) = run(&input, Default::default());
+        assert_eq!(&output[CHUNK_SIZE - 1..], "中文".as_bytes());
+        assert_eq!(report.encoding, EncodeType::GB18030);
+        assert_eq!(report.malformed, 0);
+    }
+
+    #[test]
+    fn replaces_and_counts_malformed_sequences() {
+        let options = TranscodeOptions {
+            from: EncodeType::UTF8,
+            ..Default::default()
+        };
+        let (output, report) = run(b"a\xFFb\xFF", options);
+        assert_eq!(output, "a\u{FFFD}b\u{FFFD}".as_bytes());
+        assert_eq!(report.malformed, 2);
+
+        let options = TranscodeOptions {
+            to: EncodeType::GBK,
+            ..options
+        };
+        assert_eq!(run(b"a\xFF", options).0, b"a?");
+    }
+
+    #[test]
+    fn unencodable_characters_are_an_error() {
+        let options = TranscodeOptions {
+            to: EncodeType::GBK,
+            ..Default::default()
+        };
+        assert!(transcode("😀".as_bytes(), Vec::new(), options).is_err());
+    }
+}
@@ -1,39 +1,457 @@
-use calamine::{open_workbook_auto, Reader};
-use encoding::{all::GB18030, EncoderTrap, Encoding};
+use super::EncodeType;
+use crate::{CsvWriter, Error, ErrorKind, Result};
+use calamine::{open_workbook_auto, Data, ExcelDateTime, Range, Reader};
+use chrono::format::{Item, StrftimeItems};
+use chrono::Timelike;
 
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{self, prelude::*, LineWriter};
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Extensions of the spreadsheet files that can be converted.
+pub const SPREADSHEET_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+/// Worksheets to be exported by [`xlsx2csv`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SheetSelector {
+  /// The worksheet with the given name.
+  Name(String),
+  /// The worksheet at the given (zero-based) index.
+  Index(usize),
+  /// All worksheets, each into its own file.
+  All,
+}
+
+impl Default for SheetSelector {
+  fn default() -> SheetSelector {
+    SheetSelector::Index(0)
+  }
+}
+
+/// Options of [`xlsx2csv`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xlsx2CsvOptions {
+  pub sheets: SheetSelector,
+  /// The encoding of the written CSV files.
+  pub encoding: EncodeType,
+  /// The `strftime` format of cells holding a date without time.
+  pub date_format: String,
+  /// The `strftime` format of cells holding both a date and a time.
+  pub datetime_format: String,
+  /// The `strftime` format of cells holding a time only.
+  pub time_format: String,
+}
+
+impl Default for Xlsx2CsvOptions {
+  fn default() -> Xlsx2CsvOptions {
+    Xlsx2CsvOptions {
+      sheets: Default::default(),
+      encoding: EncodeType::GB18030,
+      date_format: "%Y-%m-%d".to_owned(),
+      datetime_format: "%Y-%m-%dT%H:%M:%S".to_owned(),
+      time_format: "%H:%M:%S".to_owned(),
+    }
+  }
+}
+
+/// Returns the names of the worksheets of an Excel file, in workbook order.
+pub fn list_sheets<P: AsRef<Path>>(file: P) -> Result<Vec<String>> {
+  let excel = open_workbook_auto(file)?;
+  Ok(excel.sheet_names().to_owned())
+}
 
 /// Converts a rich format Excel file to CSV file that only contains data.
-pub fn xlsx2csv(file: &str, out_dir: &str) -> io::Result<()> {
-  let path = Path::new(file);
-  let filename = path.file_stem().unwrap().to_str().unwrap();
+///
+/// A single selected worksheet is written to `<file>.csv` under `out_dir`. With
+/// [`SheetSelector::All`], each worksheet is written to `<file>_<sheet>.csv` instead. Returns the
+/// paths of the written files.
+///
+/// Fields are quoted where necessary, date and time cells are written with the configured
+/// formats, and numbers are rounded to the 15 significant digits Excel displays.
+pub fn xlsx2csv<P, Q>(file: P, out_dir: Q, options: &Xlsx2CsvOptions) -> Result<Vec<PathBuf>>
+where
+  P: AsRef<Path>,
+  Q: AsRef<Path>,
+{
+  check_formats(options)?;
 
-  let mut excel = open_workbook_auto(path).expect("cannot open file");
+  let path = file.as_ref();
+  let filename = path.file_stem().unwrap().to_string_lossy();
 
-  if let Some(Ok(r)) = excel.worksheet_range("Sheet1") {
-    let file = File::create(format!("{}{}.csv", out_dir, filename))?;
-    let mut file = LineWriter::new(file);
+  let mut excel = open_workbook_auto(path)?;
+  let selected = select_sheets(path, &excel.sheet_names(), &options.sheets)?;
 
-    for row in r.rows() {
-      let mut line = String::new();
+  let mut written = Vec::with_capacity(selected.len());
+  for name in selected.iter() {
+    let range = excel.worksheet_range(name)?;
 
-      for cell in row.into_iter() {
-        line.push_str(&cell.to_string());
-        line.push_str(",");
-      }
-      line.pop();
-      line.push_str("\r\n");
+    let out_path = output_path(out_dir.as_ref(), &filename, name, &options.sheets);
+    write_range(&range, BufWriter::new(File::create(&out_path)?), options)?;
+    written.push(out_path);
+  }
 
-      let line_u8: Vec<u8> = GB18030.encode(&line, EncoderTrap::Strict).unwrap();
-      file.write_all(&line_u8)?;
-    }
+  Ok(written)
+}
 
-    file.flush()?;
-  } else {
-    eprintln!("ERROR: Worksheet `Sheet1` not found in the .xlsx file.");
+/// Converts the selected worksheet of an Excel file to CSV, writing it into the given writer.
+///
+/// This is how spreadsheets are read in place of CSV files without a separate conversion step.
+///
+/// # Errors
+///
+/// Besides the errors of [`xlsx2csv`], returns an error if `options.sheets` is
+/// [`SheetSelector::All`], as a single writer cannot hold several worksheets.
+pub fn xlsx2csv_into<P, W>(file: P, wtr: W, options: &Xlsx2CsvOptions) -> Result<()>
+where
+  P: AsRef<Path>,
+  W: Write,
+{
+  if options.sheets == SheetSelector::All {
+    return Err(Error::new(ErrorKind::Other(Cow::Borrowed(
+      "cannot write all worksheets into a single output",
+    ))));
   }
+  check_formats(options)?;
+
+  let path = file.as_ref();
+  let mut excel = open_workbook_auto(path)?;
+  let selected = select_sheets(path, &excel.sheet_names(), &options.sheets)?;
+  let range = excel.worksheet_range(&selected[0])?;
+
+  write_range(&range, wtr, options)
+}
+
+/// Returns `true` if the given path has the extension of a spreadsheet [`xlsx2csv`] can read.
+pub fn is_spreadsheet<P: AsRef<Path>>(path: P) -> bool {
+  match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+    Some(ext) => SPREADSHEET_EXTENSIONS
+      .iter()
+      .any(|e| e.eq_ignore_ascii_case(ext)),
+    None => false,
+  }
+}
 
+/// Returns the paths of the files [`xlsx2csv`] would write for the given Excel file, without
+/// converting it.
+///
+/// The workbook is only opened for [`SheetSelector::All`], to list its worksheets.
+pub fn xlsx2csv_outputs<P, Q>(file: P, out_dir: Q, sheets: &SheetSelector) -> Result<Vec<PathBuf>>
+where
+  P: AsRef<Path>,
+  Q: AsRef<Path>,
+{
+  let path = file.as_ref();
+  let filename = path.file_stem().unwrap().to_string_lossy();
+
+  let names = match sheets {
+    SheetSelector::All => list_sheets(path)?,
+    _ => vec![String::new()],
+  };
+  Ok(
+    names
+      .iter()
+      .map(|name| output_path(out_dir.as_ref(), &filename, name, sheets))
+      .collect(),
+  )
+}
+
+fn check_formats(options: &Xlsx2CsvOptions) -> Result<()> {
+  for format in [
+    &options.date_format,
+    &options.datetime_format,
+    &options.time_format,
+  ]
+  .iter()
+  {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+      return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+        "invalid date format `{}`",
+        format
+      )))));
+    }
+  }
   Ok(())
 }
+
+fn select_sheets(path: &Path, names: &[String], sheets: &SheetSelector) -> Result<Vec<String>> {
+  match sheets {
+    SheetSelector::Name(name) => match names.iter().find(|n| *n == name) {
+      Some(name) => Ok(vec![name.clone()]),
+      None => Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+        "worksheet `{}` not found in `{}`, available worksheets: {}",
+        name,
+        path.display(),
+        names.join(", ")
+      ))))),
+    },
+    SheetSelector::Index(i) => match names.get(*i) {
+      Some(name) => Ok(vec![name.clone()]),
+      None => Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+        "worksheet index {} out of range, `{}` has {} worksheets",
+        i,
+        path.display(),
+        names.len()
+      ))))),
+    },
+    SheetSelector::All => Ok(names.to_vec()),
+  }
+}
+
+fn output_path(out_dir: &Path, filename: &str, sheet: &str, sheets: &SheetSelector) -> PathBuf {
+  match sheets {
+    SheetSelector::All => out_dir.join(format!("{}_{}.csv", filename, sheet)),
+    _ => out_dir.join(format!("{}.csv", filename)),
+  }
+}
+
+/// Writes the cells of a worksheet as CSV.
+fn write_range<W: Write>(range: &Range<Data>, wtr: W, options: &Xlsx2CsvOptions) -> Result<()> {
+  let mut wtr = CsvWriter::with_options(wtr, options.encoding, Default::default());
+
+  let mut fields = Vec::with_capacity(range.width());
+  for row in range.rows() {
+    fields.clear();
+    fields.extend(row.iter().map(|cell| format_cell(cell, options)));
+    wtr.write_row(&fields)?;
+  }
+
+  wtr.flush()
+}
+
+/// Formats a cell the way Excel displays it, without number formats other than dates.
+fn format_cell(cell: &Data, options: &Xlsx2CsvOptions) -> String {
+  match cell {
+    Data::Empty => String::new(),
+    Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.clone(),
+    Data::Int(i) => i.to_string(),
+    Data::Float(f) => format_number(*f),
+    Data::Bool(b) => b.to_string(),
+    Data::DateTime(dt) => format_datetime(dt, options),
+    Data::Error(e) => e.to_string(),
+  }
+}
+
+/// Formats a number with at most 15 significant digits, dropping floating point noise such as
+/// `0.30000000000000004`.
+fn format_number(f: f64) -> String {
+  if f.fract() == 0.0 && f.abs() < 1e15 {
+    return format!("{}", f as i64);
+  }
+  match format!("{:.14e}", f).parse::<f64>() {
+    Ok(rounded) => rounded.to_string(),
+    Err(_) => f.to_string(),
+  }
+}
+
+fn format_datetime(dt: &ExcelDateTime, options: &Xlsx2CsvOptions) -> String {
+  if dt.is_duration() {
+    let secs = dt.as_duration().map_or(0, |d| d.num_seconds());
+    let sign = if secs < 0 { "-" } else { "" };
+    let secs = secs.abs();
+    return format!(
+      "{}{}:{:02}:{:02}",
+      sign,
+      secs / 3600,
+      secs / 60 % 60,
+      secs % 60
+    );
+  }
+
+  match dt.as_datetime() {
+    // Serial numbers below 1 carry no date part.
+    Some(datetime) if dt.as_f64() < 1.0 => datetime.format(&options.time_format).to_string(),
+    Some(datetime) if datetime.num_seconds_from_midnight() == 0 => {
+      datetime.format(&options.date_format).to_string()
+    }
+    Some(datetime) => datetime.format(&options.datetime_format).to_string(),
+    None => format_number(dt.as_f64()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{NaiveDate, NaiveTime};
+  use rust_xlsxwriter::{Format, Workbook};
+
+  use std::fs;
+
+  /// Returns an empty directory under the system temporary directory.
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dpt-xlsx2csv-{}-{}", name, std::process::id()));
+    if dir.exists() {
+      fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  /// Writes a workbook of two worksheets, whose first cells hold their names.
+  fn workbook(dir: &Path) -> PathBuf {
+    let path = dir.join("book.xlsx");
+    let mut workbook = Workbook::new();
+    for name in ["First", "Data 2"] {
+      let worksheet = workbook.add_worksheet();
+      worksheet.set_name(name).unwrap();
+      worksheet.write_string(0, 0, name).unwrap();
+    }
+    workbook.save(&path).unwrap();
+    path
+  }
+
+  fn options(sheets: SheetSelector) -> Xlsx2CsvOptions {
+    Xlsx2CsvOptions {
+      sheets,
+      encoding: EncodeType::UTF8,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn lists_sheets_in_workbook_order() {
+    let dir = temp_dir("list");
+    let path = workbook(&dir);
+    assert_eq!(list_sheets(&path).unwrap(), vec!["First", "Data 2"]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn selects_sheets_by_name_and_index() {
+    let dir = temp_dir("select");
+    let path = workbook(&dir);
+
+    let written = xlsx2csv(
+      &path,
+      &dir,
+      &options(SheetSelector::Name("Data 2".to_owned())),
+    )
+    .unwrap();
+    assert_eq!(written, vec![dir.join("book.csv")]);
+    assert_eq!(fs::read_to_string(&written[0]).unwrap(), "Data 2\r\n");
+
+    let written = xlsx2csv(&path, &dir, &options(SheetSelector::Index(0))).unwrap();
+    assert_eq!(fs::read_to_string(&written[0]).unwrap(), "First\r\n");
+
+    assert!(xlsx2csv(
+      &path,
+      &dir,
+      &options(SheetSelector::Name("Missing".to_owned()))
+    )
+    .is_err());
+    assert!(xlsx2csv(&path, &dir, &options(SheetSelector::Index(2))).is_err());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn exports_all_sheets_into_their_own_files() {
+    let dir = temp_dir("all");
+    let path = workbook(&dir);
+
+    let written = xlsx2csv(&path, &dir, &options(SheetSelector::All)).unwrap();
+    assert_eq!(
+      written,
+      vec![dir.join("book_First.csv"), dir.join("book_Data 2.csv")]
+    );
+    assert_eq!(
+      xlsx2csv_outputs(&path, &dir, &SheetSelector::All).unwrap(),
+      written
+    );
+    assert_eq!(fs::read_to_string(&written[1]).unwrap(), "Data 2\r\n");
+
+    // A single writer cannot hold several worksheets.
+    assert!(xlsx2csv_into(&path, Vec::new(), &options(SheetSelector::All)).is_err());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn quotes_fields_where_necessary() {
+    let dir = temp_dir("quote");
+    let path = dir.join("quote.xlsx");
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.write_string(0, 0, "a,b").unwrap();
+    worksheet.write_string(0, 1, "say \"hi\"").unwrap();
+    worksheet.write_string(0, 2, "two\nlines").unwrap();
+    worksheet.write_string(0, 3, "plain").unwrap();
+    worksheet.write_number(1, 0, 0.1 + 0.2).unwrap();
+    worksheet.write_number(1, 1, 1234.0).unwrap();
+    worksheet.write_boolean(1, 3, true).unwrap();
+    workbook.save(&path).unwrap();
+
+    let mut csv = Vec::new();
+    xlsx2csv_into(&path, &mut csv, &options(Default::default())).unwrap();
+    assert_eq!(
+      String::from_utf8(csv).unwrap(),
+      "\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",plain\r\n0.3,1234,,true\r\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn formats_date_and_time_cells() {
+    let dir = temp_dir("dates");
+    let path = dir.join("dates.xlsx");
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    let format = Format::new().set_num_format("yyyy/mm/dd hh:mm");
+    let date = NaiveDate::from_ymd_opt(2021, 3, 1).unwrap();
+    let datetime = date.and_hms_opt(8, 30, 0).unwrap();
+    let time = NaiveTime::from_hms_opt(8, 30, 0).unwrap();
+    worksheet
+      .write_datetime_with_format(0, 0, date, &format)
+      .unwrap();
+    worksheet
+      .write_datetime_with_format(0, 1, datetime, &format)
+      .unwrap();
+    worksheet
+      .write_datetime_with_format(0, 2, time, &format)
+      .unwrap();
+    workbook.save(&path).unwrap();
+
+    let mut csv = Vec::new();
+    xlsx2csv_into(&path, &mut csv, &options(Default::default())).unwrap();
+    assert_eq!(
+      String::from_utf8(csv).unwrap(),
+      "2021-03-01,2021-03-01T08:30:00,08:30:00\r\n"
+    );
+
+    let options = Xlsx2CsvOptions {
+      date_format: "%Y/%m/%d".to_owned(),
+      datetime_format: "%Y/%m/%d %H:%M".to_owned(),
+      time_format: "%H:%M".to_owned(),
+      ..options(Default::default())
+    };
+    let mut csv = Vec::new();
+    xlsx2csv_into(&path, &mut csv, &options).unwrap();
+    assert_eq!(
+      String::from_utf8(csv).unwrap(),
+      "2021/03/01,2021/03/01 08:30,08:30\r\n"
+    );
+
+    let options = Xlsx2CsvOptions {
+      date_format: "%Q".to_owned(),
+      ..options
+    };
+    assert!(xlsx2csv_into(&path, Vec::new(), &options).is_err());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn rounds_numbers_to_15_significant_digits() {
+    assert_eq!(format_number(0.1 + 0.2), "0.3");
+    assert_eq!(format_number(-42.0), "-42");
+    assert_eq!(format_number(1.5), "1.5");
+    assert_eq!(format_number(1e20), "100000000000000000000");
+  }
+
+  #[test]
+  fn recognizes_spreadsheet_extensions() {
+    for name in ["a.xlsx", "a.xlsm", "a.xlsb", "a.xls", "a.ods", "A.XLS"] {
+      assert!(is_spreadsheet(name), "{}", name);
+    }
+    for name in ["a.csv", "xlsx", "a.xlsx.csv"] {
+      assert!(!is_spreadsheet(name), "{}", name);
+    }
+  }
+}
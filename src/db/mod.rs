@@ -0,0 +1,400 @@
+//! Stores ST records in a local SQLite database, so the history of ST records can be queried
+//! and aggregated without parsing the original files again.
+use crate::st::aggregate::get_store_type;
+use crate::st::aggregator::Aggregator;
+use crate::st::parse::{parse_config_store_ranges, parse_header, parse_record, Record};
+use crate::CsvReader;
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+use fxhash::FxHashMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use toml::Value;
+
+use std::io::BufRead;
+use std::path::Path;
+
+/// The date format of the `dt` column, which sorts like the dates themselves.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS st (
+    mid INTEGER NOT NULL,
+    sid INTEGER NOT NULL,
+    wid INTEGER NOT NULL,
+    dt TEXT NOT NULL,
+    qt REAL NOT NULL,
+    at REAL NOT NULL,
+    seq INTEGER NOT NULL,
+    mname TEXT NOT NULL,
+    sname TEXT NOT NULL,
+    PRIMARY KEY (mid, sid, wid, dt, qt, at, seq)
+);
+CREATE INDEX IF NOT EXISTS st_dt ON st (dt);
+CREATE TABLE IF NOT EXISTS imports (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    imported_at TEXT NOT NULL,
+    records INTEGER NOT NULL,
+    inserted INTEGER NOT NULL
+);
+";
+
+/// A local database of ST records.
+pub struct Database {
+    conn: Connection,
+}
+
+/// Summary of a [`Database::import`] run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The number of records read.
+    pub records: u64,
+    /// The number of records not in the database before.
+    pub inserted: u64,
+    /// The number of records skipped because they were imported before.
+    pub existing: u64,
+    /// The number of rows skipped because they are not records, such as the total row of an
+    /// ST report, or because they cannot be parsed or have no date in non-strict mode.
+    pub skipped: u64,
+}
+
+impl Database {
+    /// Opens the database at the given path, creating it if it does not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Database { conn })
+    }
+
+    /// Imports the ST records of the given reader, whose first record must be the header row.
+    ///
+    /// Imports are idempotent: a record is identified by its material, store, warehouse, date,
+    /// quantity and amount, and by how many identical records precede it in the same source.
+    /// Importing a source twice, or sources that overlap, thus never stores a record twice,
+    /// while identical records within a single source are all kept.
+    ///
+    /// # Errors
+    ///
+    /// Besides reading and database errors, returns an error in strict mode if a record cannot
+    /// be parsed or has no date, which are skipped otherwise. Rows that are too short to be
+    /// records, such as the total row, are always skipped.
+    pub fn import<R: BufRead>(
+        &mut self,
+        mut rdr: CsvReader<R>,
+        source: &str,
+        config: &Value,
+        strict: bool,
+    ) -> Result<ImportReport> {
+        let header = parse_header(rdr.read_header()?, config)?;
+
+        let tx = self.conn.transaction()?;
+        let mut report = ImportReport::default();
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO st (mid, sid, wid, dt, qt, at, seq, mname, sname)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            // Counts identical records, see `seq`.
+            let mut seen: FxHashMap<(u32, u32, u16, NaiveDate, u64, u64), u32> =
+                FxHashMap::default();
+
+            while let Some(row) = rdr.read_row()? {
+                let record = match parse_record(row, header) {
+                    Ok(Some(record)) => record,
+                    // Not a record, e.g. the total row, which may be followed by more records
+                    // if several reports were concatenated.
+                    Ok(None) => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    Err(e) if strict => {
+                        return Err(Error::new(ErrorKind::Csv(e.to_string(), row.position())))
+                    }
+                    Err(_) => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+                let dt = match record.dt {
+                    Some(dt) => dt,
+                    None if strict => {
+                        return Err(Error::new(ErrorKind::Csv(
+                            "record has no date".to_owned(),
+                            row.position(),
+                        )))
+                    }
+                    None => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                };
+
+                let key = (
+                    record.mid,
+                    record.sid,
+                    record.wid,
+                    dt,
+                    record.qt.to_bits(),
+                    record.at.to_bits(),
+                );
+                let seq = seen.entry(key).or_insert(0);
+                *seq += 1;
+
+                let n = insert.execute(params![
+                    record.mid,
+                    record.sid,
+                    record.wid,
+                    dt.format(DATE_FORMAT).to_string(),
+                    record.qt,
+                    record.at,
+                    *seq,
+                    record.mname,
+                    record.sname,
+                ])?;
+                report.records += 1;
+                if n == 0 {
+                    report.existing += 1;
+                } else {
+                    report.inserted += 1;
+                }
+            }
+        }
+        tx.execute(
+            "INSERT INTO imports (source, imported_at, records, inserted)
+             VALUES (?1, datetime('now', 'localtime'), ?2, ?3)",
+            params![source, report.records as i64, report.inserted as i64],
+        )?;
+        tx.commit()?;
+
+        Ok(report)
+    }
+
+    /// Returns the number of records whose dates are within the given range.
+    pub fn count(&self, since: Option<NaiveDate>, until: Option<NaiveDate>) -> Result<u64> {
+        let (since, until) = date_bounds(since, until);
+        let n: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM st WHERE dt >= ?1 AND dt <= ?2",
+            params![since, until],
+            |row| row.get(0),
+        )?;
+        Ok(n as u64)
+    }
+
+    /// Returns the earliest and the latest dates of the stored records, if any.
+    pub fn date_range(&self) -> Result<Option<(NaiveDate, NaiveDate)>> {
+        let range: Option<(Option<String>, Option<String>)> = self
+            .conn
+            .query_row("SELECT MIN(dt), MAX(dt) FROM st", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+        match range {
+            Some((Some(min), Some(max))) => Ok(Some((parse_date(&min)?, parse_date(&max)?))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Calls `f` with each record whose date is within the given range, ordered by date.
+    pub fn for_each_record<F>(
+        &self,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Record) -> Result<()>,
+    {
+        let (since, until) = date_bounds(since, until);
+        let mut stmt = self.conn.prepare(
+            "SELECT mid, sid, wid, mname, sname, qt, at, dt FROM st
+             WHERE dt >= ?1 AND dt <= ?2
+             ORDER BY dt, mid, sid, wid, seq",
+        )?;
+        let mut rows = stmt.query(params![since, until])?;
+        while let Some(row) = rows.next()? {
+            let dt: String = row.get(7)?;
+            f(Record {
+                mid: row.get(0)?,
+                sid: row.get(1)?,
+                wid: row.get(2)?,
+                mname: row.get(3)?,
+                sname: row.get(4)?,
+                qt: row.get(5)?,
+                at: row.get(6)?,
+                dt: Some(parse_date(&dt)?),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Aggregates the records whose dates are within the given range with the given
+    /// aggregators, like [`aggregate_with`](crate::st::aggregate::aggregate_with) aggregates an
+    /// ST file. The aggregators are finished once all records have been observed.
+    ///
+    /// Returns the number of records read.
+    pub fn aggregate<A: Aggregator>(
+        &self,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+        config: &Value,
+        aggregators: &mut [A],
+    ) -> Result<u64> {
+        let ranges = parse_config_store_ranges(config)?;
+
+        let mut n = 0;
+        self.for_each_record(since, until, |record| {
+            n += 1;
+            // Only aggregate records whose quantity is not zero.
+            if record.qt != 0.0 {
+                let store = get_store_type(record.sid, &ranges);
+                for aggregator in aggregators.iter_mut() {
+                    aggregator.observe(&record, store);
+                }
+            }
+            Ok(())
+        })?;
+        for aggregator in aggregators.iter_mut() {
+            aggregator.finish();
+        }
+
+        Ok(n)
+    }
+}
+
+/// Returns the given date range as bounds of the `dt` column, unbounded if not given.
+fn date_bounds(since: Option<NaiveDate>, until: Option<NaiveDate>) -> (String, String) {
+    (
+        since.map_or_else(String::new, |d| d.format(DATE_FORMAT).to_string()),
+        until.map_or_else(|| "9999".to_owned(), |d| d.format(DATE_FORMAT).to_string()),
+    )
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, DATE_FORMAT)
+        .map_err(|_| Error::new(ErrorKind::FromStr(s.to_owned(), "date")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [st]
+        field_at = "amount"
+        field_dt = "date"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_qt = "quantity"
+        field_sid = "sid"
+        field_sname = "sname"
+        field_wid = "wid"
+    "#;
+
+    const HEADER: &str = "mid,mname,sid,sname,wid,quantity,amount,date\n";
+
+    fn import(db: &mut Database, records: &str, strict: bool) -> Result<ImportReport> {
+        let text = format!("{}{}", HEADER, records);
+        let rdr = CsvReader::new(text.as_bytes());
+        db.import(rdr, "test.csv", &CONFIG.parse().unwrap(), strict)
+    }
+
+    fn date(day: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(2021, 3, day)
+    }
+
+    #[test]
+    fn imports_are_idempotent() {
+        let mut db = Database::open(":memory:").unwrap();
+        let records = "\
+100,Salt,1000001,StoreA,11751,5,10,2021/03/01
+200,Rice,1000001,StoreA,11761,4,8,2021/03/02
+";
+        let report = import(&mut db, records, true).unwrap();
+        assert_eq!(
+            (report.records, report.inserted, report.existing),
+            (2, 2, 0)
+        );
+
+        let report = import(&mut db, records, true).unwrap();
+        assert_eq!(
+            (report.records, report.inserted, report.existing),
+            (2, 0, 2)
+        );
+
+        // An overlapping source only adds the records not stored yet.
+        let report = import(
+            &mut db,
+            "200,Rice,1000001,StoreA,11761,4,8,2021/03/02\n300,Oil,1000002,StoreB,11751,1,3,2021/03/03\n",
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            (report.records, report.inserted, report.existing),
+            (2, 1, 1)
+        );
+        assert_eq!(db.count(None, None).unwrap(), 3);
+    }
+
+    #[test]
+    fn keeps_identical_records_of_a_single_source() {
+        let mut db = Database::open(":memory:").unwrap();
+        let records = "\
+100,Salt,1000001,StoreA,11751,5,10,2021/03/01
+100,Salt,1000001,StoreA,11751,5,10,2021/03/01
+";
+        let report = import(&mut db, records, true).unwrap();
+        assert_eq!(report.inserted, 2);
+        assert_eq!(db.count(None, None).unwrap(), 2);
+
+        // Reimporting the source does not add the duplicates again, a third one is new.
+        let report = import(&mut db, &format!("{}{}", records, records), true).unwrap();
+        assert_eq!((report.inserted, report.existing), (2, 2));
+        assert_eq!(db.count(None, None).unwrap(), 4);
+    }
+
+    #[test]
+    fn skips_rows_that_are_not_records() {
+        let mut db = Database::open(":memory:").unwrap();
+        let records = "\
+100,Salt,1000001,StoreA,11751,5,10,2021/03/01
+total
+200,Rice,1000001,StoreA,11761,4,8,2021/03/02
+";
+        let report = import(&mut db, records, true).unwrap();
+        assert_eq!((report.records, report.skipped), (2, 1));
+        assert_eq!(db.count(None, None).unwrap(), 2);
+
+        let records = "100,Salt,1000001,StoreA,11751,5,10,\n";
+        assert!(import(&mut db, records, true).is_err());
+        assert_eq!(import(&mut db, records, false).unwrap().skipped, 1);
+    }
+
+    #[test]
+    fn filters_records_by_inclusive_date_bounds() {
+        let mut db = Database::open(":memory:").unwrap();
+        let records = "\
+100,Salt,1000001,StoreA,11751,5,10,2021/03/01
+100,Salt,1000001,StoreA,11751,5,10,2021/03/02
+100,Salt,1000001,StoreA,11751,5,10,2021/03/03
+";
+        import(&mut db, records, true).unwrap();
+
+        assert_eq!(db.count(date(2), None).unwrap(), 2);
+        assert_eq!(db.count(None, date(2)).unwrap(), 2);
+        assert_eq!(db.count(date(2), date(2)).unwrap(), 1);
+        assert_eq!(db.count(date(4), None).unwrap(), 0);
+        assert_eq!(
+            db.date_range().unwrap(),
+            Some((date(1).unwrap(), date(3).unwrap()))
+        );
+
+        let mut dates = Vec::new();
+        db.for_each_record(date(2), date(3), |record| {
+            dates.push(record.dt);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(dates, vec![date(2), date(3)]);
+    }
+}
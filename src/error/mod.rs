@@ -1,3 +1,5 @@
+use crate::reader::Position;
+
 use std::borrow::Cow;
 use std::error;
 use std::fmt;
@@ -38,20 +40,33 @@ pub enum ErrorKind {
     Io(io::Error),
     /// Can occur when walking directory entries.
     WalkDir(walkdir::Error),
+    /// An invalid glob pattern was given.
+    Glob(globset::Error),
     /// Can occur when executing some browser action.
+    #[cfg(feature = "jde")]
     CmdError(fantoccini::error::CmdError),
     /// Cannot establish a session for a new browser client.
+    #[cfg(feature = "jde")]
     NewSessionError(fantoccini::error::NewSessionError),
-    /// Represents errors that originated from crate `admerge`.
-    Merge(admerge::ErrorKind),
+    /// Can occur when reading Excel workbooks.
+    #[cfg(feature = "convert-xlsx")]
+    Xlsx(calamine::Error),
+    /// Can occur when writing Excel workbooks.
+    #[cfg(feature = "convert-xlsx")]
+    XlsxWrite(rust_xlsxwriter::XlsxError),
+    /// Can occur when reading or writing the local database.
+    #[cfg(feature = "db")]
+    Database(rusqlite::Error),
     /// Failed to decode Chinese character sets (GBK, GB18030)
     Decode(String),
+    /// Failed to encode a string with the target character set.
+    Encode(String),
     /// `config.toml` is invalid or incomplete.
     Config(String),
     /// Parsing string to another type failed.
     FromStr(String, &'static str),
-    /// Encountering malformed data.
-    MalformedData(String, usize),
+    /// Encountering malformed CSV data at the given position.
+    Csv(String, Position),
     /// The given file refused to access or is not found.
     Access(String),
     /// Other miscellaneous errors.
@@ -63,22 +78,31 @@ impl fmt::Display for Error {
         match *self.0 {
             ErrorKind::Io(ref err) => err.fmt(f),
             ErrorKind::WalkDir(ref err) => err.fmt(f),
+            ErrorKind::Glob(ref err) => err.fmt(f),
+            #[cfg(feature = "jde")]
             ErrorKind::CmdError(ref err) => err.fmt(f),
+            #[cfg(feature = "jde")]
             ErrorKind::NewSessionError(ref err) => err.fmt(f),
+            #[cfg(feature = "convert-xlsx")]
+            ErrorKind::Xlsx(ref err) => err.fmt(f),
+            #[cfg(feature = "convert-xlsx")]
+            ErrorKind::XlsxWrite(ref err) => err.fmt(f),
+            #[cfg(feature = "db")]
+            ErrorKind::Database(ref err) => err.fmt(f),
             ErrorKind::Decode(ref err) => write!(f, "decode error: failed to decode `{}`", err),
+            ErrorKind::Encode(ref err) => write!(f, "encode error: {}", err),
             ErrorKind::Config(ref err) => {
                 write!(f, "config error: `{}` is invalid or not found", err)
             }
             ErrorKind::FromStr(ref src, ref ty) => {
                 write!(f, "parse error: failed to parse `{}` as `{}`", src, ty)
             }
-            ErrorKind::MalformedData(ref msg, ref num) => {
-                write!(f, "{} (line: {})", msg, num)
+            ErrorKind::Csv(ref msg, ref pos) => {
+                write!(f, "csv error: {} ({})", msg, pos)
             }
             ErrorKind::Access(ref path) => {
                 write!(f, "access error: failed to access `{}`", path)
             }
-            ErrorKind::Merge(ref err) => err.fmt(f),
             ErrorKind::Other(ref err) => err.fmt(f),
         }
     }
@@ -104,6 +128,14 @@ macro_rules! impl_from_error {
 
 impl_from_error!(io::Error, Io);
 impl_from_error!(walkdir::Error, WalkDir);
+impl_from_error!(globset::Error, Glob);
+#[cfg(feature = "jde")]
 impl_from_error!(fantoccini::error::CmdError, CmdError);
+#[cfg(feature = "jde")]
 impl_from_error!(fantoccini::error::NewSessionError, NewSessionError);
-impl_from_error!(admerge::ErrorKind, Merge);
+#[cfg(feature = "convert-xlsx")]
+impl_from_error!(calamine::Error, Xlsx);
+#[cfg(feature = "convert-xlsx")]
+impl_from_error!(rust_xlsxwriter::XlsxError, XlsxWrite);
+#[cfg(feature = "db")]
+impl_from_error!(rusqlite::Error, Database);
@@ -0,0 +1,4 @@
+//! Processes IE reports calculated in JDE and reconciles them against ST records.
+pub mod parse;
+pub mod reconcile;
+pub mod writer;
@@ -0,0 +1,142 @@
+//! Parsing functions, mainly [`parse_ie_header`] and [`parse_ie_record`].
+use crate::st::parse::parse_csv_number;
+use crate::CsvRow;
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+use toml::Value as Config;
+
+/// Represents the indexes of IE report fields.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IeHeader {
+    pub mid: usize,
+    pub mname: usize,
+    pub sid: usize,
+    pub wid: usize,
+    pub qt: usize,
+    pub dt: usize,
+}
+
+/// Represents the names of IE report fields, as configured in `config.toml`.
+#[derive(Debug, Clone)]
+pub struct IeFields {
+    pub mid: String,
+    pub mname: String,
+    pub sid: String,
+    pub wid: String,
+    pub qt: String,
+    pub dt: String,
+}
+
+/// Represents a single row of an IE report.
+#[derive(Debug, Clone, Default)]
+pub struct IeRecord {
+    pub mid: u32,
+    pub mname: String,
+    pub sid: u32,
+    pub wid: u16,
+    pub qt: f64,
+    pub dt: Option<NaiveDate>,
+}
+
+impl IeRecord {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Parses `ie` table in `config.toml`.
+pub fn parse_config_ie_fields(config: &Config) -> Result<IeFields> {
+    let ie = config
+        .get("ie")
+        .and_then(|ie| ie.as_table())
+        .ok_or_else(|| Error::new(ErrorKind::Config("table ie".to_owned())))?;
+
+    macro_rules! field {
+        ($field:literal) => {
+            ie.get($field)
+                .and_then(|f| f.as_str())
+                .ok_or_else(|| Error::new(ErrorKind::Config(concat!("ie.", $field).to_owned())))?
+                .to_string()
+        };
+    }
+
+    Ok(IeFields {
+        mid: field!("field_mid"),
+        mname: field!("field_mname"),
+        sid: field!("field_sid"),
+        wid: field!("field_wid"),
+        qt: field!("field_qt"),
+        dt: field!("field_dt"),
+    })
+}
+
+/// Parses the header of an IE report into an [`IeHeader`].
+///
+/// # Errors
+///
+/// Returns an error variant of [`ErrorKind::Config`] if a configured field cannot be found in
+/// the header.
+pub fn parse_ie_header(row: &CsvRow, config: &Config) -> Result<IeHeader> {
+    let ie_fields = parse_config_ie_fields(config)?;
+
+    macro_rules! find_index {
+        ($($field:ident),*) => {
+            IeHeader {
+                $($field: row
+                    .iter()
+                    .position(|v| v == ie_fields.$field.as_str())
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::Config(concat!("ie.field_", stringify!($field)).to_owned()))
+                    })?,)*
+            }
+        };
+    }
+
+    Ok(find_index!(mid, mname, sid, wid, qt, dt))
+}
+
+/// Parses a single row of an IE report into an [`IeRecord`].
+///
+/// Rows without a material number (e.g. subtotal rows) are parsed into `None`.
+///
+/// # Errors
+///
+/// If the parsing failed, an error variant of [`ErrorKind::FromStr`] will be returned.
+pub fn parse_ie_record(row: &CsvRow, header: IeHeader) -> Result<Option<IeRecord>> {
+    macro_rules! get {
+        ($field:ident) => {
+            match row.get(header.$field) {
+                Some(v) => v.trim(),
+                None => return Ok(None),
+            }
+        };
+    }
+
+    let mid = get!(mid);
+    if mid.is_empty() {
+        return Ok(None);
+    }
+
+    let mut record = IeRecord::new();
+    record.mid = mid
+        .parse::<u32>()
+        .map_err(|_| Error::new(ErrorKind::FromStr(mid.to_owned(), "u32")))?;
+    record.mname = get!(mname).to_string();
+    let sid = get!(sid);
+    record.sid = sid
+        .parse::<u32>()
+        .map_err(|_| Error::new(ErrorKind::FromStr(sid.to_owned(), "u32")))?;
+    let wid = get!(wid);
+    record.wid = wid
+        .parse::<u16>()
+        .map_err(|_| Error::new(ErrorKind::FromStr(wid.to_owned(), "u16")))?;
+    record.qt = parse_csv_number(get!(qt))?;
+    let dt = get!(dt);
+    record.dt = Some(
+        NaiveDate::parse_from_str(dt, "%Y/%m/%d")
+            .map_err(|_| Error::new(ErrorKind::FromStr(dt.to_owned(), "date")))?,
+    );
+
+    Ok(Some(record))
+}
@@ -0,0 +1,259 @@
+//! Reconciles IE reports against ST records.
+use super::parse::*;
+use crate::convert::EncodeType;
+use crate::st::parse::{parse_header, parse_record};
+use crate::{CsvReader, CsvReaderOptions};
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+use fxhash::{FxHashMap, FxHashSet};
+use toml::Value;
+
+use std::fs::File;
+use std::io::BufReader;
+
+/// Two quantities are considered equal if their difference is less than this value.
+const EPSILON: f64 = 1e-6;
+
+/// Restricts which records take part in a reconciliation.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only records of this date are reconciled. If not given, the dates present in the IE
+    /// report are used.
+    pub date: Option<NaiveDate>,
+    /// Only records of these warehouses are reconciled. An empty list matches all warehouses.
+    pub warehouses: Vec<u16>,
+}
+
+impl Filter {
+    fn matches(&self, wid: u16, dt: Option<NaiveDate>) -> bool {
+        (self.warehouses.is_empty() || self.warehouses.contains(&wid))
+            && (self.date.is_none() || self.date == dt)
+    }
+}
+
+/// A material/store pair whose IE quantity does not match its ST quantity.
+#[derive(Debug, Clone, Default)]
+pub struct Mismatch {
+    pub mid: u32,
+    pub mname: String,
+    pub sid: u32,
+    pub sname: String,
+    pub ie_qt: f64,
+    pub st_qt: f64,
+}
+
+impl Mismatch {
+    /// Returns the IE quantity minus the ST quantity.
+    pub fn diff(&self) -> f64 {
+        self.ie_qt - self.st_qt
+    }
+}
+
+/// Reconciles an IE report against ST records, returning all mismatched material/store pairs
+/// sorted by material number and store number.
+pub fn reconcile(
+    ie: File,
+    st: File,
+    encoding: EncodeType,
+    config: &Value,
+    options: CsvReaderOptions,
+    filter: &Filter,
+    strict: bool,
+) -> Result<Vec<Mismatch>> {
+    let mut map: FxHashMap<(u32, u32), Mismatch> = FxHashMap::default();
+    let mut dates: FxHashSet<NaiveDate> = FxHashSet::default();
+
+    macro_rules! check {
+        ($parsed:expr, $pos:expr) => {
+            match $parsed {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    if !strict {
+                        continue;
+                    } else {
+                        return Err(Error::new(ErrorKind::Csv(e.to_string(), $pos)));
+                    }
+                }
+            }
+        };
+    }
+
+    // Sum up IE quantities.
+    let mut rdr = CsvReader::with_options(BufReader::new(ie), encoding, options);
+    let header = parse_ie_header(rdr.read_header()?, config)?;
+
+    while let Some(row) = rdr.read_row()? {
+        let record = check!(parse_ie_record(row, header), row.position());
+        if !filter.matches(record.wid, record.dt) {
+            continue;
+        }
+
+        if let Some(dt) = record.dt {
+            dates.insert(dt);
+        }
+        let entry = map
+            .entry((record.mid, record.sid))
+            .or_insert_with(|| Mismatch {
+                mid: record.mid,
+                sid: record.sid,
+                ..Default::default()
+            });
+        entry.mname = record.mname;
+        entry.ie_qt += record.qt;
+    }
+
+    // Sum up ST quantities.
+    let mut rdr = CsvReader::with_options(BufReader::new(st), encoding, options);
+    let header = parse_header(rdr.read_header()?, config)?;
+
+    while let Some(row) = rdr.read_row()? {
+        let record = check!(parse_record(row, header), row.position());
+        if !filter.matches(record.wid, record.dt) {
+            continue;
+        }
+        if filter.date.is_none() && !record.dt.is_some_and(|dt| dates.contains(&dt)) {
+            continue;
+        }
+
+        let entry = map
+            .entry((record.mid, record.sid))
+            .or_insert_with(|| Mismatch {
+                mid: record.mid,
+                sid: record.sid,
+                ..Default::default()
+            });
+        if entry.mname.is_empty() {
+            entry.mname = record.mname;
+        }
+        entry.sname = record.sname;
+        entry.st_qt += record.qt;
+    }
+
+    let mut vec = map
+        .into_iter()
+        .map(|x| x.1)
+        .filter(|m| m.diff().abs() > EPSILON)
+        .collect::<Vec<_>>();
+    vec.sort_unstable_by_key(|m| (m.mid, m.sid));
+
+    Ok(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+        [st]
+        field_at = "amount"
+        field_dt = "date"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_qt = "quantity"
+        field_sid = "sid"
+        field_sname = "sname"
+        field_wid = "wid"
+
+        [ie]
+        field_dt = "ledger date"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_qt = "quantity"
+        field_sid = "sid"
+        field_wid = "wid"
+    "#;
+
+    const IE: &str = "\
+mid,mname,sid,wid,quantity,ledger date
+100,Salt,1000001,11751,5,2021/03/01
+100,Salt,1000002,11751,2,2021/03/01
+200,Rice,1000001,11761,4,2021/03/01
+300,Oil,1000001,11799,1,2021/03/01
+";
+
+    const ST: &str = "\
+mid,mname,sid,sname,wid,quantity,amount,date
+100,Salt,1000001,StoreA,11751,3,6,2021/03/01
+100,Salt,1000001,StoreA,11751,2,4,2021/03/01
+100,Salt,1000002,StoreB,11751,1,2,2021/03/01
+200,Rice,1000001,StoreA,11761,4,8,2021/03/01
+200,Rice,1000002,StoreB,11761,6,12,2021/03/01
+200,Rice,1000002,StoreB,11761,9,18,2021/03/02
+total
+";
+
+    /// Writes the given report to a file under the system temporary directory.
+    fn report(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dpt-ie-{}-{}.csv", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn run(name: &str, ie: &str, filter: &Filter, strict: bool) -> Result<Vec<Mismatch>> {
+        let ie = report(&format!("{}-ie", name), ie);
+        let st = report(&format!("{}-st", name), ST);
+        let mismatches = reconcile(
+            File::open(&ie).unwrap(),
+            File::open(&st).unwrap(),
+            EncodeType::UTF8,
+            &CONFIG.parse().unwrap(),
+            CsvReaderOptions::default(),
+            filter,
+            strict,
+        );
+        fs::remove_file(ie).unwrap();
+        fs::remove_file(st).unwrap();
+        mismatches
+    }
+
+    fn summary(mismatches: &[Mismatch]) -> Vec<(u32, u32, f64, f64)> {
+        mismatches
+            .iter()
+            .map(|m| (m.mid, m.sid, m.ie_qt, m.st_qt))
+            .collect()
+    }
+
+    #[test]
+    fn reports_mismatched_pairs_of_the_ie_dates() {
+        let mismatches = run("dates", IE, &Filter::default(), true).unwrap();
+        // Salt of store A and Rice of store A match, the ST records of 2021/03/02 are left out.
+        assert_eq!(
+            summary(&mismatches),
+            vec![
+                (100, 1000002, 2.0, 1.0),
+                (200, 1000002, 0.0, 6.0),
+                (300, 1000001, 1.0, 0.0),
+            ]
+        );
+        assert_eq!(mismatches[0].sname, "StoreB");
+        assert_eq!(mismatches[0].diff(), 1.0);
+        assert_eq!(mismatches[1].mname, "Rice");
+    }
+
+    #[test]
+    fn filters_by_date_and_warehouse() {
+        let filter = Filter {
+            date: NaiveDate::from_ymd_opt(2021, 3, 2),
+            warehouses: vec![11761],
+        };
+        let mismatches = run("filter", IE, &filter, true).unwrap();
+        assert_eq!(summary(&mismatches), vec![(200, 1000002, 0.0, 9.0)]);
+    }
+
+    #[test]
+    fn skips_malformed_records_unless_strict() {
+        let ie = format!("{}x,Bad,1000001,11751,1,2021/03/01\n", IE);
+        assert_eq!(
+            run("lenient", &ie, &Filter::default(), false)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(run("strict", &ie, &Filter::default(), true).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+//! Writers for writing out reconciliation results.
+use super::reconcile::Mismatch;
+use crate::{CsvWriter, Result};
+
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::LineWriter;
+
+/// Writes mismatched material/store pairs to `ie_diff.csv`.
+pub fn write_mismatches(mismatches: &[Mismatch], out_dir: &str) -> Result<()> {
+    // Set up open options.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("{}/ie_diff.csv", out_dir))?;
+
+    // Write UTF-8 BOM.
+    file.write_all("\u{feff}".as_bytes())?;
+
+    // Names may contain commas, so fields are quoted when necessary.
+    let mut wtr = CsvWriter::new(LineWriter::new(file));
+    wtr.write_row([
+        "物料编码",
+        "物料名称",
+        "门店编码",
+        "门店名称",
+        "IE数量",
+        "ST数量",
+        "差异数量",
+    ])?;
+
+    // Write records.
+    for v in mismatches {
+        wtr.write_row(&[
+            v.mid.to_string(),
+            v.mname.clone(),
+            v.sid.to_string(),
+            v.sname.clone(),
+            v.ie_qt.to_string(),
+            v.st_qt.to_string(),
+            v.diff().to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Aggregates IOS reports per warehouse and per material.
+use super::parse::*;
+use crate::convert::EncodeType;
+use crate::st::aggregate::MMap;
+use crate::{CsvReader, CsvReaderOptions};
+use crate::{Error, ErrorKind, Result};
+
+use fxhash::FxHashMap;
+use toml::Value;
+
+use std::fs::File;
+use std::io::BufReader;
+
+/// Opening/closing stock and movements of a period.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stock {
+    pub open_qt: f64,
+    pub open_at: f64,
+    pub in_qt: f64,
+    pub out_qt: f64,
+    pub close_qt: f64,
+    pub close_at: f64,
+}
+
+impl Stock {
+    /// Adds the figures of an [`IosRecord`] to this `Stock`.
+    pub fn add(&mut self, record: &IosRecord) {
+        self.open_qt += record.open_qt;
+        self.open_at += record.open_at;
+        self.in_qt += record.in_qt;
+        self.out_qt += record.out_qt;
+        self.close_qt += record.close_qt;
+        self.close_at += record.close_at;
+    }
+
+    /// Returns the net stock movement of the period.
+    pub fn net_movement(&self) -> f64 {
+        self.close_qt - self.open_qt
+    }
+
+    /// Returns the average unit value of the closing stock.
+    pub fn unit_value(&self) -> Option<f64> {
+        if self.close_qt != 0.0 {
+            Some(self.close_at / self.close_qt)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stock of a single material across all warehouses.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub mid: u32,
+    pub mname: String,
+    pub warehouses: u16,
+    pub stock: Stock,
+    pub daily_usage: Option<f64>,
+    pub days_of_cover: Option<f64>,
+}
+
+/// Stock of a single warehouse across all materials.
+#[derive(Debug, Clone, Default)]
+pub struct Warehouse {
+    pub wid: u16,
+    pub sku_in_stock: u32,
+    pub stock: Stock,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Warehouse {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// A type alias for `FxHashMap<u32, Inventory>`.
+pub type IMap = FxHashMap<u32, Inventory>;
+/// A type alias for `FxHashMap<u16, Warehouse>`.
+pub type WMap = FxHashMap<u16, Warehouse>;
+
+/// Aggregates an IOS report from a single file.
+pub fn aggregate_ios(
+    file: File,
+    encoding: EncodeType,
+    config: &Value,
+    options: CsvReaderOptions,
+    strict: bool,
+) -> Result<(IMap, WMap)> {
+    let mut imap = IMap::default();
+    let mut wmap = WMap::default();
+
+    let mut rdr = CsvReader::with_options(BufReader::new(file), encoding, options);
+
+    // Read header row and parse it into a `IosHeader`.
+    let header = parse_ios_header(rdr.read_header()?, config)?;
+
+    while let Some(row) = rdr.read_row()? {
+        let record = match parse_ios_record(row, header) {
+            Ok(Some(record)) => record,
+            Ok(None) => continue,
+            Err(e) => {
+                if !strict {
+                    continue;
+                } else {
+                    return Err(Error::new(ErrorKind::Csv(e.to_string(), row.position())));
+                }
+            }
+        };
+
+        // Update `Inventory`.
+        let imap_entry = imap.entry(record.mid).or_insert_with(|| Inventory {
+            mid: record.mid,
+            mname: record.mname.clone(),
+            ..Default::default()
+        });
+        imap_entry.stock.add(&record);
+        if record.close_qt != 0.0 {
+            imap_entry.warehouses += 1;
+        }
+
+        // Update `Warehouse`.
+        let wmap_entry = wmap.entry(record.wid).or_insert_with(|| Warehouse {
+            wid: record.wid,
+            ..Default::default()
+        });
+        wmap_entry.stock.add(&record);
+        if record.close_qt != 0.0 {
+            wmap_entry.sku_in_stock += 1;
+        }
+    }
+
+    Ok((imap, wmap))
+}
+
+/// Joins IOS aggregations with ST aggregations, filling in the average daily usage and the
+/// days of cover of each material.
+///
+/// The average daily usage of a material is computed over the full time range spanned by the
+/// given ST aggregations, so that materials requested only once are not overestimated.
+pub fn join_st(imap: &mut IMap, mmap: &MMap) {
+    let first = mmap.values().filter_map(|m| m.first_req_date).min();
+    let last = mmap.values().filter_map(|m| m.last_req_date).max();
+    let days = match (first, last) {
+        (Some(first), Some(last)) => (last - first).num_days() + 1,
+        _ => return,
+    };
+
+    for (mid, inv) in imap.iter_mut() {
+        if let Some(m) = mmap.get(mid) {
+            let usage = m.quantity.sum() / days as f64;
+            inv.daily_usage = Some(usage);
+            inv.days_of_cover = if usage > 0.0 {
+                Some(inv.stock.close_qt / usage)
+            } else {
+                None
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::st::aggregate::{FSlots, Material};
+
+    use chrono::NaiveDate;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+        [ios]
+        field_close_at = "close amount"
+        field_close_qt = "close quantity"
+        field_in_qt = "in"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_open_at = "open amount"
+        field_open_qt = "open quantity"
+        field_out_qt = "out"
+        field_wid = "wid"
+    "#;
+
+    const REPORT: &str = "\
+wid,mid,mname,open quantity,open amount,in,out,close quantity,close amount
+11751,100,Salt,10,20,5,8,7,14
+11761,100,Salt,0,0,3,0,3,6
+11751,200,Rice,\"1,000\",\"2,000\",0,1000,0,0
+11751,,Subtotal,1010,2020,8,1008,10,20
+";
+
+    /// Writes the given report to a file under the system temporary directory.
+    fn report(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("dpt-ios-{}-{}.csv", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn aggregate(path: &PathBuf, strict: bool) -> Result<(IMap, WMap)> {
+        aggregate_ios(
+            File::open(path).unwrap(),
+            EncodeType::UTF8,
+            &CONFIG.parse().unwrap(),
+            CsvReaderOptions::default(),
+            strict,
+        )
+    }
+
+    #[test]
+    fn aggregates_per_material_and_per_warehouse() {
+        let path = report("aggregate", REPORT);
+        let (imap, wmap) = aggregate(&path, true).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // The subtotal row has no material number and is left out.
+        assert_eq!(imap.len(), 2);
+        let salt = &imap[&100];
+        assert_eq!(salt.mname, "Salt");
+        assert_eq!(salt.warehouses, 2);
+        assert_eq!(salt.stock.open_qt, 10.0);
+        assert_eq!(salt.stock.in_qt, 8.0);
+        assert_eq!(salt.stock.close_qt, 10.0);
+        assert_eq!(salt.stock.net_movement(), 0.0);
+        assert_eq!(salt.stock.unit_value(), Some(2.0));
+        // Rice is out of stock everywhere.
+        let rice = &imap[&200];
+        assert_eq!(rice.warehouses, 0);
+        assert_eq!(rice.stock.open_at, 2000.0);
+        assert_eq!(rice.stock.unit_value(), None);
+
+        assert_eq!(wmap.len(), 2);
+        assert_eq!(wmap[&11751].sku_in_stock, 1);
+        assert_eq!(wmap[&11751].stock.out_qt, 1008.0);
+        assert_eq!(wmap[&11761].sku_in_stock, 1);
+    }
+
+    #[test]
+    fn skips_malformed_records_unless_strict() {
+        let text = format!("{}11751,300,Oil,x,0,0,0,1,1\n", REPORT);
+        let path = report("malformed", &text);
+        let (imap, _) = aggregate(&path, false).unwrap();
+        assert!(!imap.contains_key(&300));
+        assert!(aggregate(&path, true).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn joins_daily_usage_over_the_whole_st_period() {
+        let path = report("join", REPORT);
+        let (mut imap, _) = aggregate(&path, true).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let date = |day| NaiveDate::from_ymd_opt(2021, 3, day);
+        let material = |mid, quantity, first, last| Material {
+            mid,
+            quantity: FSlots {
+                local_jmj: quantity,
+                ..Default::default()
+            },
+            first_req_date: date(first),
+            last_req_date: date(last),
+            ..Default::default()
+        };
+        let mut mmap = MMap::default();
+        // Salt is requested on a single day, but its usage spans the ten days of all records.
+        mmap.insert(100, material(100, 20.0, 5, 5));
+        mmap.insert(200, material(200, 0.0, 1, 10));
+        join_st(&mut imap, &mmap);
+
+        assert_eq!(imap[&100].daily_usage, Some(2.0));
+        assert_eq!(imap[&100].days_of_cover, Some(5.0));
+        assert_eq!(imap[&200].daily_usage, Some(0.0));
+        assert_eq!(imap[&200].days_of_cover, None);
+    }
+}
@@ -0,0 +1,4 @@
+//! Processes IOS (inventory on stock) reports downloaded from JDE.
+pub mod aggregate;
+pub mod parse;
+pub mod writer;
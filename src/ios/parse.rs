@@ -0,0 +1,151 @@
+//! Parsing functions, mainly [`parse_ios_header`] and [`parse_ios_record`].
+use crate::st::parse::parse_csv_number;
+use crate::CsvRow;
+use crate::{Error, ErrorKind, Result};
+
+use toml::Value as Config;
+
+/// Represents the indexes of IOS report fields.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IosHeader {
+    pub wid: usize,
+    pub mid: usize,
+    pub mname: usize,
+    pub open_qt: usize,
+    pub open_at: usize,
+    pub in_qt: usize,
+    pub out_qt: usize,
+    pub close_qt: usize,
+    pub close_at: usize,
+}
+
+/// Represents the names of IOS report fields, as configured in `config.toml`.
+#[derive(Debug, Clone)]
+pub struct IosFields {
+    pub wid: String,
+    pub mid: String,
+    pub mname: String,
+    pub open_qt: String,
+    pub open_at: String,
+    pub in_qt: String,
+    pub out_qt: String,
+    pub close_qt: String,
+    pub close_at: String,
+}
+
+/// Represents a single row of an IOS report.
+#[derive(Debug, Clone, Default)]
+pub struct IosRecord {
+    pub wid: u16,
+    pub mid: u32,
+    pub mname: String,
+    pub open_qt: f64,
+    pub open_at: f64,
+    pub in_qt: f64,
+    pub out_qt: f64,
+    pub close_qt: f64,
+    pub close_at: f64,
+}
+
+impl IosRecord {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+/// Parses `ios` table in `config.toml`.
+pub fn parse_config_ios_fields(config: &Config) -> Result<IosFields> {
+    let ios = config
+        .get("ios")
+        .and_then(|ios| ios.as_table())
+        .ok_or_else(|| Error::new(ErrorKind::Config("table ios".to_owned())))?;
+
+    macro_rules! field {
+        ($field:literal) => {
+            ios.get($field)
+                .and_then(|f| f.as_str())
+                .ok_or_else(|| Error::new(ErrorKind::Config(concat!("ios.", $field).to_owned())))?
+                .to_string()
+        };
+    }
+
+    Ok(IosFields {
+        wid: field!("field_wid"),
+        mid: field!("field_mid"),
+        mname: field!("field_mname"),
+        open_qt: field!("field_open_qt"),
+        open_at: field!("field_open_at"),
+        in_qt: field!("field_in_qt"),
+        out_qt: field!("field_out_qt"),
+        close_qt: field!("field_close_qt"),
+        close_at: field!("field_close_at"),
+    })
+}
+
+/// Parses the header of an IOS report into an [`IosHeader`].
+///
+/// # Errors
+///
+/// Returns an error variant of [`ErrorKind::Config`] if a configured field cannot be found in
+/// the header.
+pub fn parse_ios_header(row: &CsvRow, config: &Config) -> Result<IosHeader> {
+    let ios_fields = parse_config_ios_fields(config)?;
+
+    macro_rules! find_index {
+        ($($field:ident),*) => {
+            IosHeader {
+                $($field: row
+                    .iter()
+                    .position(|v| v == ios_fields.$field.as_str())
+                    .ok_or_else(|| {
+                        Error::new(ErrorKind::Config(concat!("ios.field_", stringify!($field)).to_owned()))
+                    })?,)*
+            }
+        };
+    }
+
+    Ok(find_index!(
+        wid, mid, mname, open_qt, open_at, in_qt, out_qt, close_qt, close_at
+    ))
+}
+
+/// Parses a single row of an IOS report into an [`IosRecord`].
+///
+/// Rows without a material number (e.g. subtotal rows) are parsed into `None`.
+///
+/// # Errors
+///
+/// If the parsing failed, an error variant of [`ErrorKind::FromStr`] will be returned.
+pub fn parse_ios_record(row: &CsvRow, header: IosHeader) -> Result<Option<IosRecord>> {
+    macro_rules! get {
+        ($field:ident) => {
+            match row.get(header.$field) {
+                Some(v) => v.trim(),
+                None => return Ok(None),
+            }
+        };
+    }
+
+    let mid = get!(mid);
+    if mid.is_empty() {
+        return Ok(None);
+    }
+
+    let mut record = IosRecord::new();
+    record.mid = mid
+        .parse::<u32>()
+        .map_err(|_| Error::new(ErrorKind::FromStr(mid.to_owned(), "u32")))?;
+    let wid = get!(wid);
+    record.wid = wid
+        .parse::<u16>()
+        .map_err(|_| Error::new(ErrorKind::FromStr(wid.to_owned(), "u16")))?;
+    record.mname = get!(mname).to_string();
+    record.open_qt = parse_csv_number(get!(open_qt))?;
+    record.open_at = parse_csv_number(get!(open_at))?;
+    record.in_qt = parse_csv_number(get!(in_qt))?;
+    record.out_qt = parse_csv_number(get!(out_qt))?;
+    record.close_qt = parse_csv_number(get!(close_qt))?;
+    record.close_at = parse_csv_number(get!(close_at))?;
+
+    Ok(Some(record))
+}
@@ -0,0 +1,123 @@
+//! Writers for writing out aggregated IOS data.
+use super::aggregate::{IMap, WMap};
+use crate::{CsvWriter, Result};
+
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::LineWriter;
+
+/// Write IOS aggregation result to files.
+pub fn write_ios_result(maps: (IMap, WMap), out_dir: &str) -> Result<()> {
+    write_imap(maps.0, out_dir)?;
+    write_wmap(maps.1, out_dir)?;
+    Ok(())
+}
+
+/// Formats an optional number, leaving the field empty if it's `None`.
+fn opt(v: Option<f64>) -> String {
+    v.map(|v| format!("{:.2}", v)).unwrap_or_default()
+}
+
+/// `IMap` writer.
+pub fn write_imap(imap: IMap, out_dir: &str) -> Result<()> {
+    // Set up open options.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("{}/ios_sku.csv", out_dir))?;
+
+    // Write UTF-8 BOM.
+    file.write_all("\u{feff}".as_bytes())?;
+
+    // Names may contain commas, so fields are quoted when necessary.
+    let mut wtr = CsvWriter::new(LineWriter::new(file));
+    wtr.write_row([
+        "物料编码",
+        "物料名称",
+        "库存仓位数",
+        "期初数量",
+        "期初金额",
+        "入库数量",
+        "出库数量",
+        "净变动数量",
+        "期末数量",
+        "期末金额",
+        "期末单价",
+        "日均用量",
+        "可用天数",
+    ])?;
+
+    // Write records, sorted by material number.
+    let mut vec = imap.into_iter().map(|x| x.1).collect::<Vec<_>>();
+    vec.sort_unstable_by_key(|v| v.mid);
+    for v in vec {
+        wtr.write_row(&[
+            v.mid.to_string(),
+            v.mname,
+            v.warehouses.to_string(),
+            v.stock.open_qt.to_string(),
+            v.stock.open_at.to_string(),
+            v.stock.in_qt.to_string(),
+            v.stock.out_qt.to_string(),
+            v.stock.net_movement().to_string(),
+            v.stock.close_qt.to_string(),
+            v.stock.close_at.to_string(),
+            opt(v.stock.unit_value()),
+            opt(v.daily_usage),
+            opt(v.days_of_cover),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// `WMap` writer.
+pub fn write_wmap(wmap: WMap, out_dir: &str) -> Result<()> {
+    // Set up open options.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("{}/ios_warehouse.csv", out_dir))?;
+
+    // Write UTF-8 BOM.
+    file.write_all("\u{feff}".as_bytes())?;
+
+    // Write header to file
+    let mut wtr = CsvWriter::new(LineWriter::new(file));
+    wtr.write_row([
+        "仓位编码",
+        "库存SKU数",
+        "期初数量",
+        "期初金额",
+        "入库数量",
+        "出库数量",
+        "净变动数量",
+        "期末数量",
+        "期末金额",
+    ])?;
+
+    // Write records, sorted by warehouse number.
+    let mut vec = wmap.into_iter().map(|x| x.1).collect::<Vec<_>>();
+    vec.sort_unstable_by_key(|v| v.wid);
+    for v in vec {
+        wtr.write_row(&[
+            v.wid.to_string(),
+            v.sku_in_stock.to_string(),
+            v.stock.open_qt.to_string(),
+            v.stock.open_at.to_string(),
+            v.stock.in_qt.to_string(),
+            v.stock.out_qt.to_string(),
+            v.stock.net_movement().to_string(),
+            v.stock.close_qt.to_string(),
+            v.stock.close_at.to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
@@ -1,10 +1,17 @@
 //! Definitions of various iterators.
-use crate::{Error, ErrorKind, Result};
+use crate::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use walkdir::WalkDir;
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+mod multi;
+pub use self::multi::MultiFileReader;
+
+mod order;
+pub use self::order::SortOrder;
 
 /// File path entries.
 pub struct FilePathEntries {
@@ -19,7 +26,7 @@ impl FilePathEntries {
         }
     }
 
-    /// Creates an `Entries` from a directory of files.
+    /// Creates an `Entries` from a directory of files, sorted by name.
     pub fn from_dir(dir: &str) -> Result<Self> {
         let mut entries = Vec::new();
         for entry in WalkDir::new(dir).max_depth(1) {
@@ -29,11 +36,12 @@ impl FilePathEntries {
                 entries.push(path);
             }
         }
+        entries.sort();
         Ok(FilePathEntries { entries })
     }
 
-    /// Creates an `Entries` from a directory of files, excluding files whose extension
-    /// does not match one of the given extensions.
+    /// Creates an `Entries` from a directory of files sorted by name, excluding files whose
+    /// extension does not match one of the given extensions.
     ///
     /// Note that the `.` before extensions should not be given.
     ///
@@ -51,77 +59,169 @@ impl FilePathEntries {
                 }
             }
         }
+        entries.sort();
         Ok(FilePathEntries { entries })
     }
-}
 
-impl IntoIterator for FilePathEntries {
-    type Item = PathBuf;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
+    /// Sorts the entries in the given order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the modification time of an entry cannot be read when sorting by
+    /// [`SortOrder::Modified`].
+    pub fn sort(&mut self, order: SortOrder) -> Result<()> {
+        order::sort_paths(&mut self.entries, order)
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.entries.into_iter()
+impl FilePathEntries {
+    /// Creates a [`FilePathEntriesBuilder`] that collects files under the given directory.
+    pub fn builder<P: AsRef<Path>>(dir: P) -> FilePathEntriesBuilder {
+        FilePathEntriesBuilder {
+            root: dir.as_ref().to_path_buf(),
+            recursive: false,
+            include: GlobSetBuilder::new(),
+            has_include: false,
+            exclude: GlobSetBuilder::new(),
+            hidden: true,
+            since: None,
+            until: None,
+            order: SortOrder::Name,
+        }
     }
 }
 
-/// Line reader of mutiply files.
+/// A builder of [`FilePathEntries`] with recursion, glob and modification time filters.
+///
+/// Glob patterns are matched case-insensitively against paths relative to the root directory,
+/// and `*` also matches path separators, so `*.xlsx` matches `2021/03/DATA.XLSX`. Entries are
+/// sorted by path unless another order is given.
 #[derive(Debug)]
-pub struct LineReader {
-    paths: Vec<File>,
-    current: Option<BufReader<File>>,
-    head_once: bool,
+pub struct FilePathEntriesBuilder {
+    root: PathBuf,
+    recursive: bool,
+    include: GlobSetBuilder,
+    has_include: bool,
+    exclude: GlobSetBuilder,
+    hidden: bool,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    order: SortOrder,
 }
 
-impl LineReader {
-    pub fn new<'a, P>(paths: Vec<&'a P>, head_once: bool) -> Result<LineReader>
-    where
-        P: AsRef<Path> + ?Sized,
-    {
-        let mut checked = Vec::new();
-
-        for p in paths {
-            if !p.as_ref().is_file() {
-                return Err(Error::new(ErrorKind::Access(format!(
-                    "{}",
-                    p.as_ref().display()
-                ))));
-            }
-            checked.push(File::open(p)?)
-        }
+impl FilePathEntriesBuilder {
+    /// Whether to descend into subdirectories, defaults to `false`.
+    pub fn recursive(&mut self, yes: bool) -> &mut Self {
+        self.recursive = yes;
+        self
+    }
 
-        Ok(LineReader {
-            paths: checked,
-            head_once,
-            current: None,
-        })
+    /// Only collects files matching the given glob pattern, or any other included pattern.
+    ///
+    /// All files are collected if no pattern is included.
+    pub fn include(&mut self, glob: &str) -> Result<&mut Self> {
+        self.include.add(case_insensitive(glob)?);
+        self.has_include = true;
+        Ok(self)
     }
 
-    pub fn next_line(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
-        match self.current.as_mut() {
-            Some(reader) => match reader.read_until(b'\n', buf) {
-                Ok(0) => match self.paths.pop() {
-                    Some(f) => {
-                        let mut reader = BufReader::new(f);
-                        if self.head_once {
-                            let mut buf = Vec::new();
-                            reader.read_until(b'\n', &mut buf)?;
-                        }
-                        self.current = Some(reader);
-                        return self.next_line(buf);
-                    }
-                    None => return Ok(0),
-                },
-                Ok(bytes_read) => return Ok(bytes_read),
-                Err(e) => return Err(e.into()),
-            },
-            None => {
-                if let Some(f) = self.paths.pop() {
-                    let reader = BufReader::new(f);
-                    self.current = Some(reader);
-                    return self.next_line(buf);
+    /// Skips files matching the given glob pattern.
+    pub fn exclude(&mut self, glob: &str) -> Result<&mut Self> {
+        self.exclude.add(case_insensitive(glob)?);
+        Ok(self)
+    }
+
+    /// Whether to collect hidden files and descend into hidden directories, i.e. those whose
+    /// names start with `.`, defaults to `true`.
+    pub fn hidden(&mut self, yes: bool) -> &mut Self {
+        self.hidden = yes;
+        self
+    }
+
+    /// Only collects files modified at or after the given time.
+    pub fn modified_since(&mut self, time: SystemTime) -> &mut Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Only collects files modified before the given time.
+    pub fn modified_until(&mut self, time: SystemTime) -> &mut Self {
+        self.until = Some(time);
+        self
+    }
+
+    /// The order of the collected files, defaults to [`SortOrder::Name`].
+    pub fn order(&mut self, order: SortOrder) -> &mut Self {
+        self.order = order;
+        self
+    }
+
+    /// Walks the root directory and collects the matching files.
+    pub fn build(&self) -> Result<FilePathEntries> {
+        let include = self.include.build()?;
+        let exclude = self.exclude.build()?;
+        let is_match = |path: &Path, set: &GlobSet| {
+            let rel = path.strip_prefix(&self.root).unwrap_or(path);
+            set.is_match(rel)
+        };
+
+        let mut walker = WalkDir::new(&self.root).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        if !self.recursive {
+            walker = walker.max_depth(1);
+        }
+
+        let hidden = self.hidden;
+        let is_visible = move |entry: &walkdir::DirEntry| {
+            hidden || entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        };
+
+        let mut entries = Vec::new();
+        for entry in walker.into_iter().filter_entry(is_visible) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            if self.has_include && !is_match(path, &include) {
+                continue;
+            }
+            if is_match(path, &exclude) {
+                continue;
+            }
+            if self.since.is_some() || self.until.is_some() {
+                let modified = entry.metadata()?.modified()?;
+                if self.since.is_some_and(|since| modified < since)
+                    || self.until.is_some_and(|until| modified >= until)
+                {
+                    continue;
                 }
-                return Ok(0);
             }
+            entries.push(path.to_path_buf());
+        }
+        order::sort_paths(&mut entries, self.order)?;
+
+        Ok(FilePathEntries { entries })
+    }
+}
+
+/// Builds a glob pattern that ignores case, as file names usually do on Windows.
+fn case_insensitive(glob: &str) -> Result<globset::Glob> {
+    Ok(GlobBuilder::new(glob).case_insensitive(true).build()?)
+}
+
+impl FromIterator<PathBuf> for FilePathEntries {
+    fn from_iter<I: IntoIterator<Item = PathBuf>>(iter: I) -> Self {
+        FilePathEntries {
+            entries: iter.into_iter().collect(),
         }
     }
 }
+
+impl IntoIterator for FilePathEntries {
+    type Item = PathBuf;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
@@ -0,0 +1,487 @@
+use crate::convert::{detect, EncodeType, Utf8Check};
+use crate::{Error, ErrorKind, Result};
+use encoding_rs::{CoderResult, Decoder};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+/// The number of bytes read from a file at a time.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A function called with the index and path of each file when starting to read it.
+type BoundaryFn = Box<dyn FnMut(usize, &Path)>;
+
+/// A reader of multiple files that reads them one after another as a single stream of lines.
+///
+/// Each file is decoded with its own encoding and yielded as UTF-8, so files of different
+/// encodings can be read together. Every yielded line ends with a newline, even the last line
+/// of a file that does not, so lines of different files are never joined.
+///
+/// Lines can be skipped at the head and at the tail of each file, such as the header and total
+/// lines of reports, and the origin of each line is available from
+/// [`provenance`](MultiFileReader::provenance).
+pub struct MultiFileReader {
+    sources: Vec<(PathBuf, Option<EncodeType>)>,
+    /// The index of the next file to open.
+    next: usize,
+    current: Option<Source>,
+    encoding: EncodeType,
+    skip_head: usize,
+    head_once: bool,
+    skip_tail: usize,
+    on_boundary: Option<BoundaryFn>,
+    /// The line being read and how much of it has been consumed.
+    line: String,
+    consumed: usize,
+    /// The file index and line number of `line`.
+    provenance: Option<(usize, u64)>,
+}
+
+impl fmt::Debug for MultiFileReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiFileReader")
+            .field("sources", &self.sources)
+            .field("next", &self.next)
+            .field("encoding", &self.encoding)
+            .field("skip_head", &self.skip_head)
+            .field("head_once", &self.head_once)
+            .field("skip_tail", &self.skip_tail)
+            .field("provenance", &self.provenance)
+            .finish()
+    }
+}
+
+impl MultiFileReader {
+    /// Creates a reader of the given files, decoded with the default encoding, see
+    /// [`encoding`](MultiFileReader::encoding).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error variant of [`ErrorKind::Access`] if a path is not a file.
+    pub fn new<I, P>(paths: I) -> Result<MultiFileReader>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        MultiFileReader::with_encodings(paths.into_iter().map(|p| (p, None)))
+    }
+
+    /// Creates a reader of the given files, each decoded with the paired encoding, or with the
+    /// default encoding if `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error variant of [`ErrorKind::Access`] if a path is not a file.
+    pub fn with_encodings<I, P>(paths: I) -> Result<MultiFileReader>
+    where
+        I: IntoIterator<Item = (P, Option<EncodeType>)>,
+        P: AsRef<Path>,
+    {
+        let mut sources = Vec::new();
+        for (path, encoding) in paths {
+            let path = path.as_ref();
+            if !path.is_file() {
+                return Err(Error::new(ErrorKind::Access(format!("{}", path.display()))));
+            }
+            sources.push((path.to_path_buf(), encoding));
+        }
+
+        Ok(MultiFileReader {
+            sources,
+            next: 0,
+            current: None,
+            encoding: EncodeType::Auto,
+            skip_head: 0,
+            head_once: false,
+            skip_tail: 0,
+            on_boundary: None,
+            line: String::new(),
+            consumed: 0,
+            provenance: None,
+        })
+    }
+
+    /// The encoding of files without their own encoding, defaults to [`EncodeType::Auto`].
+    pub fn encoding(&mut self, encoding: EncodeType) -> &mut Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Skips the given number of lines at the head of each file, defaults to `0`.
+    pub fn skip_head(&mut self, lines: usize) -> &mut Self {
+        self.skip_head = lines;
+        self
+    }
+
+    /// Whether to keep the head lines of the first file, skipping those of the other files only,
+    /// defaults to `false`.
+    pub fn head_once(&mut self, yes: bool) -> &mut Self {
+        self.head_once = yes;
+        self
+    }
+
+    /// Skips the given number of lines at the tail of each file, defaults to `0`.
+    pub fn skip_tail(&mut self, lines: usize) -> &mut Self {
+        self.skip_tail = lines;
+        self
+    }
+
+    /// Calls the given function with the index and path of each file when starting to read it.
+    pub fn on_boundary<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(usize, &Path) + 'static,
+    {
+        self.on_boundary = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the paths of the files, in reading order.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.sources.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Returns the path and the (one-based) line number of the line being read, i.e. the line
+    /// last returned by [`fill_buf`](BufRead::fill_buf) or [`read_line`](BufRead::read_line).
+    ///
+    /// Returns `None` if no line has been read yet.
+    pub fn provenance(&self) -> Option<(&Path, u64)> {
+        self.provenance
+            .map(|(file, line)| (self.sources[file].0.as_path(), line))
+    }
+
+    /// Loads the next line into `self.line`, returning `false` at the end of the last file.
+    fn load_line(&mut self) -> Result<bool> {
+        loop {
+            if self.current.is_none() {
+                if self.next == self.sources.len() {
+                    return Ok(false);
+                }
+                let index = self.next;
+                self.next += 1;
+
+                let (path, encoding) = &self.sources[index];
+                let mut source = Source::open(index, path, encoding.unwrap_or(self.encoding))?;
+                if let Some(f) = self.on_boundary.as_mut() {
+                    f(index, path);
+                }
+
+                let skip_head = if self.head_once && index == 0 {
+                    0
+                } else {
+                    self.skip_head
+                };
+                for _ in 0..skip_head {
+                    if source.read_line()?.is_none() {
+                        break;
+                    }
+                }
+                self.current = Some(source);
+            }
+
+            let source = self.current.as_mut().unwrap();
+            // Holds back the last `skip_tail` lines until the end of the file is known.
+            while source.pending.len() <= self.skip_tail {
+                match source.read_line()? {
+                    Some(line) => source.pending.push_back(line),
+                    None => break,
+                }
+            }
+            if source.pending.len() <= self.skip_tail {
+                self.current = None;
+                continue;
+            }
+
+            let (n, line) = source.pending.pop_front().unwrap();
+            self.line = line;
+            self.consumed = 0;
+            self.provenance = Some((source.index, n));
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for MultiFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for MultiFileReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed >= self.line.len() && !self.load_line()? {
+            return Ok(&[]);
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed = (self.consumed + amt).min(self.line.len());
+    }
+}
+
+/// A file being read, decoded chunk by chunk.
+struct Source {
+    index: usize,
+    path: PathBuf,
+    file: File,
+    encoding: EncodeType,
+    /// `None` until the encoding has been detected from the first chunk.
+    decoder: Option<Decoder>,
+    /// Set while a file detected as UTF-8 may still fall back to GB18030, see [`detect`].
+    check: Option<Utf8Check>,
+    chunk: Vec<u8>,
+    /// Decoded text, of which the part from `start` on has not been split into lines yet.
+    text: String,
+    start: usize,
+    eof: bool,
+    /// The number of lines read so far.
+    lines: u64,
+    /// Lines held back for tail skipping, with their line numbers.
+    pending: VecDeque<(u64, String)>,
+}
+
+impl Source {
+    fn open(index: usize, path: &Path, encoding: EncodeType) -> Result<Source> {
+        Ok(Source {
+            index,
+            path: path.to_path_buf(),
+            file: File::open(path)?,
+            encoding,
+            decoder: None,
+            check: None,
+            chunk: vec![0u8; CHUNK_SIZE],
+            text: String::new(),
+            start: 0,
+            eof: false,
+            lines: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Reads the next line with its line number, returning `None` at the end of the file.
+    fn read_line(&mut self) -> Result<Option<(u64, String)>> {
+        loop {
+            if let Some(i) = self.text[self.start..].find('\n') {
+                let end = self.start + i + 1;
+                let line = self.text[self.start..end].to_owned();
+                self.start = end;
+                self.lines += 1;
+                return Ok(Some((self.lines, line)));
+            }
+            if self.eof {
+                if self.start == self.text.len() {
+                    return Ok(None);
+                }
+                let mut line = self.text[self.start..].to_owned();
+                self.start = self.text.len();
+                line.push('\n');
+                self.lines += 1;
+                return Ok(Some((self.lines, line)));
+            }
+            // Drops the lines already read once per chunk, rather than once per line.
+            self.text.drain(..self.start);
+            self.start = 0;
+            self.decode_chunk()?;
+        }
+    }
+
+    /// Reads and decodes the next chunk of the file into `self.text`.
+    fn decode_chunk(&mut self) -> Result<()> {
+        let len = self.file.read(&mut self.chunk)?;
+        let mut src = &self.chunk[..len];
+
+        if self.decoder.is_none() {
+            let encoding = match self.encoding {
+                EncodeType::Auto => detect(src),
+                encoding => encoding,
+            };
+            let has_bom = match encoding.bom() {
+                Some(bom) if src.starts_with(bom) => {
+                    src = &src[bom.len()..];
+                    true
+                }
+                _ => false,
+            };
+            if self.encoding == EncodeType::Auto && encoding == EncodeType::UTF8 && !has_bom {
+                self.check = Some(Utf8Check::default());
+            }
+            self.encoding = encoding;
+            self.decoder =
+                Some(crate::convert::encoding_rs(encoding).new_decoder_without_bom_handling());
+        }
+
+        let last = len == 0;
+        let fallback;
+        if let Some(bytes) = self.check.as_mut().and_then(|check| check.check(src, last)) {
+            self.check = None;
+            fallback = bytes;
+            src = &fallback;
+            self.encoding = EncodeType::GB18030;
+            self.decoder = Some(
+                crate::convert::encoding_rs(EncodeType::GB18030).new_decoder_without_bom_handling(),
+            );
+        }
+        let decoder = self.decoder.as_mut().unwrap();
+        loop {
+            if let Some(n) = decoder.max_utf8_buffer_length(src.len()) {
+                self.text.reserve(n);
+            }
+            let (result, read, malformed) = decoder.decode_to_string(src, &mut self.text, last);
+            if malformed {
+                return Err(Error::new(ErrorKind::Decode(format!(
+                    "`{}` is not valid {:?} (line {})",
+                    self.path.display(),
+                    self.encoding,
+                    self.lines + 1
+                ))));
+            }
+            src = &src[read..];
+            if result == CoderResult::InputEmpty {
+                break;
+            }
+        }
+        self.eof = last;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Returns an empty directory under the system temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dpt-multi-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_to_string(rdr: &mut MultiFileReader) -> String {
+        let mut text = String::new();
+        rdr.read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn falls_back_to_gb18030_after_the_first_chunk() {
+        let dir = temp_dir("fallback");
+        let path = dir.join("a.csv");
+        // The first chunk is ASCII only, and so detected as UTF-8.
+        let mut bytes = vec![b'a'; CHUNK_SIZE - 1];
+        bytes.extend_from_slice(b"\xD6\xD0\xCE\xC4\n");
+        fs::write(&path, bytes).unwrap();
+
+        let mut rdr = MultiFileReader::new([&path]).unwrap();
+        let text = read_to_string(&mut rdr);
+        assert_eq!(&text[CHUNK_SIZE - 1..], "中文\n");
+
+        // An explicit encoding does not fall back.
+        let mut rdr = MultiFileReader::new([&path]).unwrap();
+        rdr.encoding(EncodeType::UTF8);
+        let mut text = String::new();
+        assert!(rdr.read_to_string(&mut text).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Writes the given files into `dir`, returning their paths.
+    fn write_files(dir: &Path, files: &[(&str, &[u8])]) -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|(name, bytes)| {
+                let path = dir.join(name);
+                fs::write(&path, bytes).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_head_and_tail_lines_of_each_file() {
+        let dir = temp_dir("skip");
+        let paths = write_files(
+            &dir,
+            &[
+                ("a.csv", b"name,qty\r\nsalt,1\r\nrice,2\r\ntotal,3\r\n"),
+                ("b.csv", b"name,qty\r\ntea,4\r\ntotal,4\r\n"),
+                ("c.csv", b"name,qty\r\n"),
+            ],
+        );
+
+        let mut rdr = MultiFileReader::new(&paths).unwrap();
+        rdr.skip_head(1).skip_tail(1);
+        assert_eq!(read_to_string(&mut rdr), "salt,1\r\nrice,2\r\ntea,4\r\n");
+
+        let mut rdr = MultiFileReader::new(&paths).unwrap();
+        rdr.skip_head(1).head_once(true).skip_tail(1);
+        assert_eq!(
+            read_to_string(&mut rdr),
+            "name,qty\r\nsalt,1\r\nrice,2\r\ntea,4\r\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ends_the_last_line_of_each_file_with_a_newline() {
+        let dir = temp_dir("newline");
+        let paths = write_files(&dir, &[("a.csv", b"salt,1\r\nrice,2"), ("b.csv", b"tea,4")]);
+
+        let mut rdr = MultiFileReader::new(&paths).unwrap();
+        assert_eq!(read_to_string(&mut rdr), "salt,1\r\nrice,2\ntea,4\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tracks_the_provenance_of_each_line() {
+        let dir = temp_dir("provenance");
+        let paths = write_files(
+            &dir,
+            &[
+                ("a.csv", b"name,qty\nsalt,1\nrice,2\n"),
+                ("b.csv", b"\xC3\xFB\xB3\xC6,qty\n\xB2\xE8,4\n"),
+            ],
+        );
+
+        let boundaries = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut rdr = MultiFileReader::with_encodings(vec![
+            (&paths[0], None),
+            (&paths[1], Some(EncodeType::GB18030)),
+        ])
+        .unwrap();
+        let seen = boundaries.clone();
+        rdr.skip_head(1)
+            .on_boundary(move |index, _| seen.borrow_mut().push(index));
+        assert_eq!(rdr.provenance(), None);
+
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        while rdr.read_line(&mut line).unwrap() > 0 {
+            let (path, n) = rdr.provenance().unwrap();
+            lines.push((line.clone(), path.to_path_buf(), n));
+            line.clear();
+        }
+        assert_eq!(
+            lines,
+            [
+                ("salt,1\n".to_owned(), paths[0].clone(), 2),
+                ("rice,2\n".to_owned(), paths[0].clone(), 3),
+                ("茶,4\n".to_owned(), paths[1].clone(), 2),
+            ]
+        );
+        assert_eq!(*boundaries.borrow(), [0, 1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+use crate::Result;
+use chrono::NaiveDate;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Orders in which file path entries can be sorted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Lexicographic order of paths, e.g. `data10.csv` before `data2.csv`.
+    Name,
+    /// Paths compared with runs of digits taken as numbers, e.g. `data2.csv` before
+    /// `data10.csv`.
+    Natural,
+    /// Oldest modified first.
+    Modified,
+    /// Earliest date in the file name first, such as `ST_2021-03-01.csv` or `ST20210301.csv`.
+    /// Files without a date in their names come last.
+    Date,
+}
+
+/// Sorts the given paths in the given order, breaking ties by natural order.
+pub(super) fn sort_paths(paths: &mut Vec<PathBuf>, order: SortOrder) -> Result<()> {
+    match order {
+        SortOrder::Name => paths.sort(),
+        SortOrder::Natural => paths.sort_by(|a, b| natural_cmp(a, b)),
+        SortOrder::Modified => {
+            let mut keyed = Vec::with_capacity(paths.len());
+            for path in paths.drain(..) {
+                let modified = fs::metadata(&path)?.modified()?;
+                keyed.push((modified, path));
+            }
+            keyed.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| natural_cmp(&a.1, &b.1)));
+            paths.extend(keyed.into_iter().map(|(_, path)| path));
+        }
+        SortOrder::Date => {
+            let mut keyed = paths
+                .drain(..)
+                .map(|path| (date_in_name(&path), path))
+                .collect::<Vec<_>>();
+            keyed.sort_by(|a, b| match (a.0, b.0) {
+                (Some(x), Some(y)) => x.cmp(&y).then_with(|| natural_cmp(&a.1, &b.1)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => natural_cmp(&a.1, &b.1),
+            });
+            paths.extend(keyed.into_iter().map(|(_, path)| path));
+        }
+    }
+    Ok(())
+}
+
+/// Compares two paths, taking runs of ASCII digits as numbers.
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let a = a.to_string_lossy();
+    let b = b.to_string_lossy();
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    while !a.is_empty() && !b.is_empty() {
+        if a[0].is_ascii_digit() && b[0].is_ascii_digit() {
+            let (x, rest_a) = split_digits(a);
+            let (y, rest_b) = split_digits(b);
+            // Compares numbers by their significant digits, then by their leading zeros.
+            let (tx, ty) = (trim_zeros(x), trim_zeros(y));
+            let ord = tx
+                .len()
+                .cmp(&ty.len())
+                .then_with(|| tx.cmp(ty))
+                .then_with(|| x.len().cmp(&y.len()));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = rest_a;
+            b = rest_b;
+        } else {
+            match a[0].cmp(&b[0]) {
+                Ordering::Equal => {
+                    a = &a[1..];
+                    b = &b[1..];
+                }
+                ord => return ord,
+            }
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn split_digits(s: &[u8]) -> (&[u8], &[u8]) {
+    let n = s.iter().take_while(|c| c.is_ascii_digit()).count();
+    s.split_at(n)
+}
+
+fn trim_zeros(s: &[u8]) -> &[u8] {
+    let n = s.iter().take_while(|&&c| c == b'0').count();
+    &s[n..]
+}
+
+/// Finds the first date in a file name, written as `yyyymmdd` or as `yyyy-m-d` with `-`, `_` or
+/// `.` as separators.
+fn date_in_name(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_string_lossy();
+    let name = name.as_bytes();
+
+    // Runs of digits with their start positions.
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < name.len() {
+        if name[i].is_ascii_digit() {
+            let (digits, _) = split_digits(&name[i..]);
+            runs.push((i, digits));
+            i += digits.len();
+        } else {
+            i += 1;
+        }
+    }
+
+    let number = |digits: &[u8]| std::str::from_utf8(digits).ok()?.parse::<u32>().ok();
+    let is_separated = |a: (usize, &[u8]), b: (usize, &[u8])| {
+        let gap = a.0 + a.1.len();
+        b.0 == gap + 1 && [b'-', b'_', b'.'].contains(&name[gap])
+    };
+
+    for (k, &run) in runs.iter().enumerate() {
+        let date = match run.1.len() {
+            8 => NaiveDate::from_ymd_opt(
+                number(&run.1[..4])? as i32,
+                number(&run.1[4..6])?,
+                number(&run.1[6..])?,
+            ),
+            4 if k + 2 < runs.len() => {
+                let (month, day) = (runs[k + 1], runs[k + 2]);
+                if is_separated(run, month)
+                    && is_separated(month, day)
+                    && (1..=2).contains(&month.1.len())
+                    && (1..=2).contains(&day.1.len())
+                {
+                    NaiveDate::from_ymd_opt(number(run.1)? as i32, number(month.1)?, number(day.1)?)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if date.is_some() {
+            return date;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str], order: SortOrder) -> Vec<String> {
+        let mut paths = names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        sort_paths(&mut paths, order).unwrap();
+        paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn natural_order_compares_numbers() {
+        let cmp = |a: &str, b: &str| natural_cmp(Path::new(a), Path::new(b));
+        assert_eq!(cmp("data2.csv", "data10.csv"), Ordering::Less);
+        assert_eq!(cmp("data10.csv", "data9.csv"), Ordering::Greater);
+        assert_eq!(cmp("data02.csv", "data2.csv"), Ordering::Greater);
+        assert_eq!(cmp("data2.csv", "data2.csv"), Ordering::Equal);
+        assert_eq!(cmp("data", "data1"), Ordering::Less);
+        assert_eq!(cmp("a100", "b2"), Ordering::Less);
+    }
+
+    #[test]
+    fn sorts_by_name_and_naturally() {
+        let names = ["d10.csv", "d9.csv", "d1.csv"];
+        assert_eq!(
+            sorted(&names, SortOrder::Name),
+            ["d1.csv", "d10.csv", "d9.csv"]
+        );
+        assert_eq!(
+            sorted(&names, SortOrder::Natural),
+            ["d1.csv", "d9.csv", "d10.csv"]
+        );
+    }
+
+    #[test]
+    fn finds_dates_in_names() {
+        let date = |name: &str| date_in_name(Path::new(name));
+        let ymd = |y, m, d| NaiveDate::from_ymd_opt(y, m, d);
+        assert_eq!(date("ST_2021-03-01.csv"), ymd(2021, 3, 1));
+        assert_eq!(date("ST20210301.csv"), ymd(2021, 3, 1));
+        assert_eq!(date("st_2021_3_9.csv"), ymd(2021, 3, 9));
+        assert_eq!(date("v2 2021.12.31.csv"), ymd(2021, 12, 31));
+        assert_eq!(date("dir2021-01-01/ST.csv"), None);
+        assert_eq!(date("ST_2021-13-01.csv"), None);
+        assert_eq!(date("ST_2021-03.csv"), None);
+        assert_eq!(date("ST_2021-03-001.csv"), None);
+    }
+
+    #[test]
+    fn sorts_by_date_then_naturally() {
+        let names = [
+            "b.csv",
+            "ST_2021-03-10.csv",
+            "ST20210302_2.csv",
+            "a.csv",
+            "ST_2021-3-2_10.csv",
+        ];
+        assert_eq!(
+            sorted(&names, SortOrder::Date),
+            [
+                "ST20210302_2.csv",
+                "ST_2021-3-2_10.csv",
+                "ST_2021-03-10.csv",
+                "a.csv",
+                "b.csv"
+            ]
+        );
+    }
+}
@@ -63,7 +63,7 @@ pub async fn calculate_ie_report(
 
 pub async fn open_report_menu(
     mut client: JdeClient,
-    locators: &Locators
+    locators: &Locators,
 ) -> Result<JdeClient, fantoccini::error::CmdError> {
     client
         .wait_click(&locators.report_btn)
@@ -14,6 +14,7 @@ pub use login::*;
 use crate::{Error, ErrorKind, Result};
 use toml::Value;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Jde {
     pub username: String,
     pub password: String,
@@ -21,6 +22,7 @@ pub struct Jde {
     pub browser_path: String,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Locators {
     pub close_btn: String,
     pub data_select_btn: String,
@@ -3,20 +3,49 @@
 //! The main entries of this library are several core modules, including:
 //!
 //! - [`st`]: Performs preset statistical aggregations for ST records.
+//! - [`ios`]: Aggregates IOS (inventory on stock) reports and computes days of cover.
+//! - [`ie`]: Reconciles IE reports against ST records.
 //! - [`jde`]: Automatically connects JDE and downloads data from JDE.
+//! - [`db`]: Stores ST records in a local SQLite database.
+//! - [`query`]: Runs ad-hoc queries over ST records.
+//! - [`concat`]: Concatenates CSV files aligned by header name and drops duplicate records.
 //! - [`convert`]: Converts .xlsx to .csv and performs character set encoding and decoding.
-#![feature(seek_stream_len)]
+//!
+//! # Features
+//!
+//! - `jde`: Builds [`jde`], which drives a browser with `fantoccini`.
+//! - `convert-xlsx`: Reads and writes Excel workbooks in [`convert`].
+//! - `db`: Builds [`db`], which compiles SQLite from source.
+//! - `logger`: Provides [`Logger`]. Without it, e.g. with `default-features = false`, `Logger`
+//!   is not available.
+//! - `cli`: Builds [`cli`] and the `dpt` binary, enabling all of the above as well. Enabled by
+//!   default.
+//! - `serde`: Implements `Serialize` and `Deserialize` for the public data types, such as
+//!   records and aggregation results.
 #![allow(deprecated)]
 
 mod error;
+#[cfg(feature = "logger")]
 mod logger;
 mod reader;
+mod writer;
 
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod concat;
 pub mod convert;
+#[cfg(feature = "db")]
+pub mod db;
+pub mod ie;
+pub mod ios;
 pub mod iter;
+#[cfg(feature = "jde")]
 pub mod jde;
+pub mod query;
 pub mod st;
 
 pub use crate::error::{Error, ErrorKind, Result};
+#[cfg(feature = "logger")]
 pub use crate::logger::Logger;
-pub use crate::reader::{CsvReader, CsvReaderOptions};
+pub use crate::reader::{CsvReader, CsvReaderOptions, CsvRow, CsvRowIter, Position, Terminator};
+pub use crate::writer::{CsvWriter, CsvWriterOptions};
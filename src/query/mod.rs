@@ -0,0 +1,515 @@
+//! Runs ad-hoc queries over ST records: filters records with an expression, groups them by any
+//! combination of keys and computes aggregates of each group.
+pub mod parse;
+pub mod writer;
+
+use crate::convert::EncodeType;
+use crate::st::aggregate::{get_store_type, StoreLoc, StoreType};
+use crate::st::parse::{parse_config_store_ranges, parse_header, parse_record, Record, StoreRange};
+use crate::{CsvReader, CsvReaderOptions};
+use crate::{Error, ErrorKind, Result};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use fxhash::{FxHashMap, FxHashSet};
+use toml::Value as Config;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+
+/// A field of an ST record, or derived from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Mid,
+    Sid,
+    Wid,
+    Mname,
+    Sname,
+    Qt,
+    At,
+    Dt,
+    /// The brand of the store, as configured by the store ranges of `config.toml`.
+    Brand,
+    /// Whether the store is local or outer, as configured by the store ranges of `config.toml`.
+    Market,
+}
+
+/// A comparison operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Whether a text field contains the value.
+    Contains,
+}
+
+/// A value a field is compared to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+    Date(NaiveDate),
+}
+
+/// A filter expression, see [`parse::parse_filter`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Cmp(Field, Op, Value),
+    In(Field, Vec<Value>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// A key records are grouped by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupKey {
+    Material,
+    Store,
+    Warehouse,
+    Brand,
+    Market,
+    Day,
+    /// The week of the date, identified by its Monday.
+    Week,
+    Month,
+    Year,
+}
+
+/// An aggregate computed for each group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// The number of records.
+    Count,
+    Sum(Field),
+    /// The number of distinct values of a field.
+    Distinct(Field),
+    Min(Field),
+    Max(Field),
+}
+
+/// A query over ST records.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// Only records matching this expression are aggregated, all records if `None`.
+    pub filter: Option<Expr>,
+    /// The keys records are grouped by, in column order. All records form a single group if
+    /// empty.
+    pub group_by: Vec<GroupKey>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// The result of a query, one row per group, ordered by group keys.
+#[derive(Debug, Clone, Default)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Field {
+    /// Returns the name of this field in the query language.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Mid => "mid",
+            Field::Sid => "sid",
+            Field::Wid => "wid",
+            Field::Mname => "mname",
+            Field::Sname => "sname",
+            Field::Qt => "qt",
+            Field::At => "at",
+            Field::Dt => "dt",
+            Field::Brand => "brand",
+            Field::Market => "market",
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Field::Mid | Field::Sid | Field::Wid | Field::Qt | Field::At
+        )
+    }
+
+    pub fn is_text(self) -> bool {
+        matches!(
+            self,
+            Field::Mname | Field::Sname | Field::Brand | Field::Market
+        )
+    }
+
+    /// Translates the short names of brands (`jmj`, `tey`, `lkd`, `son`, `nws`, `dc`, `oth`) and
+    /// markets (`local`, `outer`, `unknown`) into the names used in results.
+    fn normalize(self, s: &str) -> String {
+        match (self, s.to_lowercase().as_str()) {
+            (Field::Brand, "jmj") => StoreType::Jmj.to_string(),
+            (Field::Brand, "tey") => StoreType::Tey.to_string(),
+            (Field::Brand, "lkd") => StoreType::Lkd.to_string(),
+            (Field::Brand, "son") => StoreType::Son.to_string(),
+            (Field::Brand, "nws") => StoreType::Nws.to_string(),
+            (Field::Brand, "dc") => StoreType::Dc.to_string(),
+            (Field::Brand, "oth") => StoreType::Oth.to_string(),
+            (Field::Market, "local") => StoreLoc::Local.to_string(),
+            (Field::Market, "outer") => StoreLoc::Outer.to_string(),
+            (Field::Market, "unknown") => StoreLoc::Unknown.to_string(),
+            _ => s.to_owned(),
+        }
+    }
+}
+
+impl Op {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Contains => "~",
+        }
+    }
+}
+
+impl fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Aggregate::Count => write!(f, "count"),
+            Aggregate::Sum(field) => write!(f, "sum({})", field.name()),
+            Aggregate::Distinct(field) => write!(f, "distinct({})", field.name()),
+            Aggregate::Min(field) => write!(f, "min({})", field.name()),
+            Aggregate::Max(field) => write!(f, "max({})", field.name()),
+        }
+    }
+}
+
+impl GroupKey {
+    /// Returns the header names of the columns of this key.
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            GroupKey::Material => &["物料编码", "物料名称"],
+            GroupKey::Store => &["门店编码", "门店名称"],
+            GroupKey::Warehouse => &["仓位编码"],
+            GroupKey::Brand => &["品牌"],
+            GroupKey::Market => &["区域"],
+            GroupKey::Day => &["日期"],
+            GroupKey::Week => &["周"],
+            GroupKey::Month => &["月份"],
+            GroupKey::Year => &["年份"],
+        }
+    }
+}
+
+impl Query {
+    /// Creates a query from its textual parts, see [`parse::parse_filter`],
+    /// [`parse::parse_group_by`] and [`parse::parse_aggregates`].
+    ///
+    /// Without aggregates, the number of records and the sums of quantity and amount are
+    /// computed.
+    pub fn parse(
+        filter: Option<&str>,
+        group_by: Option<&str>,
+        aggregates: Option<&str>,
+    ) -> Result<Query> {
+        let filter = match filter {
+            Some(s) if !s.trim().is_empty() => Some(parse::parse_filter(s)?),
+            _ => None,
+        };
+        let group_by = match group_by {
+            Some(s) => parse::parse_group_by(s)?,
+            None => Vec::new(),
+        };
+        let mut aggregates = match aggregates {
+            Some(s) => parse::parse_aggregates(s)?,
+            None => Vec::new(),
+        };
+        if aggregates.is_empty() {
+            aggregates = vec![
+                Aggregate::Count,
+                Aggregate::Sum(Field::Qt),
+                Aggregate::Sum(Field::At),
+            ];
+        }
+
+        Ok(Query {
+            filter,
+            group_by,
+            aggregates,
+        })
+    }
+}
+
+/// Runs a query over the ST records of the given file.
+pub fn query(
+    file: File,
+    encoding: EncodeType,
+    config: &Config,
+    options: CsvReaderOptions,
+    query: &Query,
+    strict: bool,
+) -> Result<QueryResult> {
+    let mut rdr = CsvReader::with_options(BufReader::new(file), encoding, options);
+    let header = parse_header(rdr.read_header()?, config)?;
+    let mut executor = Executor::new(query, config)?;
+
+    while let Some(row) = rdr.read_row()? {
+        let record = match parse_record(row, header) {
+            Ok(Some(record)) => record,
+            // All records have been read when reaching here.
+            Ok(None) => break,
+            Err(e) if strict => {
+                return Err(Error::new(ErrorKind::Csv(e.to_string(), row.position())))
+            }
+            Err(_) => continue,
+        };
+        executor.observe(&record);
+    }
+
+    Ok(executor.finish())
+}
+
+/// Runs a query over records observed one by one, for records that do not come from a file.
+pub struct Executor<'a> {
+    query: &'a Query,
+    ranges: StoreRange,
+    groups: BTreeMap<Vec<KeyPart>, Vec<Accumulator>>,
+    mnames: FxHashMap<u32, String>,
+    snames: FxHashMap<u32, String>,
+}
+
+/// A part of a group key, or a distinct value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum KeyPart {
+    Int(u64),
+    Text(String),
+    Date(Option<NaiveDate>),
+}
+
+#[derive(Debug, Clone)]
+enum Accumulator {
+    Count(u64),
+    Sum(f64),
+    Distinct(FxHashSet<KeyPart>),
+    Min(Option<f64>),
+    Max(Option<f64>),
+}
+
+impl<'a> Executor<'a> {
+    /// Creates an executor of the given query, reading store ranges from `config.toml`.
+    pub fn new(query: &'a Query, config: &Config) -> Result<Executor<'a>> {
+        Ok(Executor {
+            query,
+            ranges: parse_config_store_ranges(config)?,
+            groups: BTreeMap::new(),
+            mnames: FxHashMap::default(),
+            snames: FxHashMap::default(),
+        })
+    }
+
+    /// Aggregates the given record if it matches the filter of the query.
+    pub fn observe(&mut self, record: &Record) {
+        let (store_type, store_loc) = get_store_type(record.sid, &self.ranges);
+        let row = Row {
+            record,
+            brand: store_type.to_string(),
+            market: store_loc.to_string(),
+        };
+        if let Some(filter) = self.query.filter.as_ref() {
+            if !row.matches(filter) {
+                return;
+            }
+        }
+
+        let key = self
+            .query
+            .group_by
+            .iter()
+            .map(|&k| row.key(k))
+            .collect::<Vec<_>>();
+        let query = self.query;
+        let accumulators = self.groups.entry(key).or_insert_with(|| {
+            query
+                .aggregates
+                .iter()
+                .map(|a| match a {
+                    Aggregate::Count => Accumulator::Count(0),
+                    Aggregate::Sum(_) => Accumulator::Sum(0.0),
+                    Aggregate::Distinct(_) => Accumulator::Distinct(FxHashSet::default()),
+                    Aggregate::Min(_) => Accumulator::Min(None),
+                    Aggregate::Max(_) => Accumulator::Max(None),
+                })
+                .collect()
+        });
+        for (acc, aggregate) in accumulators.iter_mut().zip(query.aggregates.iter()) {
+            match (acc, *aggregate) {
+                (Accumulator::Count(n), _) => *n += 1,
+                (Accumulator::Sum(sum), Aggregate::Sum(field)) => *sum += row.number(field),
+                (Accumulator::Distinct(set), Aggregate::Distinct(field)) => {
+                    set.insert(row.distinct(field));
+                }
+                (Accumulator::Min(min), Aggregate::Min(field)) => {
+                    let v = row.number(field);
+                    *min = Some(min.map_or(v, |m| m.min(v)));
+                }
+                (Accumulator::Max(max), Aggregate::Max(field)) => {
+                    let v = row.number(field);
+                    *max = Some(max.map_or(v, |m| m.max(v)));
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        self.mnames
+            .entry(record.mid)
+            .or_insert_with(|| record.mname.clone());
+        self.snames
+            .entry(record.sid)
+            .or_insert_with(|| record.sname.clone());
+    }
+
+    /// Returns the aggregates of each group.
+    pub fn finish(self) -> QueryResult {
+        let mut columns = Vec::new();
+        for key in self.query.group_by.iter() {
+            columns.extend(key.columns().iter().map(|&c| c.to_owned()));
+        }
+        columns.extend(self.query.aggregates.iter().map(|a| a.to_string()));
+
+        let mut rows = Vec::with_capacity(self.groups.len());
+        for (key, accumulators) in self.groups {
+            let mut row = Vec::with_capacity(columns.len());
+            for (part, group_key) in key.into_iter().zip(self.query.group_by.iter()) {
+                match (part, group_key) {
+                    (KeyPart::Int(id), GroupKey::Material) => {
+                        row.push(id.to_string());
+                        row.push(self.mnames[&(id as u32)].clone());
+                    }
+                    (KeyPart::Int(id), GroupKey::Store) => {
+                        row.push(id.to_string());
+                        row.push(self.snames[&(id as u32)].clone());
+                    }
+                    (KeyPart::Int(n), _) => row.push(n.to_string()),
+                    (KeyPart::Text(s), _) => row.push(s),
+                    (KeyPart::Date(dt), GroupKey::Month) => {
+                        row.push(dt.map_or_else(String::new, |d| d.format("%Y-%m").to_string()))
+                    }
+                    (KeyPart::Date(dt), GroupKey::Year) => {
+                        row.push(dt.map_or_else(String::new, |d| d.format("%Y").to_string()))
+                    }
+                    (KeyPart::Date(dt), _) => {
+                        row.push(dt.map_or_else(String::new, |d| d.format("%Y-%m-%d").to_string()))
+                    }
+                }
+            }
+            for acc in accumulators {
+                row.push(match acc {
+                    Accumulator::Count(n) => n.to_string(),
+                    Accumulator::Sum(sum) => sum.to_string(),
+                    Accumulator::Distinct(set) => set.len().to_string(),
+                    Accumulator::Min(v) | Accumulator::Max(v) => {
+                        v.map_or_else(String::new, |v| v.to_string())
+                    }
+                });
+            }
+            rows.push(row);
+        }
+
+        QueryResult { columns, rows }
+    }
+}
+
+/// A record with its derived fields.
+struct Row<'r> {
+    record: &'r Record,
+    brand: String,
+    market: String,
+}
+
+impl<'r> Row<'r> {
+    fn number(&self, field: Field) -> f64 {
+        match field {
+            Field::Mid => self.record.mid as f64,
+            Field::Sid => self.record.sid as f64,
+            Field::Wid => self.record.wid as f64,
+            Field::Qt => self.record.qt,
+            Field::At => self.record.at,
+            _ => 0.0,
+        }
+    }
+
+    fn text(&self, field: Field) -> &str {
+        match field {
+            Field::Mname => &self.record.mname,
+            Field::Sname => &self.record.sname,
+            Field::Brand => &self.brand,
+            Field::Market => &self.market,
+            _ => "",
+        }
+    }
+
+    fn key(&self, key: GroupKey) -> KeyPart {
+        let dt = self.record.dt;
+        match key {
+            GroupKey::Material => KeyPart::Int(self.record.mid as u64),
+            GroupKey::Store => KeyPart::Int(self.record.sid as u64),
+            GroupKey::Warehouse => KeyPart::Int(self.record.wid as u64),
+            GroupKey::Brand => KeyPart::Text(self.brand.clone()),
+            GroupKey::Market => KeyPart::Text(self.market.clone()),
+            GroupKey::Day => KeyPart::Date(dt),
+            GroupKey::Week => KeyPart::Date(
+                dt.map(|d| d - Duration::days(d.weekday().num_days_from_monday() as i64)),
+            ),
+            GroupKey::Month => KeyPart::Date(dt.and_then(|d| d.with_day(1))),
+            GroupKey::Year => KeyPart::Date(dt.and_then(|d| d.with_ordinal(1))),
+        }
+    }
+
+    fn distinct(&self, field: Field) -> KeyPart {
+        match field {
+            Field::Mid | Field::Sid | Field::Wid => KeyPart::Int(self.number(field) as u64),
+            Field::Qt | Field::At => KeyPart::Int(self.number(field).to_bits()),
+            Field::Dt => KeyPart::Date(self.record.dt),
+            _ => KeyPart::Text(self.text(field).to_owned()),
+        }
+    }
+
+    fn matches(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Cmp(field, op, value) => self.compare(*field, *op, value),
+            Expr::In(field, values) => values.iter().any(|v| self.compare(*field, Op::Eq, v)),
+            Expr::And(a, b) => self.matches(a) && self.matches(b),
+            Expr::Or(a, b) => self.matches(a) || self.matches(b),
+            Expr::Not(e) => !self.matches(e),
+        }
+    }
+
+    fn compare(&self, field: Field, op: Op, value: &Value) -> bool {
+        use std::cmp::Ordering;
+
+        let ord = match value {
+            Value::Number(v) => self.number(field).partial_cmp(v),
+            Value::Date(v) => self.record.dt.map(|dt| dt.cmp(v)),
+            Value::Text(v) => {
+                let text = self.text(field);
+                if op == Op::Contains {
+                    return text.contains(v.as_str());
+                }
+                Some(text.cmp(v.as_str()))
+            }
+        };
+        match (ord, op) {
+            (None, _) => false,
+            (Some(ord), Op::Eq) => ord == Ordering::Equal,
+            (Some(ord), Op::Ne) => ord != Ordering::Equal,
+            (Some(ord), Op::Lt) => ord == Ordering::Less,
+            (Some(ord), Op::Le) => ord != Ordering::Greater,
+            (Some(ord), Op::Gt) => ord == Ordering::Greater,
+            (Some(ord), Op::Ge) => ord != Ordering::Less,
+            (Some(_), Op::Contains) => false,
+        }
+    }
+}
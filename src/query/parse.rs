@@ -0,0 +1,496 @@
+//! Parsing functions of the query language, mainly [`parse_filter`], [`parse_group_by`] and
+//! [`parse_aggregates`].
+use super::{Aggregate, Expr, Field, GroupKey, Op, Value};
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+
+use std::borrow::Cow;
+
+/// A token of a filter expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A bare word, such as a field name, a keyword or an unquoted value.
+    Word(String),
+    /// A quoted string.
+    Quoted(String),
+    Op(Op),
+    LParen,
+    RParen,
+    Comma,
+}
+
+/// Parses a filter expression, such as `wid = 11751 and (qt > 10 or brand = 太二)`.
+///
+/// Conditions compare a field to a value with `=`, `!=`, `<`, `<=`, `>`, `>=`, `~` (contains)
+/// or `in (..)`, and are combined with `and`, `or`, `not` and parentheses. Dates are written as
+/// `yyyy-mm-dd` or `yyyy/mm/dd`, and values containing spaces or operators are quoted with `"`.
+///
+/// # Errors
+///
+/// Returns an error if the expression is malformed, refers to an unknown field, or compares a
+/// field to a value of another type.
+pub fn parse_filter(src: &str) -> Result<Expr> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser {
+        src,
+        tokens,
+        pos: 0,
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(parser.error(format!("unexpected `{}`", describe(token)))),
+    }
+}
+
+/// Parses a comma separated list of group keys, such as `material,month`.
+pub fn parse_group_by(src: &str) -> Result<Vec<GroupKey>> {
+    src.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.to_lowercase().as_str() {
+            "material" | "mid" => Ok(GroupKey::Material),
+            "store" | "sid" => Ok(GroupKey::Store),
+            "warehouse" | "wid" => Ok(GroupKey::Warehouse),
+            "brand" => Ok(GroupKey::Brand),
+            "market" => Ok(GroupKey::Market),
+            "day" | "date" | "dt" => Ok(GroupKey::Day),
+            "week" => Ok(GroupKey::Week),
+            "month" => Ok(GroupKey::Month),
+            "year" => Ok(GroupKey::Year),
+            _ => Err(invalid(format!(
+                "unknown group key `{}`, expected one of material, store, warehouse, brand, \
+                 market, day, week, month or year",
+                s
+            ))),
+        })
+        .collect()
+}
+
+/// Parses a comma separated list of aggregates, such as `sum(qt),count,distinct(sid)`.
+pub fn parse_aggregates(src: &str) -> Result<Vec<Aggregate>> {
+    let mut aggregates = Vec::new();
+    for s in split_top_level(src) {
+        let s = s.trim();
+        if s.is_empty() {
+            continue;
+        }
+        if s.eq_ignore_ascii_case("count") {
+            aggregates.push(Aggregate::Count);
+            continue;
+        }
+
+        let (func, arg) = match (s.find('('), s.strip_suffix(')')) {
+            (Some(i), Some(rest)) => (s[..i].trim().to_lowercase(), rest[i + 1..].trim()),
+            _ => return Err(invalid(format!("invalid aggregate `{}`", s))),
+        };
+        let field = parse_field(arg)?;
+        let numeric = || {
+            if field.is_numeric() {
+                Ok(field)
+            } else {
+                Err(invalid(format!("`{}` of `{}` is not a number", func, arg)))
+            }
+        };
+        let aggregate = match func.as_str() {
+            "sum" => Aggregate::Sum(numeric()?),
+            "min" => Aggregate::Min(numeric()?),
+            "max" => Aggregate::Max(numeric()?),
+            "distinct" => Aggregate::Distinct(field),
+            _ => {
+                return Err(invalid(format!(
+                    "unknown aggregate `{}`, expected one of sum, count, distinct, min or max",
+                    func
+                )))
+            }
+        };
+        aggregates.push(aggregate);
+    }
+    Ok(aggregates)
+}
+
+/// Parses the name of a [`Field`].
+pub fn parse_field(s: &str) -> Result<Field> {
+    match s.trim().to_lowercase().as_str() {
+        "mid" | "material" => Ok(Field::Mid),
+        "sid" | "store" => Ok(Field::Sid),
+        "wid" | "warehouse" => Ok(Field::Wid),
+        "mname" => Ok(Field::Mname),
+        "sname" => Ok(Field::Sname),
+        "qt" | "quantity" => Ok(Field::Qt),
+        "at" | "amount" => Ok(Field::At),
+        "dt" | "date" => Ok(Field::Dt),
+        "brand" => Ok(Field::Brand),
+        "market" => Ok(Field::Market),
+        _ => Err(invalid(format!(
+            "unknown field `{}`, expected one of mid, sid, wid, mname, sname, qt, at, dt, brand \
+             or market",
+            s.trim()
+        ))),
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.is_keyword("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+        while self.is_keyword("and") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.is_keyword("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr> {
+        let field = match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                return match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(self.error("missing `)`".to_owned())),
+                };
+            }
+            Some(Token::Word(w)) => parse_field(&w).map_err(|e| self.error(e.to_string()))?,
+            Some(token) => {
+                return Err(self.error(format!("expected a field, found `{}`", describe(&token))))
+            }
+            None => return Err(self.error("unexpected end of expression".to_owned())),
+        };
+
+        if self.is_keyword("in") {
+            self.pos += 1;
+            if self.next() != Some(Token::LParen) {
+                return Err(self.error("expected `(` after `in`".to_owned()));
+            }
+            let mut values = Vec::new();
+            loop {
+                values.push(self.parse_value(field, Op::Eq)?);
+                match self.next() {
+                    Some(Token::Comma) => continue,
+                    Some(Token::RParen) => break,
+                    _ => return Err(self.error("expected `,` or `)` in list".to_owned())),
+                }
+            }
+            return Ok(Expr::In(field, values));
+        }
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(self.error(format!("expected an operator after `{}`", field.name()))),
+        };
+        if op == Op::Contains && !field.is_text() {
+            return Err(self.error(format!("`~` cannot be applied to `{}`", field.name())));
+        }
+        if field.is_text() && !matches!(op, Op::Eq | Op::Ne | Op::Contains) {
+            return Err(self.error(format!(
+                "`{}` can only be compared with `=`, `!=` or `~`",
+                field.name()
+            )));
+        }
+        let value = self.parse_value(field, op)?;
+        Ok(Expr::Cmp(field, op, value))
+    }
+
+    fn parse_value(&mut self, field: Field, op: Op) -> Result<Value> {
+        let s = match self.next() {
+            Some(Token::Word(s)) | Some(Token::Quoted(s)) => s,
+            _ => return Err(self.error(format!("expected a value for `{}`", field.name()))),
+        };
+        if field == Field::Dt {
+            NaiveDate::parse_from_str(&s, "%Y-%m-%d")
+                .or_else(|_| NaiveDate::parse_from_str(&s, "%Y/%m/%d"))
+                .map(Value::Date)
+                .map_err(|_| self.error(format!("`{}` is not a date", s)))
+        } else if field.is_numeric() {
+            s.parse::<f64>()
+                .map(Value::Number)
+                .map_err(|_| self.error(format!("`{}` is not a number", s)))
+        } else if op == Op::Contains {
+            Ok(Value::Text(s))
+        } else {
+            Ok(Value::Text(field.normalize(&s)))
+        }
+    }
+
+    fn error(&self, msg: String) -> Error {
+        invalid(format!("invalid filter `{}`: {}", self.src, msg))
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    _ => Token::Comma,
+                });
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        // A doubled quote stands for a quote.
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            s.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => {
+                            return Err(invalid(format!(
+                                "invalid filter `{}`: unterminated string",
+                                src
+                            )))
+                        }
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                chars.next();
+                // `==` is accepted as well as `=`.
+                let eq = c != '~' && chars.peek() == Some(&'=');
+                if eq {
+                    chars.next();
+                }
+                let op = match (c, eq) {
+                    ('=', _) => Op::Eq,
+                    ('~', _) => Op::Contains,
+                    ('!', true) => Op::Ne,
+                    ('<', true) => Op::Le,
+                    ('<', false) => Op::Lt,
+                    ('>', true) => Op::Ge,
+                    ('>', false) => Op::Gt,
+                    _ => return Err(invalid(format!("invalid filter `{}`: unexpected `!`", src))),
+                };
+                tokens.push(Token::Op(op));
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()\",=!<>~".contains(c) {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(s));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Splits a list at the commas outside parentheses.
+fn split_top_level(src: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in src.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&src[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&src[start..]);
+    parts
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(s) => s.clone(),
+        Token::Quoted(s) => format!("\"{}\"", s),
+        Token::Op(op) => op.as_str().to_owned(),
+        Token::LParen => "(".to_owned(),
+        Token::RParen => ")".to_owned(),
+        Token::Comma => ",".to_owned(),
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::Other(Cow::Owned(msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::st::aggregate::StoreType;
+
+    fn cmp(field: Field, op: Op, value: Value) -> Expr {
+        Expr::Cmp(field, op, value)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = parse_filter("wid = 11751 and qt > 10 or not mid <= 3").unwrap();
+        let expected = Expr::Or(
+            Box::new(Expr::And(
+                Box::new(cmp(Field::Wid, Op::Eq, Value::Number(11751.0))),
+                Box::new(cmp(Field::Qt, Op::Gt, Value::Number(10.0))),
+            )),
+            Box::new(Expr::Not(Box::new(cmp(
+                Field::Mid,
+                Op::Le,
+                Value::Number(3.0),
+            )))),
+        );
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn parses_parentheses_lists_and_quoted_values() {
+        let expr =
+            parse_filter("(sid in (1, 2) OR sname ~ \"a \"\"b\"\"\") and brand == jmj").unwrap();
+        let expected = Expr::And(
+            Box::new(Expr::Or(
+                Box::new(Expr::In(
+                    Field::Sid,
+                    vec![Value::Number(1.0), Value::Number(2.0)],
+                )),
+                Box::new(cmp(
+                    Field::Sname,
+                    Op::Contains,
+                    Value::Text("a \"b\"".into()),
+                )),
+            )),
+            Box::new(cmp(
+                Field::Brand,
+                Op::Eq,
+                Value::Text(StoreType::Jmj.to_string()),
+            )),
+        );
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn parses_dates_and_operators() {
+        let date = Value::Date(NaiveDate::from_ymd_opt(2021, 3, 1).unwrap());
+        assert_eq!(
+            parse_filter("dt>=2021-03-01").unwrap(),
+            cmp(Field::Dt, Op::Ge, date.clone())
+        );
+        assert_eq!(
+            parse_filter("date != 2021/03/01").unwrap(),
+            cmp(Field::Dt, Op::Ne, date)
+        );
+        assert_eq!(
+            parse_filter("at<1.5").unwrap(),
+            cmp(Field::At, Op::Lt, Value::Number(1.5))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for src in [
+            "",
+            "qt",
+            "qt >",
+            "qt > abc",
+            "dt = 2021-13-01",
+            "foo = 1",
+            "(qt > 1",
+            "qt > 1 qt",
+            "sid in 1, 2",
+            "sid in (1 2)",
+            "qt ~ 1",
+            "mname > a",
+            "qt ! 1",
+            "mname = \"a",
+        ]
+        .iter()
+        {
+            assert!(parse_filter(src).is_err(), "`{}` should be rejected", src);
+        }
+    }
+
+    #[test]
+    fn parses_group_keys() {
+        assert_eq!(
+            parse_group_by(" Material, month,,sid ").unwrap(),
+            vec![GroupKey::Material, GroupKey::Month, GroupKey::Store]
+        );
+        assert!(parse_group_by("material,quarter").is_err());
+    }
+
+    #[test]
+    fn parses_aggregates() {
+        assert_eq!(
+            parse_aggregates("sum(qt), COUNT, distinct( sid ),max(at)").unwrap(),
+            vec![
+                Aggregate::Sum(Field::Qt),
+                Aggregate::Count,
+                Aggregate::Distinct(Field::Sid),
+                Aggregate::Max(Field::At),
+            ]
+        );
+        assert!(parse_aggregates("sum(mname)").is_err());
+        assert!(parse_aggregates("avg(qt)").is_err());
+        assert!(parse_aggregates("sum qt").is_err());
+    }
+
+    #[test]
+    fn query_defaults_to_count_and_sums() {
+        let query = Query::parse(Some(" "), None, None).unwrap();
+        assert_eq!(query.filter, None);
+        assert_eq!(
+            query.aggregates,
+            vec![
+                Aggregate::Count,
+                Aggregate::Sum(Field::Qt),
+                Aggregate::Sum(Field::At)
+            ]
+        );
+    }
+}
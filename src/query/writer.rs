@@ -0,0 +1,30 @@
+//! Writers for writing out query results.
+use super::QueryResult;
+use crate::{CsvWriter, Result};
+
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::LineWriter;
+
+/// Writes a query result to `query.csv`.
+pub fn write_query_result(result: &QueryResult, out_dir: &str) -> Result<()> {
+    // Set up open options.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(format!("{}/query.csv", out_dir))?;
+
+    // Write UTF-8 BOM.
+    file.write_all("\u{feff}".as_bytes())?;
+
+    // Names may contain commas, so fields are quoted when necessary.
+    let mut wtr = CsvWriter::new(LineWriter::new(file));
+    wtr.write_row(&result.columns)?;
+    for row in result.rows.iter() {
+        wtr.write_row(row)?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
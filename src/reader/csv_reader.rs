@@ -1,165 +1,550 @@
-use std::mem;
+use crate::convert::{decode, detect, EncodeType};
+use crate::{Error, ErrorKind, Result};
 
-static STRING_INITIAL_CAPACITY: usize = 64usize;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::slice;
+
+static STRING_INITIAL_CAPACITY: usize = 256usize;
 
 enum ParseState {
-  Neutral,
-  InField,
-  InQuotedField,
-  EncounteredQuoteInQuotedField,
-  EndOfRow,
+    Neutral,
+    InField,
+    InQuotedField,
+    EncounteredQuoteInQuotedField,
 }
 
-/// A simple CSV reader.
-pub struct CsvReader {
-  state: ParseState,
-  row_data: Vec<String>,
-  column_buffer: String,
-  options: CsvReaderOptions,
+/// A streaming CSV reader.
+///
+/// `CsvReader` reads records from any [`BufRead`], decoding each physical line with the given
+/// encoding. With [`EncodeType::Auto`], the encoding is detected from the first buffered bytes
+/// of the input. A leading byte order mark is skipped. Quoted fields may span multiple physical
+/// lines. Field buffers are reused between records, so reading a record does not allocate once
+/// the buffers have grown large enough.
+pub struct CsvReader<R> {
+    rdr: R,
+    encoding: EncodeType,
+    /// Whether the encoding was detected as UTF-8 from the first bytes alone, in which case
+    /// the first line that is not valid UTF-8 switches it to GB18030, see [`detect`].
+    may_fall_back: bool,
+    options: CsvReaderOptions,
+    buf: Vec<u8>,
+    line: String,
+    row: CsvRow,
+    pos: Position,
 }
 
 /// CSV reader options.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CsvReaderOptions {
-  pub delimiter: char,
-  pub text_enclosure: char,
+    pub delimiter: char,
+    pub text_enclosure: char,
+    /// Whether the first record is a header row.
+    pub has_header: bool,
+    pub terminator: Terminator,
 }
 
 impl Default for CsvReaderOptions {
-  fn default() -> CsvReaderOptions {
-    CsvReaderOptions {
-      delimiter: ',',
-      text_enclosure: '"',
+    fn default() -> CsvReaderOptions {
+        CsvReaderOptions {
+            delimiter: ',',
+            text_enclosure: '"',
+            has_header: true,
+            terminator: Terminator::CRLF,
+        }
     }
-  }
 }
 
-impl CsvReader {
-  pub fn new() -> CsvReader {
-    CsvReader::with_options(Default::default())
-  }
-
-  pub fn with_options(options: CsvReaderOptions) -> CsvReader {
-    CsvReader {
-      state: ParseState::Neutral,
-      row_data: Vec::new(),
-      column_buffer: String::with_capacity(STRING_INITIAL_CAPACITY),
-      options: options,
-    }
-  }
-
-  fn new_column(&mut self) {
-    let column_data = mem::replace(
-      &mut self.column_buffer,
-      String::with_capacity(STRING_INITIAL_CAPACITY),
-    );
-    self.row_data.push(column_data);
-    self.state = ParseState::Neutral;
-  }
-
-  pub fn read_line(&mut self, str: &str) -> Option<Vec<String>> {
-    let line = str.to_owned();
-    let delimiter = self.options.delimiter;
-    let text_enclosure = self.options.text_enclosure;
-
-    for c in line.chars() {
-      match self.state {
-        ParseState::Neutral => {
-          match c {
-            _ if c == text_enclosure => {
-              // Start of quoted field
-              self.state = ParseState::InQuotedField;
-            }
-            _ if c == delimiter => {
-              // Empty field
-              self.row_data.push(String::new());
-            }
-            '\n' => {
-              // Newline outside of quoted field
-              self.new_column();
-              self.state = ParseState::EndOfRow;
-            }
-            '\r' => { // Return outside of quoted field. Eat it and keep going
-            }
-            _ => {
-              // Anything else is unquoted data
-              self.column_buffer.push(c);
-              self.state = ParseState::InField;
-            }
-          }
+/// Line terminators of CSV records.
+///
+/// Both `CRLF` and `LF` accept either `\r\n` or `\n` when reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminator {
+    CRLF,
+    LF,
+    CR,
+}
+
+impl Terminator {
+    /// Returns the character that ends a record.
+    pub fn as_char(self) -> char {
+        match self {
+            Terminator::CRLF | Terminator::LF => '\n',
+            Terminator::CR => '\r',
         }
-        ParseState::InQuotedField => {
-          match c {
-            _ if c == text_enclosure => {
-              self.state = ParseState::EncounteredQuoteInQuotedField;
-            }
-            _ => {
-              // Anything else is data
-              self.column_buffer.push(c);
-            }
-          }
+    }
+
+    /// Returns the string that should be written after a record.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Terminator::CRLF => "\r\n",
+            Terminator::LF => "\n",
+            Terminator::CR => "\r",
         }
-        ParseState::InField => {
-          match c {
-            _ if c == delimiter => {
-              self.new_column();
+    }
+}
+
+/// The position of a record in a CSV source.
+///
+/// Byte offsets are counted on the raw (undecoded) input. Lines and records are counted
+/// from 1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "byte: {}, line: {}, record: {}",
+            self.byte, self.line, self.record
+        )
+    }
+}
+
+/// A single CSV record, whose fields borrow from a shared buffer.
+#[derive(Debug, Clone, Default)]
+pub struct CsvRow {
+    data: String,
+    bounds: Vec<usize>,
+    pos: Position,
+}
+
+impl CsvRow {
+    /// Returns the number of fields in this record.
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /// Returns `true` if this record has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    /// Returns the field at the given index.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        let end = *self.bounds.get(i)?;
+        let start = if i == 0 { 0 } else { self.bounds[i - 1] };
+        Some(&self.data[start..end])
+    }
+
+    /// Returns an iterator over the fields of this record.
+    pub fn iter(&self) -> CsvRowIter<'_> {
+        CsvRowIter {
+            row: self,
+            bounds: self.bounds.iter(),
+            start: 0,
+        }
+    }
+
+    /// Returns the position of the start of this record.
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.bounds.clear();
+    }
+
+    fn end_field(&mut self) {
+        self.bounds.push(self.data.len());
+    }
+}
+
+/// An iterator over the fields of a [`CsvRow`].
+pub struct CsvRowIter<'a> {
+    row: &'a CsvRow,
+    bounds: slice::Iter<'a, usize>,
+    start: usize,
+}
+
+impl<'a> Iterator for CsvRowIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let end = *self.bounds.next()?;
+        let field = &self.row.data[self.start..end];
+        self.start = end;
+        Some(field)
+    }
+}
+
+impl<'a> IntoIterator for &'a CsvRow {
+    type Item = &'a str;
+    type IntoIter = CsvRowIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<R: BufRead> CsvReader<R> {
+    /// Creates a `CsvReader` that reads UTF-8 encoded, comma separated records.
+    pub fn new(rdr: R) -> CsvReader<R> {
+        CsvReader::with_options(rdr, EncodeType::UTF8, Default::default())
+    }
+
+    /// Creates a `CsvReader` with the given encoding and options.
+    pub fn with_options(rdr: R, encoding: EncodeType, options: CsvReaderOptions) -> CsvReader<R> {
+        CsvReader {
+            rdr,
+            encoding,
+            may_fall_back: false,
+            options,
+            buf: Vec::with_capacity(STRING_INITIAL_CAPACITY),
+            line: String::with_capacity(STRING_INITIAL_CAPACITY),
+            row: CsvRow {
+                data: String::with_capacity(STRING_INITIAL_CAPACITY),
+                bounds: Vec::new(),
+                pos: Position::default(),
+            },
+            pos: Position {
+                byte: 0,
+                line: 1,
+                record: 1,
+            },
+        }
+    }
+
+    /// Returns the position of the next record to be read.
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.rdr
+    }
+
+    /// Unwraps this `CsvReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.rdr
+    }
+
+    /// Reads the header row, i.e. the first record of the input.
+    ///
+    /// # Errors
+    ///
+    /// Besides the errors of [`read_row`](CsvReader::read_row), returns an error variant of
+    /// [`ErrorKind::Csv`] if the input is empty.
+    pub fn read_header(&mut self) -> Result<&CsvRow> {
+        if self.read_row()?.is_none() {
+            return Err(Error::new(ErrorKind::Csv(
+                "missing header row".to_owned(),
+                self.pos,
+            )));
+        }
+        Ok(&self.row)
+    }
+
+    /// Reads the next record, returning `None` if the end of input is reached.
+    ///
+    /// The returned record borrows the internal buffers of this reader, which are overwritten by
+    /// the next call to this method.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error variant of [`ErrorKind::Csv`] if the input cannot be decoded or the
+    /// input ends inside a quoted field.
+    pub fn read_row(&mut self) -> Result<Option<&CsvRow>> {
+        let delimiter = self.options.delimiter;
+        let text_enclosure = self.options.text_enclosure;
+        let terminator = self.options.terminator.as_char();
+
+        if self.pos.byte == 0 {
+            self.detect_encoding()?;
+        }
+
+        self.row.clear();
+        self.row.pos = self.pos;
+        let mut state = ParseState::Neutral;
+
+        loop {
+            self.buf.clear();
+            let bytes_read = self.read_line(terminator as u8)?;
+            if bytes_read == 0 {
+                return match state {
+                    // Nothing has been read since the last record.
+                    ParseState::Neutral if self.row.is_empty() => Ok(None),
+                    ParseState::InQuotedField => Err(Error::new(ErrorKind::Csv(
+                        "unexpected end of input inside a quoted field".to_owned(),
+                        self.row.pos,
+                    ))),
+                    _ => {
+                        self.row.end_field();
+                        self.pos.record += 1;
+                        Ok(Some(&self.row))
+                    }
+                };
             }
-            '\n' => {
-              self.new_column();
-              self.state = ParseState::EndOfRow;
+
+            if let Err(e) = decode(&self.buf, self.encoding, &mut self.line) {
+                if !self.may_fall_back {
+                    return Err(Error::new(ErrorKind::Csv(e.to_string(), self.pos)));
+                }
+                self.may_fall_back = false;
+                self.encoding = EncodeType::GB18030;
+                decode(&self.buf, self.encoding, &mut self.line)
+                    .map_err(|e| Error::new(ErrorKind::Csv(e.to_string(), self.pos)))?;
             }
-            '\r' => {}
-            _ => {
-              // Anything else is data
-              self.column_buffer.push(c);
+            self.pos.byte += bytes_read as u64;
+            self.pos.line += 1;
+
+            for c in self.line.chars() {
+                match state {
+                    ParseState::Neutral => match c {
+                        _ if c == text_enclosure => state = ParseState::InQuotedField,
+                        _ if c == delimiter => self.row.end_field(),
+                        _ if c == terminator => {
+                            self.row.end_field();
+                            self.pos.record += 1;
+                            return Ok(Some(&self.row));
+                        }
+                        // Line breaks other than the terminator outside of quoted field.
+                        // Eat it and keep going.
+                        '\r' | '\n' => (),
+                        _ => {
+                            self.row.data.push(c);
+                            state = ParseState::InField;
+                        }
+                    },
+                    ParseState::InField => match c {
+                        _ if c == delimiter => {
+                            self.row.end_field();
+                            state = ParseState::Neutral;
+                        }
+                        _ if c == terminator => {
+                            self.row.end_field();
+                            self.pos.record += 1;
+                            return Ok(Some(&self.row));
+                        }
+                        '\r' | '\n' => (),
+                        _ => self.row.data.push(c),
+                    },
+                    // Anything inside a quoted field is data, including newlines.
+                    ParseState::InQuotedField => match c {
+                        _ if c == text_enclosure => {
+                            state = ParseState::EncounteredQuoteInQuotedField;
+                        }
+                        _ => self.row.data.push(c),
+                    },
+                    ParseState::EncounteredQuoteInQuotedField => match c {
+                        _ if c == text_enclosure => {
+                            // 2nd quote in a row inside quoted field - escaped quote
+                            self.row.data.push(c);
+                            state = ParseState::InQuotedField;
+                        }
+                        _ if c == delimiter => {
+                            self.row.end_field();
+                            state = ParseState::Neutral;
+                        }
+                        _ if c == terminator => {
+                            self.row.end_field();
+                            self.pos.record += 1;
+                            return Ok(Some(&self.row));
+                        }
+                        '\r' | '\n' => (),
+                        _ => {
+                            // Data after quoted field, treat it as data and add to existing data
+                            self.row.data.push(c);
+                            state = ParseState::InField;
+                        }
+                    },
+                }
             }
-          }
         }
-        ParseState::EncounteredQuoteInQuotedField => {
-          match c {
-            _ if c == text_enclosure => {
-              // 2nd " in a row inside quoted field - escaped quote
-              self.column_buffer.push(c);
-              self.state = ParseState::InQuotedField;
-            }
-            _ if c == delimiter => {
-              // Field separator, end of quoted field
-              self.new_column();
+    }
+
+    /// Resolves [`EncodeType::Auto`] and skips the byte order mark of the encoding, if any.
+    fn detect_encoding(&mut self) -> Result<()> {
+        let sample = self.rdr.fill_buf()?;
+        let is_auto = self.encoding == EncodeType::Auto;
+        if is_auto {
+            self.encoding = detect(sample);
+        }
+        match self.encoding.bom() {
+            Some(bom) if sample.starts_with(bom) => {
+                self.rdr.consume(bom.len());
+                self.pos.byte += bom.len() as u64;
             }
-            '\n' => {
-              self.new_column();
-              self.state = ParseState::EndOfRow;
+            _ => self.may_fall_back = is_auto && self.encoding == EncodeType::UTF8,
+        }
+        Ok(())
+    }
+
+    /// Reads a physical line into `buf`, including its terminator.
+    ///
+    /// For UTF-16, a line only ends at a whole code unit equal to the terminator, which spans two
+    /// bytes.
+    fn read_line(&mut self, terminator: u8) -> io::Result<usize> {
+        if !self.encoding.is_utf16() {
+            return self.rdr.read_until(terminator, &mut self.buf);
+        }
+
+        let mut bytes_read = 0;
+        loop {
+            let n = self.rdr.read_until(terminator, &mut self.buf)?;
+            bytes_read += n;
+            if n == 0 || self.buf.last() != Some(&terminator) {
+                return Ok(bytes_read);
             }
-            '\r' => {}
-            _ => {
-              // Data after quoted field, treat it as data and add to existing data
-              self.column_buffer.push(c);
-              self.state = ParseState::InField;
+
+            let len = self.buf.len();
+            let is_odd = len % 2 == 1;
+            match self.encoding {
+                // The terminator is the low byte, followed by a zero high byte.
+                EncodeType::UTF16LE if is_odd => {
+                    let high = match self.rdr.fill_buf()?.first() {
+                        Some(&b) => b,
+                        None => return Ok(bytes_read),
+                    };
+                    self.rdr.consume(1);
+                    self.buf.push(high);
+                    bytes_read += 1;
+                    if high == 0 {
+                        return Ok(bytes_read);
+                    }
+                }
+                // The terminator is the low byte, preceded by a zero high byte.
+                EncodeType::UTF16BE if !is_odd && self.buf[len - 2] == 0 => {
+                    return Ok(bytes_read);
+                }
+                _ => (),
             }
-          }
         }
-        ParseState::EndOfRow => {
-          assert!(false, "Should never reach match of EndOfRow");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::encode;
+
+    fn read_all(input: &[u8], encoding: EncodeType, options: CsvReaderOptions) -> Vec<Vec<String>> {
+        let mut rdr = CsvReader::with_options(input, encoding, options);
+        let mut rows = Vec::new();
+        while let Some(row) = rdr.read_row().unwrap() {
+            rows.push(row.iter().map(|s| s.to_owned()).collect());
         }
-      }
-    } // end for
+        rows
+    }
+
+    fn encoded(s: &str, encoding: EncodeType) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode(s, encoding, &mut buf).unwrap();
+        let mut bytes = encoding.bom().unwrap().to_vec();
+        bytes.extend(buf);
+        bytes
+    }
+
+    #[test]
+    fn quoted_fields_span_lines() {
+        let input = "a,\"b\r\nc\",d\r\ne,\"f \"\"g\"\"\",h\r\n";
+        let rows = read_all(input.as_bytes(), EncodeType::UTF8, Default::default());
+        assert_eq!(
+            rows,
+            vec![vec!["a", "b\r\nc", "d"], vec!["e", "f \"g\"", "h"]]
+        );
+    }
+
+    #[test]
+    fn positions_count_physical_lines() {
+        let input = "a,\"b\nc\"\nd,e\n";
+        let mut rdr = CsvReader::new(input.as_bytes());
+        let first = rdr.read_row().unwrap().unwrap().position();
+        assert_eq!((first.byte, first.line, first.record), (0, 1, 1));
+        let second = rdr.read_row().unwrap().unwrap().position();
+        assert_eq!((second.byte, second.line, second.record), (8, 3, 2));
+    }
+
+    #[test]
+    fn lf_and_crlf_terminators_are_mixed() {
+        let input = "a,b\nc,d\r\ne,f";
+        let rows = read_all(input.as_bytes(), EncodeType::UTF8, Default::default());
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["c", "d"], vec!["e", "f"]]);
+    }
 
-    // The parser might have left some data in the column_buffer if it never encountered a newline
-    // Add whatever was collected to the current row
-    if !self.column_buffer.is_empty() {
-      self.new_column();
+    #[test]
+    fn cr_terminator() {
+        let options = CsvReaderOptions {
+            terminator: Terminator::CR,
+            ..Default::default()
+        };
+        let rows = read_all(b"a,b\rc,\"d\re\"\r", EncodeType::UTF8, options);
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["c", "d\re"]]);
     }
 
-    // Move row_data out of CsvReader
-    let row_data = mem::take(&mut self.row_data);
+    #[test]
+    fn utf16le_lines_end_at_whole_code_units() {
+        // The low byte of `Ċ` (U+010A) equals the line feed.
+        let input = encoded("Ċ,b\r\nc,d\r\n", EncodeType::UTF16LE);
+        let rows = read_all(&input, EncodeType::UTF16LE, Default::default());
+        assert_eq!(rows, vec![vec!["Ċ", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn utf16be_lines_end_at_whole_code_units() {
+        // The low byte of `上` (U+4E0A) equals the line feed.
+        let input = encoded("上,b\r\nc,d\r\n", EncodeType::UTF16BE);
+        let rows = read_all(&input, EncodeType::UTF16BE, Default::default());
+        assert_eq!(rows, vec![vec!["上", "b"], vec!["c", "d"]]);
+    }
 
-    // Reset state
-    self.state = ParseState::Neutral;
+    #[test]
+    fn auto_detects_utf16_from_bom() {
+        let input = encoded("名称,b\r\n", EncodeType::UTF16LE);
+        let rows = read_all(&input, EncodeType::Auto, Default::default());
+        assert_eq!(rows, vec![vec!["名称", "b"]]);
+    }
+
+    #[test]
+    fn utf8_bom_is_skipped() {
+        let input = encoded("a,b\r\n", EncodeType::UTF8);
+        for &encoding in [EncodeType::UTF8, EncodeType::Auto].iter() {
+            let mut rdr = CsvReader::with_options(&input[..], encoding, Default::default());
+            assert_eq!(rdr.read_header().unwrap().get(0), Some("a"));
+            assert_eq!(rdr.position().byte, input.len() as u64);
+        }
+    }
+
+    #[test]
+    fn auto_falls_back_to_gb18030_after_the_first_bytes() {
+        // Only the first buffered bytes, which are ASCII, are used to detect the encoding.
+        let input = b"a,b\r\nc,d\r\n\xD6\xD0,\xCE\xC4\r\n";
+        let mut rdr = CsvReader::with_options(
+            io::BufReader::with_capacity(8, &input[..]),
+            EncodeType::Auto,
+            Default::default(),
+        );
+        let mut rows = Vec::new();
+        while let Some(row) = rdr.read_row().unwrap() {
+            rows.push(row.iter().map(|s| s.to_owned()).collect::<Vec<_>>());
+        }
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["c", "d"], vec!["中", "文"]]);
+
+        // An explicit encoding does not fall back.
+        let mut rdr = CsvReader::new(&input[..]);
+        rdr.read_row().unwrap();
+        rdr.read_row().unwrap();
+        assert!(rdr.read_row().is_err());
+    }
+
+    #[test]
+    fn unterminated_quote_is_an_error() {
+        let mut rdr = CsvReader::new(&b"a,\"b\nc"[..]);
+        assert!(matches!(
+            rdr.read_row().unwrap_err().kind(),
+            ErrorKind::Csv(_, _)
+        ));
+    }
 
-    if row_data.is_empty() {
-      return None;
-    } else {
-      return Some(row_data);
+    #[test]
+    fn empty_input_has_no_header() {
+        let mut rdr = CsvReader::new(&b""[..]);
+        assert!(rdr.read_header().is_err());
     }
-  }
 }
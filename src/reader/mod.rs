@@ -1,2 +1,3 @@
 mod csv_reader;
-pub use self::csv_reader::{CsvReader, CsvReaderOptions};
+mod sniffer;
+pub use self::csv_reader::{CsvReader, CsvReaderOptions, CsvRow, CsvRowIter, Position, Terminator};
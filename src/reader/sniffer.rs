@@ -0,0 +1,294 @@
+use super::csv_reader::{CsvReader, CsvReaderOptions, Terminator};
+use crate::convert::{decode, detect, EncodeType};
+use crate::Result;
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// The number of bytes inspected by [`CsvReaderOptions::sniff_reader`].
+const SAMPLE_SIZE: u64 = 16 * 1024;
+
+/// The maximum number of lines inspected when sniffing.
+const SAMPLE_LINES: usize = 20;
+
+/// Delimiters that can be detected, in order of preference.
+const DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+
+/// Text enclosures that can be detected, in order of preference.
+const ENCLOSURES: [char; 2] = ['"', '\''];
+
+impl CsvReaderOptions {
+    /// Infers the dialect of CSV data from the first lines of the given sample.
+    ///
+    /// The sample should end at a line break. Options that cannot be inferred fall back to
+    /// their defaults.
+    pub fn sniff(sample: &str) -> CsvReaderOptions {
+        let default = CsvReaderOptions::default();
+        let terminator = sniff_terminator(sample);
+
+        let lines = sample
+            .split(terminator.as_char())
+            .map(|line| line.trim_end_matches(&['\r', '\n'][..]))
+            .filter(|line| !line.is_empty())
+            .take(SAMPLE_LINES)
+            .collect::<Vec<_>>();
+        let lines = &lines[..];
+
+        let text_enclosure = sniff_enclosure(lines).unwrap_or(default.text_enclosure);
+        let delimiter = sniff_delimiter(lines, text_enclosure).unwrap_or(default.delimiter);
+
+        let options = CsvReaderOptions {
+            delimiter,
+            text_enclosure,
+            has_header: default.has_header,
+            terminator,
+        };
+
+        CsvReaderOptions {
+            has_header: sniff_header(lines, options).unwrap_or(default.has_header),
+            ..options
+        }
+    }
+
+    /// Infers the dialect of CSV data from the first bytes of the given reader, and rewinds the
+    /// reader to its start afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from or seeking the reader failed, or if the sample cannot
+    /// be decoded with the given encoding.
+    pub fn sniff_reader<R: Read + Seek>(
+        rdr: &mut R,
+        encoding: EncodeType,
+    ) -> Result<CsvReaderOptions> {
+        let mut buf = Vec::new();
+        rdr.by_ref().take(SAMPLE_SIZE).read_to_end(&mut buf)?;
+        rdr.seek(SeekFrom::Start(0))?;
+
+        let is_full = buf.len() as u64 == SAMPLE_SIZE;
+        let encoding = match encoding {
+            EncodeType::Auto => detect(&buf),
+            _ => encoding,
+        };
+        if let Some(bom) = encoding.bom() {
+            if buf.starts_with(bom) {
+                buf.drain(..bom.len());
+            }
+        }
+
+        // Cut the sample at the last line break, so that no multi-byte character is truncated.
+        if is_full && encoding.is_utf16() {
+            buf.truncate(buf.len() / 2 * 2);
+            // Drop a dangling high surrogate as well.
+            let high = match encoding {
+                EncodeType::UTF16LE => buf.last(),
+                _ => buf.len().checked_sub(2).and_then(|i| buf.get(i)),
+            };
+            if let Some(0xD8..=0xDB) = high {
+                buf.truncate(buf.len() - 2);
+            }
+        } else if is_full {
+            if let Some(i) = buf.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+                buf.truncate(i + 1);
+            }
+        }
+
+        let mut sample = String::new();
+        decode(&buf, encoding, &mut sample)?;
+        if is_full && encoding.is_utf16() {
+            if let Some(i) = sample.rfind(&['\n', '\r'][..]) {
+                sample.truncate(i + 1);
+            }
+        }
+
+        Ok(CsvReaderOptions::sniff(&sample))
+    }
+}
+
+/// Picks the most frequent line terminator.
+fn sniff_terminator(sample: &str) -> Terminator {
+    let crlf = sample.matches("\r\n").count();
+    let lf = sample.matches('\n').count() - crlf;
+    let cr = sample.matches('\r').count() - crlf;
+
+    if cr > crlf && cr > lf {
+        Terminator::CR
+    } else if lf > crlf {
+        Terminator::LF
+    } else {
+        Terminator::CRLF
+    }
+}
+
+/// Picks the enclosure that most often starts or ends a line or is adjacent to a candidate
+/// delimiter.
+fn sniff_enclosure(lines: &[&str]) -> Option<char> {
+    let count = |quote: char| {
+        lines
+            .iter()
+            .map(|line| {
+                let chars = line.chars().collect::<Vec<_>>();
+                let is_boundary = |i: Option<usize>| match i.and_then(|i| chars.get(i)) {
+                    None => true,
+                    Some(c) => DELIMITERS.contains(c),
+                };
+                chars
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, &c)| {
+                        c == quote && (is_boundary(i.checked_sub(1)) || is_boundary(Some(i + 1)))
+                    })
+                    .count()
+            })
+            .sum::<usize>()
+    };
+
+    ENCLOSURES
+        .iter()
+        .map(|&quote| (quote, count(quote)))
+        .filter(|&(_, n)| n > 0)
+        .fold(None, |best: Option<(char, usize)>, (quote, n)| match best {
+            Some((_, m)) if m >= n => best,
+            _ => Some((quote, n)),
+        })
+        .map(|(quote, _)| quote)
+}
+
+/// Picks the delimiter that occurs the same number of times (outside quoted fields) on the
+/// most lines.
+fn sniff_delimiter(lines: &[&str], text_enclosure: char) -> Option<char> {
+    let mut best: Option<(char, usize, usize)> = None;
+
+    for &delimiter in DELIMITERS.iter() {
+        let counts = lines
+            .iter()
+            .map(|line| {
+                let mut in_quotes = false;
+                line.chars()
+                    .filter(|&c| {
+                        if c == text_enclosure {
+                            in_quotes = !in_quotes;
+                        }
+                        !in_quotes && c == delimiter
+                    })
+                    .count()
+            })
+            .collect::<Vec<_>>();
+
+        // Find the most common non-zero count and how many lines agree on it.
+        let mut mode = (0, 0);
+        for &n in counts.iter().filter(|&&n| n > 0) {
+            let agree = counts.iter().filter(|&&m| m == n).count();
+            if (agree, n) > mode {
+                mode = (agree, n);
+            }
+        }
+        let (agree, n) = mode;
+        if agree == 0 {
+            continue;
+        }
+
+        match best {
+            Some((_, best_agree, best_n)) if (best_agree, best_n) >= (agree, n) => (),
+            _ => best = Some((delimiter, agree, n)),
+        }
+    }
+
+    best.map(|(delimiter, _, _)| delimiter)
+}
+
+/// Guesses whether the first line is a header, by comparing the first row against the rows
+/// below it: columns whose values are numeric except in the first row vote for a header,
+/// columns that are numeric in the first row as well vote against it.
+fn sniff_header(lines: &[&str], options: CsvReaderOptions) -> Option<bool> {
+    if lines.len() < 2 {
+        return None;
+    }
+
+    let sample = lines.join(options.terminator.as_str());
+    let mut rdr = CsvReader::with_options(sample.as_bytes(), EncodeType::UTF8, options);
+    let mut rows = Vec::new();
+    while let Ok(Some(row)) = rdr.read_row() {
+        rows.push(row.iter().map(|s| s.trim().to_owned()).collect::<Vec<_>>());
+    }
+    if rows.len() < 2 {
+        return None;
+    }
+
+    let is_number = |s: &str| !s.is_empty() && s.replace(',', "").parse::<f64>().is_ok();
+    let mut votes = 0i32;
+    for (i, first) in rows[0].iter().enumerate() {
+        let rest_numeric = rows[1..]
+            .iter()
+            .all(|row| row.get(i).is_some_and(|v| is_number(v)));
+        if rest_numeric {
+            votes += if is_number(first) { -1 } else { 1 };
+        }
+    }
+
+    match votes {
+        0 => None,
+        _ => Some(votes > 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::encode;
+    use std::io::Cursor;
+
+    #[test]
+    fn sniffs_semicolons_and_lf() {
+        let options = CsvReaderOptions::sniff("name;qty;price\nfoo;1;2.5\nbar;3;4,5\n");
+        assert_eq!(options.delimiter, ';');
+        assert_eq!(options.terminator, Terminator::LF);
+        assert!(options.has_header);
+    }
+
+    #[test]
+    fn sniffs_tabs_and_cr() {
+        let options = CsvReaderOptions::sniff("a\tb\r1\t2\r3\t4\r");
+        assert_eq!(options.delimiter, '\t');
+        assert_eq!(options.terminator, Terminator::CR);
+    }
+
+    #[test]
+    fn ignores_delimiters_inside_quotes() {
+        let sample = "'a,b'|'c'\r\n'd,e,f'|'g'\r\n'h'|'i,j'\r\n";
+        let options = CsvReaderOptions::sniff(sample);
+        assert_eq!(options.text_enclosure, '\'');
+        assert_eq!(options.delimiter, '|');
+        assert_eq!(options.terminator, Terminator::CRLF);
+    }
+
+    #[test]
+    fn numeric_first_row_is_not_a_header() {
+        let options = CsvReaderOptions::sniff("1,2\n3,4\n5,6\n");
+        assert!(!options.has_header);
+    }
+
+    #[test]
+    fn falls_back_to_defaults() {
+        assert_eq!(CsvReaderOptions::sniff(""), CsvReaderOptions::default());
+        assert_eq!(
+            CsvReaderOptions::sniff("abc\r\n"),
+            CsvReaderOptions::default()
+        );
+    }
+
+    #[test]
+    fn sniffs_and_rewinds_utf16_readers() {
+        let mut buf = Vec::new();
+        encode("名\t数\n甲\t1\n乙\t2\n", EncodeType::UTF16LE, &mut buf).unwrap();
+        let mut bytes = EncodeType::UTF16LE.bom().unwrap().to_vec();
+        bytes.extend(buf);
+        let mut rdr = Cursor::new(bytes);
+        rdr.set_position(4);
+
+        let options = CsvReaderOptions::sniff_reader(&mut rdr, EncodeType::Auto).unwrap();
+        assert_eq!(options.delimiter, '\t');
+        assert_eq!(options.terminator, Terminator::LF);
+        assert!(options.has_header);
+        assert_eq!(rdr.position(), 0);
+    }
+}
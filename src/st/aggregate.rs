@@ -1,18 +1,25 @@
 //! Performs preset statistical aggregations for ST records.
+use super::aggregation::{StAggregation, StReport};
+use super::aggregator::{Aggregator, Brands, Materials, Stores};
 use super::parse::*;
-use crate::convert::{decode, EncodeType};
-use crate::CsvReader;
+use super::state::FileHash;
+use super::trace::Trace;
+use crate::convert::EncodeType;
+use crate::iter::MultiFileReader;
+use crate::{CsvReader, CsvReaderOptions};
 use crate::{Error, ErrorKind, Result};
 
 use chrono::NaiveDate;
-use fxhash::{FxHashMap, FxHashSet};
+use fxhash::FxHashMap;
 use toml::Value;
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FSlots {
     pub local_jmj: f64,
     pub local_tey: f64,
@@ -25,6 +32,7 @@ pub struct FSlots {
 }
 
 #[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ISlots {
     pub local_jmj: u32,
     pub local_tey: u32,
@@ -37,6 +45,7 @@ pub struct ISlots {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StoreType {
     Jmj,
     Tey,
@@ -48,6 +57,7 @@ pub enum StoreType {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StoreLoc {
     Local,
     Outer,
@@ -55,6 +65,7 @@ pub enum StoreLoc {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BrandType {
     Jmj,
     Tey,
@@ -141,6 +152,7 @@ impl BrandType {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Material {
     pub mid: u32,
     pub wid: u16,
@@ -160,6 +172,7 @@ pub struct Material {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Store {
     pub sid: u32,
     pub sname: String,
@@ -178,6 +191,7 @@ pub struct Store {
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Brand {
     pub brand: BrandType,
     pub req_amount: f64,
@@ -208,6 +222,20 @@ pub struct Brand {
     pub sku_in_use_alone_other: u16,
 }
 
+impl FSlots {
+    /// Returns the sum of all slots.
+    pub fn sum(&self) -> f64 {
+        self.local_jmj
+            + self.local_tey
+            + self.local_lkd
+            + self.local_son
+            + self.local_nws
+            + self.outer_store
+            + self.outer_dc
+            + self.other
+    }
+}
+
 impl Material {
     pub fn new() -> Self {
         Default::default()
@@ -233,308 +261,291 @@ pub type SMap = FxHashMap<u32, Store>;
 /// A type alias for `FxHashMap<u32, Brand>`.
 pub type BMap = FxHashMap<BrandType, Brand>;
 
+/// The slots of [`ISlots`] and [`FSlots`], one per group of stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    LocalJmj,
+    LocalTey,
+    LocalLkd,
+    LocalSon,
+    LocalNws,
+    OuterStore,
+    OuterDc,
+    Other,
+}
+
+impl Slot {
+    /// All slots, in the order of the fields of [`ISlots`] and [`FSlots`].
+    pub const ALL: [Slot; 8] = [
+        Slot::LocalJmj,
+        Slot::LocalTey,
+        Slot::LocalLkd,
+        Slot::LocalSon,
+        Slot::LocalNws,
+        Slot::OuterStore,
+        Slot::OuterDc,
+        Slot::Other,
+    ];
+
+    /// Returns the slot of a store of the given type and location, as returned by
+    /// [`get_store_type`].
+    pub fn of(store: (StoreType, StoreLoc)) -> Option<Slot> {
+        use StoreLoc::*;
+        use StoreType::*;
+        match store {
+            (Jmj, Local) => Some(Slot::LocalJmj),
+            (Tey, Local) => Some(Slot::LocalTey),
+            (Lkd, Local) => Some(Slot::LocalLkd),
+            (Son, Local) => Some(Slot::LocalSon),
+            (Nws, Local) => Some(Slot::LocalNws),
+            (Jmj, Outer) | (Tey, Outer) | (Lkd, Outer) | (Son, Outer) | (Nws, Outer) => {
+                Some(Slot::OuterStore)
+            }
+            (Dc, Outer) => Some(Slot::OuterDc),
+            (Oth, Unknown) => Some(Slot::Other),
+            _ => None,
+        }
+    }
+}
+
+impl ISlots {
+    /// Returns a mutable reference to the given slot.
+    pub fn get_mut(&mut self, slot: Slot) -> &mut u32 {
+        match slot {
+            Slot::LocalJmj => &mut self.local_jmj,
+            Slot::LocalTey => &mut self.local_tey,
+            Slot::LocalLkd => &mut self.local_lkd,
+            Slot::LocalSon => &mut self.local_son,
+            Slot::LocalNws => &mut self.local_nws,
+            Slot::OuterStore => &mut self.outer_store,
+            Slot::OuterDc => &mut self.outer_dc,
+            Slot::Other => &mut self.other,
+        }
+    }
+}
+
+impl FSlots {
+    /// Returns a mutable reference to the given slot.
+    pub fn get_mut(&mut self, slot: Slot) -> &mut f64 {
+        match slot {
+            Slot::LocalJmj => &mut self.local_jmj,
+            Slot::LocalTey => &mut self.local_tey,
+            Slot::LocalLkd => &mut self.local_lkd,
+            Slot::LocalSon => &mut self.local_son,
+            Slot::LocalNws => &mut self.local_nws,
+            Slot::OuterStore => &mut self.outer_store,
+            Slot::OuterDc => &mut self.outer_dc,
+            Slot::Other => &mut self.other,
+        }
+    }
+}
+
+/// The intermediate state of an ST aggregation, made of the built-in aggregators.
+///
+/// Records can be folded into a state in several runs, and the state can be persisted in
+/// between, see [`State::save`] and [`State::load`]. The final maps are derived from the state
+/// with [`State::maps`].
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub(super) materials: Materials,
+    pub(super) stores: Stores,
+    pub(super) brands: Brands,
+    /// Hashes of the ingested files, with their paths at the time of ingestion.
+    pub(super) ledger: BTreeMap<FileHash, PathBuf>,
+}
+
 /// Aggregates ST records from a single file.
 pub fn aggregate(
     file: File,
     encoding: EncodeType,
     config: &Value,
-    reader: &mut CsvReader,
+    options: CsvReaderOptions,
     strict: bool,
 ) -> Result<(MMap, SMap, BMap)> {
-    let mut mmap = MMap::default();
-    let mut smap = SMap::default();
-    let mut bmap = BMap::default();
-    let mut jmj_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut tey_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut lkd_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut son_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut nws_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut oth_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut os_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut dc_req_set: FxHashSet<(u32, u32)> = FxHashSet::default();
-    let mut daily_req_qt: FxHashMap<u32, FxHashMap<NaiveDate, f64>> = FxHashMap::default();
-    let mut store_map: FxHashMap<u32, (FxHashSet<u32>, FxHashMap<NaiveDate, f64>)> =
-        FxHashMap::default();
-    let mut brand_set: FxHashSet<(u32, BrandType)> = FxHashSet::default();
-
-    let mut rdr = BufReader::new(file);
-    let mut buf = Vec::new();
-    let mut line = String::new();
+    StAggregation::new(config)
+        .encoding(encoding)
+        .options(options)
+        .strict(strict)
+        .read(file)
+        .map(StReport::into_maps)
+}
 
-    // Get store ranges from config file.
-    let ranges = parse_config_store_ranges(config)?;
+/// Aggregates ST records from a single file with the given aggregators, see
+/// [`Registry`](super::aggregator::Registry) for the built-in ones. The aggregators are
+/// finished once all records have been observed.
+pub fn aggregate_with<A: Aggregator>(
+    file: File,
+    encoding: EncodeType,
+    config: &Value,
+    options: CsvReaderOptions,
+    strict: bool,
+    aggregators: &mut [A],
+) -> Result<()> {
+    let rdr = CsvReader::with_options(BufReader::new(file), encoding, options);
+    for_each_record(rdr, config, strict, |_, _, record, store| {
+        for aggregator in aggregators.iter_mut() {
+            aggregator.observe(record, store);
+        }
+    })?;
+    for aggregator in aggregators.iter_mut() {
+        aggregator.finish();
+    }
+    Ok(())
+}
 
-    // Read header row and parse it into a `Header`.
-    rdr.read_until(b'\n', &mut buf)?;
-    decode(&buf, encoding, &mut line)?;
-    let header = parse_header(&line, config, reader)?;
+/// Aggregates ST records from multiple files like [`aggregate`], also tracking the input lines
+/// each material and store was aggregated from.
+///
+/// The reader should keep the header line of the first file only, see
+/// [`MultiFileReader::head_once`].
+pub fn aggregate_traced(
+    rdr: MultiFileReader,
+    config: &Value,
+    options: CsvReaderOptions,
+    strict: bool,
+) -> Result<((MMap, SMap, BMap), Trace)> {
+    let mut trace = Trace::default();
+    // `MultiFileReader` yields UTF-8 whatever the encodings of the files are.
+    let rdr = CsvReader::with_options(rdr, EncodeType::UTF8, options);
+    let mut state = State::new();
+    state.fold_with(rdr, config, strict, |rdr, lines, record| {
+        if let Some((path, end)) = rdr.get_ref().provenance() {
+            let lines = (end + 1).saturating_sub(lines).max(1)..=end;
+            trace
+                .materials
+                .entry(record.mid)
+                .or_default()
+                .add(path, lines.clone());
+            trace.stores.entry(record.sid).or_default().add(path, lines);
+        }
+    })?;
+    Ok((state.into_maps(), trace))
+}
 
-    let mut line_number = 1;
-    // Analyse records
-    loop {
-        buf.clear();
-        match rdr.read_until(b'\n', &mut buf) {
-            Err(e) => return Err(Error::new(ErrorKind::Io(e))),
-            Ok(0) => {
-                // All contents have been read when reaching here.
-                // Just break here.
-                break;
-            }
-            Ok(_) => {
-                decode(&buf, encoding, &mut line)?;
-                line_number += 1;
-                let record = match parse_record(&line, header, reader) {
-                    Ok(option_record) => option_record,
-                    Err(e) => {
-                        if !strict {
-                            continue;
-                        } else {
-                            return Err(Error::new(ErrorKind::MalformedData(
-                                e.to_string(),
-                                line_number,
-                            )));
-                        }
-                    }
-                };
-                let record = if record.is_none() {
-                    // All records have been aggregated when reaching here.
-                    // Break this loop.
-                    break;
-                } else {
-                    record.unwrap()
-                };
-
-                // Only insert a `Material` into `mmap` if quantity is not zero.
-                if record.qt != 0.0 {
-                    // Insert a new `Material` into `mmap` if `record.mid` does not exist yet.
-                    let mmap_entry = mmap.entry(record.mid).or_insert(Material {
-                        mid: record.mid,
-                        wid: record.wid,
-                        mname: record.mname,
-                        store: Default::default(),
-                        req_times: Default::default(),
-                        quantity: Default::default(),
-                        amount: Default::default(),
-                        first_req_date: record.dt,
-                        last_req_date: record.dt,
-                        max_req_interval: 0,
-                        min_req_interval: 0,
-                        max_req_quantity: 0.0,
-                        min_req_quantity: 0.0,
-                        max_req_date: None,
-                        min_req_date: None,
-                    });
-
-                    let mut smap_entry = smap.entry(record.sid).or_insert(Store {
-                        sid: record.sid,
-                        sname: record.sname,
-                        store_type: Default::default(),
-                        store_loc: Default::default(),
-                        sku_in_use: 0,
-                        amount: 0.0,
-                        first_req_date: None,
-                        last_req_date: None,
-                        max_req_interval: 0,
-                        min_req_interval: 0,
-                        max_req_date: None,
-                        min_req_date: None,
-                        max_req_amount: 0.0,
-                        min_req_amount: 0.0,
-                    });
-
-                    use StoreLoc::*;
-                    use StoreType::*;
-                    macro_rules! update_maps_stage1 {
-                    ($($type:ident, $loc:ident, $set:ident, $field:ident)*) => {
-                        match get_store_type(record.sid, &ranges) {
-                            $(($type, $loc) => {
-                                // Update `Material`.
-                                if record.qt > 0.0 && $set.insert((record.mid, record.sid)) {
-                                    (*mmap_entry).store.$field += 1;
-                                }
-                                (*mmap_entry).quantity.$field += record.qt;
-                                {
-                                    let entry = daily_req_qt.entry(record.mid).or_default();
-                                    let entry_inner = entry.entry(record.dt.unwrap()).or_default();
-                                    *entry_inner += record.qt;
-                                }
-                                (*mmap_entry).amount.$field += record.at;
-                                if record.qt > 0.0 {
-                                    (*mmap_entry).req_times.$field += 1;
-                                }
-
-                                // Update `Store`.
-                                (*smap_entry).store_type = $type;
-                                (*smap_entry).store_loc = $loc;
-                                (*smap_entry).amount += record.at;
-                                let entry = store_map.entry(record.sid).or_default();
-                                if (*entry).0.insert(record.mid) {
-                                    (*smap_entry).sku_in_use += 1;
-                                }
-                                let entry_inner = (*entry).1.entry(record.dt.unwrap()).or_default();
-                                *entry_inner += record.at;
-
-                                // Update `Brand`.
-                                let brand_type = ($type, $loc).into();
-                                let bmap_entry = bmap.entry(brand_type).or_default();
-                                (*bmap_entry).brand = brand_type;
-                                (*bmap_entry).req_amount += record.at;
-                                match record.wid {
-                                    11751 => (*bmap_entry).req_amount_11751 += record.at,
-                                    11752 => (*bmap_entry).req_amount_11752 += record.at,
-                                    11753 => (*bmap_entry).req_amount_11753 += record.at,
-                                    11754 => (*bmap_entry).req_amount_11754 += record.at,
-                                    11755 => (*bmap_entry).req_amount_11755 += record.at,
-                                    11759 => (*bmap_entry).req_amount_11759 += record.at,
-                                    11795 => (*bmap_entry).req_amount_11795 += record.at,
-                                    _ => (*bmap_entry).req_amount_other += record.at,
-                                }
-                                if brand_set.insert((record.mid, brand_type)) {
-                                    (*bmap_entry).sku_in_use += 1;
-                                    match record.wid {
-                                        11751 => (*bmap_entry).sku_in_use_11751 += 1,
-                                        11752 => (*bmap_entry).sku_in_use_11752 += 1,
-                                        11753 => (*bmap_entry).sku_in_use_11753 += 1,
-                                        11754 => (*bmap_entry).sku_in_use_11754 += 1,
-                                        11755 => (*bmap_entry).sku_in_use_11755 += 1,
-                                        11759 => (*bmap_entry).sku_in_use_11759 += 1,
-                                        11795 => (*bmap_entry).sku_in_use_11795 += 1,
-                                        _ => (*bmap_entry).sku_in_use_other += 1,
-                                    }
-                                }
-                            })*
-                            _ => (),
-                        }
-                    }
-                }
+impl State {
+    /// Creates an empty state.
+    pub fn new() -> Self {
+        Default::default()
+    }
 
-                    update_maps_stage1!(
-                        Jmj, Local, jmj_req_set, local_jmj
-                        Tey, Local, tey_req_set, local_tey
-                        Lkd, Local, lkd_req_set, local_lkd
-                        Son, Local, son_req_set, local_son
-                        Nws, Local, nws_req_set, local_nws
-                        Jmj, Outer, os_req_set, outer_store
-                        Tey, Outer, os_req_set, outer_store
-                        Lkd, Outer, os_req_set, outer_store
-                        Son, Outer, os_req_set, outer_store
-                        Nws, Outer, os_req_set, outer_store
-                        Dc, Outer, dc_req_set, outer_dc
-                        Oth, Unknown, oth_req_set, other
-                    );
-                }
-            }
-        }
+    /// Folds the records of the given reader into this state. The first record of the reader
+    /// must be the header row.
+    pub fn fold<R: BufRead>(
+        &mut self,
+        rdr: CsvReader<R>,
+        config: &Value,
+        strict: bool,
+    ) -> Result<()> {
+        self.fold_with(rdr, config, strict, |_, _, _| ())
     }
 
-    // Stage 2 `mmap` generation process.
-    for (mid, map) in daily_req_qt {
-        // Transmute `map` into a `vec` for easy in-place sorting.
-        let mut vec = map.into_iter().collect::<Vec<_>>();
-        vec.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-
-        // SAFETY: `unwrap`s here is safe because when current `(mid, map)` pair is present in
-        // `daily_req_qt`, there must be at least one entry in `map`.
-        let &(dt, qt) = vec.first().unwrap();
-        let last_dt = vec.last().unwrap().0;
-        let mut min_qt = qt;
-        let mut max_qt = qt;
-        let mut min_dt = dt;
-        let mut max_dt = dt;
-        let mut min_gap = 0;
-        let mut max_gap = 0;
-        let mut gap_prev = 0;
-        vec.iter()
-            .zip(vec.iter().skip(1))
-            .for_each(|(&(d1, _q1), &(d2, q2))| {
-                if q2 > max_qt {
-                    max_qt = q2;
-                    max_dt = d2;
-                } else if q2 < min_qt {
-                    min_qt = q2;
-                    min_dt = d2;
-                }
+    /// Returns the path a file with the given hash was ingested from, if it has been ingested.
+    pub fn ingested(&self, hash: &FileHash) -> Option<&Path> {
+        self.ledger.get(hash).map(|path| path.as_path())
+    }
 
-                let gap = (d2 - d1).num_days().abs();
-                if gap_prev == 0 {
-                    max_gap = gap;
-                    min_gap = gap;
-                } else if gap > gap_prev {
-                    max_gap = gap;
-                } else if gap < gap_prev {
-                    min_gap = gap;
-                }
-                gap_prev = gap;
-            });
-
-        // Update `mmap` entries.
-        mmap.entry(mid).and_modify(|e| {
-            (*e).min_req_interval = min_gap as u16;
-            (*e).max_req_interval = max_gap as u16;
-            (*e).min_req_quantity = min_qt;
-            (*e).max_req_quantity = max_qt;
-            (*e).min_req_date = Some(min_dt);
-            (*e).max_req_date = Some(max_dt);
-            (*e).first_req_date = Some(dt);
-            (*e).last_req_date = Some(last_dt);
-        });
+    /// Records that the file with the given path and hash has been folded into this state.
+    pub fn mark_ingested<P: AsRef<Path>>(&mut self, path: P, hash: FileHash) {
+        self.ledger.insert(hash, path.as_ref().to_path_buf());
     }
 
-    // Stage 2 `smap` generation process.
-    for (sid, (_, map)) in store_map {
-        // Transmute `map` into a `vec` for easy in-place sorting.
-        let mut vec = map.into_iter().collect::<Vec<_>>();
-        vec.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-
-        // SAFETY: `unwrap`s here is safe because when current `(sid, map)` pair is present in
-        // `store_map`, there must be at least one entry in `map`.
-        let &(dt, at) = vec.first().unwrap();
-        let last_dt = vec.last().unwrap().0;
-        let mut min_at = at;
-        let mut max_at = at;
-        let mut min_dt = dt;
-        let mut max_dt = dt;
-        let mut min_gap = 0;
-        let mut max_gap = 0;
-        let mut gap_prev = 0;
-        vec.iter()
-            .zip(vec.iter().skip(1))
-            .for_each(|(&(d1, _a1), &(d2, a2))| {
-                if a2 > max_at {
-                    max_at = a2;
-                    max_dt = d2;
-                } else if a2 < min_at {
-                    min_at = a2;
-                    min_dt = d2;
-                }
+    /// Returns the ingested files with their hashes.
+    pub fn ledger(&self) -> impl Iterator<Item = (&FileHash, &Path)> {
+        self.ledger
+            .iter()
+            .map(|(hash, path)| (hash, path.as_path()))
+    }
+
+    /// Derives the aggregated maps from this state.
+    pub fn maps(&self) -> (MMap, SMap, BMap) {
+        self.clone().into_maps()
+    }
+
+    /// Derives the aggregated maps from this state, consuming it.
+    pub fn into_maps(self) -> (MMap, SMap, BMap) {
+        let (materials, stores, brands) = self.into_aggregators();
+        (materials.mmap, stores.smap, brands.bmap)
+    }
+
+    /// Finishes the built-in aggregators of this state and returns them, consuming it.
+    pub fn into_aggregators(mut self) -> (Materials, Stores, Brands) {
+        self.materials.finish();
+        self.stores.finish();
+        self.brands.finish();
+        (self.materials, self.stores, self.brands)
+    }
 
-                let gap = (d2 - d1).num_days().abs();
-                if gap_prev == 0 {
-                    max_gap = gap;
-                    min_gap = gap;
-                } else if gap > gap_prev {
-                    max_gap = gap;
-                } else if gap < gap_prev {
-                    min_gap = gap;
+    /// Folds the records of the given reader into this state, calling `on_record` with the
+    /// reader, the number of lines and the parsed record of each record that is aggregated.
+    fn fold_with<R, F>(
+        &mut self,
+        rdr: CsvReader<R>,
+        config: &Value,
+        strict: bool,
+        mut on_record: F,
+    ) -> Result<()>
+    where
+        R: BufRead,
+        F: FnMut(&CsvReader<R>, u64, &Record),
+    {
+        for_each_record(rdr, config, strict, |rdr, lines, record, store| {
+            on_record(rdr, lines, record);
+            self.observe(record, store);
+        })
+    }
+
+    /// Observes a single record with the built-in aggregators, see [`Aggregator::observe`].
+    pub(super) fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc)) {
+        self.materials.observe(record, store);
+        self.stores.observe(record, store);
+        self.brands.observe(record, store);
+    }
+}
+
+/// Calls `f` with the reader, the number of lines, the parsed record and the store type of each
+/// record of non-zero quantity. The first record of the reader must be the header row.
+fn for_each_record<R, F>(
+    mut rdr: CsvReader<R>,
+    config: &Value,
+    strict: bool,
+    mut f: F,
+) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(&CsvReader<R>, u64, &Record, (StoreType, StoreLoc)),
+{
+    // Get store ranges from config file.
+    let ranges = parse_config_store_ranges(config)?;
+
+    // Read header row and parse it into a `Header`.
+    let header = parse_header(rdr.read_header()?, config)?;
+
+    // Analyse records
+    while let Some(row) = rdr.read_row()? {
+        let row_line = row.position().line;
+        let record = match parse_record(row, header) {
+            Ok(option_record) => option_record,
+            Err(e) => {
+                if !strict {
+                    continue;
+                } else {
+                    return Err(Error::new(ErrorKind::Csv(e.to_string(), row.position())));
                 }
-                gap_prev = gap;
-            });
-
-        // Update `smap` entries.
-        smap.entry(sid).and_modify(|e| {
-            (*e).min_req_interval = min_gap as u16;
-            (*e).max_req_interval = max_gap as u16;
-            (*e).min_req_amount = min_at;
-            (*e).max_req_amount = max_at;
-            (*e).min_req_date = Some(min_dt);
-            (*e).max_req_date = Some(max_dt);
-            (*e).first_req_date = Some(dt);
-            (*e).last_req_date = Some(last_dt);
-        });
+            }
+        };
+        // All records have been aggregated when reaching here.
+        let Some(record) = record else {
+            break;
+        };
+
+        // Only aggregate records whose quantity is not zero.
+        if record.qt != 0.0 {
+            let lines = rdr.position().line - row_line;
+            f(&rdr, lines, &record, get_store_type(record.sid, &ranges));
+        }
     }
 
-    // Return three maps.
-    Ok((mmap, smap, bmap))
+    Ok(())
 }
 
 pub fn get_store_type(sid: u32, ranges: &StoreRange) -> (StoreType, StoreLoc) {
@@ -0,0 +1,285 @@
+//! A builder of ST aggregations over in-memory data, see [`StAggregation`].
+use super::aggregate::{get_store_type, BMap, MMap, SMap, State};
+use super::parse::{parse_config_store_ranges, Record};
+use crate::convert::EncodeType;
+use crate::{CsvReader, CsvReaderOptions};
+use crate::{Error, ErrorKind, Result};
+
+use toml::Value;
+
+use std::borrow::Cow;
+use std::io::{BufReader, Read};
+
+/// Configures and runs an ST aggregation.
+///
+/// Unlike [`aggregate`](super::aggregate::aggregate), the records can be read from any
+/// [`Read`], a string or an iterator of [`Record`]s, and the results are returned as an
+/// [`StReport`] without writing any file.
+#[derive(Debug, Clone, Copy)]
+pub struct StAggregation<'a> {
+    config: &'a Value,
+    encoding: EncodeType,
+    options: CsvReaderOptions,
+    strict: bool,
+}
+
+/// The results of an ST aggregation.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StReport {
+    /// The statistics per material.
+    pub materials: MMap,
+    /// The statistics per store.
+    pub stores: SMap,
+    /// The statistics per brand.
+    pub brands: BMap,
+}
+
+impl<'a> StAggregation<'a> {
+    /// Creates an aggregation with the header names and store ranges of the given config,
+    /// reading UTF-8 encoded, comma separated records in non-strict mode.
+    pub fn new(config: &'a Value) -> StAggregation<'a> {
+        StAggregation {
+            config,
+            encoding: EncodeType::UTF8,
+            options: Default::default(),
+            strict: false,
+        }
+    }
+
+    /// Sets the encoding of the records read with [`StAggregation::read`].
+    pub fn encoding(mut self, encoding: EncodeType) -> StAggregation<'a> {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the CSV options of the records read with [`StAggregation::read`] and
+    /// [`StAggregation::read_str`].
+    pub fn options(mut self, options: CsvReaderOptions) -> StAggregation<'a> {
+        self.options = options;
+        self
+    }
+
+    /// Sets whether a record that cannot be parsed is an error, instead of being skipped.
+    pub fn strict(mut self, strict: bool) -> StAggregation<'a> {
+        self.strict = strict;
+        self
+    }
+
+    /// Aggregates the ST table of the given reader, whose first record must be the header row.
+    pub fn read<R: Read>(&self, rdr: R) -> Result<StReport> {
+        let rdr = CsvReader::with_options(BufReader::new(rdr), self.encoding, self.options);
+        let mut state = State::new();
+        state.fold(rdr, self.config, self.strict)?;
+        Ok(state.into())
+    }
+
+    /// Aggregates the given ST table, whose first line must be the header row. The encoding
+    /// set with [`StAggregation::encoding`] is ignored.
+    pub fn read_str(&self, s: &str) -> Result<StReport> {
+        let rdr = CsvReader::with_options(s.as_bytes(), EncodeType::UTF8, self.options);
+        let mut state = State::new();
+        state.fold(rdr, self.config, self.strict)?;
+        Ok(state.into())
+    }
+
+    /// Aggregates the given records. Only the store ranges of the config are used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a record of non-zero quantity has no date.
+    pub fn records<I>(&self, records: I) -> Result<StReport>
+    where
+        I: IntoIterator<Item = Record>,
+    {
+        let ranges = parse_config_store_ranges(self.config)?;
+        let mut state = State::new();
+        for record in records {
+            // Only aggregate records whose quantity is not zero, like `aggregate`.
+            if record.qt == 0.0 {
+                continue;
+            }
+            if record.dt.is_none() {
+                return Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                    "record of material {} and store {} has no date",
+                    record.mid, record.sid
+                )))));
+            }
+            state.observe(&record, get_store_type(record.sid, &ranges));
+        }
+        Ok(state.into())
+    }
+}
+
+impl StReport {
+    /// Returns the statistics per material, per store and per brand.
+    pub fn into_maps(self) -> (MMap, SMap, BMap) {
+        (self.materials, self.stores, self.brands)
+    }
+}
+
+impl From<State> for StReport {
+    fn from(state: State) -> StReport {
+        let (materials, stores, brands) = state.into_maps();
+        StReport {
+            materials,
+            stores,
+            brands,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    const CONFIG: &str = r#"
+        [range]
+        range_jmj = ["1000000-1999999"]
+        range_jmj_local = ["1000000-1799999"]
+        range_lkd = ["7000000-7999999"]
+        range_lkd_local = ["7000000-7999999"]
+        range_nws = ["32000000-32999999"]
+        range_nws_local = ["32000000-32999999"]
+        range_outer_warehouse = ["11951-11955"]
+        range_son = ["33000000-33999999"]
+        range_son_local = ["33000000-33999999"]
+        range_tey = ["2000000-2999999"]
+        range_tey_local = ["2020000-2020999", "2600000-2799999"]
+
+        [st]
+        field_at = "amount"
+        field_dt = "date"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_qt = "quantity"
+        field_sid = "sid"
+        field_sname = "sname"
+        field_wid = "wid"
+    "#;
+
+    const RECORDS: &str = "\
+mid,mname,sid,sname,wid,quantity,amount,date
+100,盐,1000001,Jmj local,11751,5,10,2021/03/01
+100,盐,1800001,Jmj outer,11751,3,6,2021/03/02
+100,盐,1000001,Jmj local,11751,2,4,2021/03/04
+200,Rice,11951,Warehouse,11761,10,20,2021/03/05
+200,Rice,1000001,Jmj local,11761,0,0,2021/03/06
+total
+";
+
+    /// Returns the quantity and amount of each material and the amount of each store, sorted.
+    fn summary(report: StReport) -> (Vec<(u32, f64, f64)>, Vec<(u32, f64)>) {
+        let (materials, stores, _) = report.into_maps();
+        let mut materials = materials
+            .values()
+            .map(|m| (m.mid, m.quantity.sum(), m.amount.sum()))
+            .collect::<Vec<_>>();
+        materials.sort_by_key(|m| m.0);
+        let mut stores = stores
+            .values()
+            .map(|s| (s.sid, s.amount))
+            .collect::<Vec<_>>();
+        stores.sort_by_key(|s| s.0);
+        (materials, stores)
+    }
+
+    fn record(mid: u32, sid: u32, qt: f64, at: f64, dt: Option<(i32, u32, u32)>) -> Record {
+        Record {
+            mid,
+            sid,
+            wid: 11751,
+            qt,
+            at,
+            dt: dt.and_then(|(y, m, d)| NaiveDate::from_ymd_opt(y, m, d)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aggregates_records_by_material_store_and_slot() {
+        let config = CONFIG.parse().unwrap();
+        let report = StAggregation::new(&config).read_str(RECORDS).unwrap();
+
+        let salt = &report.materials[&100];
+        assert_eq!(salt.quantity.local_jmj, 7.0);
+        assert_eq!(salt.quantity.outer_store, 3.0);
+        assert_eq!(salt.req_times.local_jmj, 2);
+        assert_eq!(salt.first_req_date, NaiveDate::from_ymd_opt(2021, 3, 1));
+        assert_eq!(salt.last_req_date, NaiveDate::from_ymd_opt(2021, 3, 4));
+        assert_eq!(report.materials[&200].quantity.outer_dc, 10.0);
+        // Records of zero quantity are left out.
+        assert_eq!(
+            summary(report),
+            (
+                vec![(100, 10.0, 20.0), (200, 10.0, 20.0)],
+                vec![(11951, 20.0), (1000001, 14.0), (1800001, 6.0)]
+            )
+        );
+    }
+
+    #[test]
+    fn reads_records_of_the_given_encoding_and_options() {
+        use crate::convert::encode;
+
+        let config = CONFIG.parse().unwrap();
+        let expected = summary(StAggregation::new(&config).read_str(RECORDS).unwrap());
+
+        let mut gb18030 = Vec::new();
+        encode(
+            &RECORDS.replace(',', ";"),
+            EncodeType::GB18030,
+            &mut gb18030,
+        )
+        .unwrap();
+        let options = CsvReaderOptions {
+            delimiter: ';',
+            ..Default::default()
+        };
+        let aggregation = StAggregation::new(&config)
+            .encoding(EncodeType::GB18030)
+            .options(options);
+        let report = aggregation.read(&gb18030[..]).unwrap();
+        assert_eq!(report.materials[&100].mname, "盐");
+        assert_eq!(summary(report), expected);
+        assert_eq!(
+            summary(aggregation.read_str(&RECORDS.replace(',', ";")).unwrap()),
+            expected
+        );
+    }
+
+    #[test]
+    fn skips_malformed_records_unless_strict() {
+        let config = CONFIG.parse().unwrap();
+        let records = RECORDS.replace("2,4,2021/03/04", "two,4,2021/03/04");
+
+        let report = StAggregation::new(&config).read_str(&records).unwrap();
+        assert_eq!(report.materials[&100].quantity.local_jmj, 5.0);
+        assert!(StAggregation::new(&config)
+            .strict(true)
+            .read_str(&records)
+            .is_err());
+    }
+
+    #[test]
+    fn aggregates_given_records_like_read_ones() {
+        let config = CONFIG.parse().unwrap();
+        let records = vec![
+            record(100, 1000001, 5.0, 10.0, Some((2021, 3, 1))),
+            record(100, 1800001, 3.0, 6.0, Some((2021, 3, 2))),
+            record(100, 1000001, 2.0, 4.0, Some((2021, 3, 4))),
+            record(200, 11951, 10.0, 20.0, Some((2021, 3, 5))),
+            // Records of zero quantity need no date.
+            record(200, 1000001, 0.0, 0.0, None),
+        ];
+        let aggregation = StAggregation::new(&config);
+        assert_eq!(
+            summary(aggregation.records(records).unwrap()),
+            summary(aggregation.read_str(RECORDS).unwrap())
+        );
+
+        let undated = vec![record(100, 1000001, 1.0, 2.0, None)];
+        assert!(aggregation.records(undated).is_err());
+    }
+}
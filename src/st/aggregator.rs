@@ -0,0 +1,506 @@
+//! Pluggable aggregations of ST records, see [`Aggregator`].
+//!
+//! The statistics per material, per store and per brand are computed by the built-in
+//! aggregators [`Materials`], [`Stores`] and [`Brands`]. Additional aggregators can be
+//! registered by name in a [`Registry`], so that they can be selected from the command line.
+use super::aggregate::*;
+use super::parse::Record;
+use super::writer::{write_bmap, write_mmap, write_smap};
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+use fxhash::{FxHashMap, FxHashSet};
+
+use std::borrow::Cow;
+
+/// An aggregation of ST records.
+pub trait Aggregator {
+    /// Observes a single record of non-zero quantity, whose store is of the given type and
+    /// location, see [`get_store_type`].
+    ///
+    /// Records without a date are counted, but left out of the statistics by date.
+    fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc));
+
+    /// Completes the aggregation once all records have been observed.
+    fn finish(&mut self) {}
+
+    /// Writes the results of the aggregation to the given directory.
+    fn write(&self, out_dir: &str) -> Result<()>;
+}
+
+impl<A: Aggregator + ?Sized> Aggregator for Box<A> {
+    fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc)) {
+        (**self).observe(record, store)
+    }
+
+    fn finish(&mut self) {
+        (**self).finish()
+    }
+
+    fn write(&self, out_dir: &str) -> Result<()> {
+        (**self).write(out_dir)
+    }
+}
+
+/// Aggregates statistics per material, written to `sku.csv`.
+#[derive(Debug, Clone, Default)]
+pub struct Materials {
+    pub(super) mmap: MMap,
+    /// Material/store pairs per slot, for counting the stores requisitioning each material.
+    pub(super) req_sets: [FxHashSet<(u32, u32)>; 8],
+    pub(super) daily_req_qt: FxHashMap<u32, FxHashMap<NaiveDate, f64>>,
+}
+
+/// Aggregates statistics per store, written to `store.csv`.
+#[derive(Debug, Clone, Default)]
+pub struct Stores {
+    pub(super) smap: SMap,
+    /// The materials requisitioned by each store and its daily requisition amounts.
+    pub(super) store_map: FxHashMap<u32, (FxHashSet<u32>, FxHashMap<NaiveDate, f64>)>,
+}
+
+/// Aggregates statistics per brand, written to `brand.csv`.
+#[derive(Debug, Clone, Default)]
+pub struct Brands {
+    pub(super) bmap: BMap,
+    /// Material/brand pairs, for counting the materials in use by each brand.
+    pub(super) brand_set: FxHashSet<(u32, BrandType)>,
+}
+
+impl Materials {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the aggregated materials, complete once finished.
+    pub fn mmap(&self) -> &MMap {
+        &self.mmap
+    }
+}
+
+impl Stores {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the aggregated stores, complete once finished.
+    pub fn smap(&self) -> &SMap {
+        &self.smap
+    }
+}
+
+impl Brands {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the aggregated brands.
+    pub fn bmap(&self) -> &BMap {
+        &self.bmap
+    }
+}
+
+impl Aggregator for Materials {
+    fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc)) {
+        // Insert a new `Material` into `mmap` if `record.mid` does not exist yet.
+        let entry = self.mmap.entry(record.mid).or_insert_with(|| Material {
+            mid: record.mid,
+            wid: record.wid,
+            mname: record.mname.clone(),
+            first_req_date: record.dt,
+            last_req_date: record.dt,
+            ..Default::default()
+        });
+
+        let slot = match Slot::of(store) {
+            Some(slot) => slot,
+            None => return,
+        };
+        if record.qt > 0.0 && self.req_sets[slot as usize].insert((record.mid, record.sid)) {
+            *entry.store.get_mut(slot) += 1;
+        }
+        *entry.quantity.get_mut(slot) += record.qt;
+        *entry.amount.get_mut(slot) += record.at;
+        if record.qt > 0.0 {
+            *entry.req_times.get_mut(slot) += 1;
+        }
+
+        if let Some(dt) = record.dt {
+            let daily = self.daily_req_qt.entry(record.mid).or_default();
+            *daily.entry(dt).or_default() += record.qt;
+        }
+    }
+
+    fn finish(&mut self) {
+        for (&mid, map) in self.daily_req_qt.iter() {
+            let stats = DailyStats::of(map);
+            self.mmap.entry(mid).and_modify(|e| {
+                e.min_req_interval = stats.min_gap;
+                e.max_req_interval = stats.max_gap;
+                e.min_req_quantity = stats.min_value;
+                e.max_req_quantity = stats.max_value;
+                e.min_req_date = Some(stats.min_date);
+                e.max_req_date = Some(stats.max_date);
+                e.first_req_date = Some(stats.first_date);
+                e.last_req_date = Some(stats.last_date);
+            });
+        }
+    }
+
+    fn write(&self, out_dir: &str) -> Result<()> {
+        write_mmap(&self.mmap, out_dir)
+    }
+}
+
+impl Aggregator for Stores {
+    fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc)) {
+        // Insert a new `Store` into `smap` if `record.sid` does not exist yet.
+        let entry = self.smap.entry(record.sid).or_insert_with(|| Store {
+            sid: record.sid,
+            sname: record.sname.clone(),
+            ..Default::default()
+        });
+
+        if Slot::of(store).is_none() {
+            return;
+        }
+        entry.store_type = store.0;
+        entry.store_loc = store.1;
+        entry.amount += record.at;
+
+        let (mids, daily) = self.store_map.entry(record.sid).or_default();
+        if mids.insert(record.mid) {
+            entry.sku_in_use += 1;
+        }
+        if let Some(dt) = record.dt {
+            *daily.entry(dt).or_default() += record.at;
+        }
+    }
+
+    fn finish(&mut self) {
+        for (&sid, (_, map)) in self
+            .store_map
+            .iter()
+            .filter(|(_, (_, map))| !map.is_empty())
+        {
+            let stats = DailyStats::of(map);
+            self.smap.entry(sid).and_modify(|e| {
+                e.min_req_interval = stats.min_gap;
+                e.max_req_interval = stats.max_gap;
+                e.min_req_amount = stats.min_value;
+                e.max_req_amount = stats.max_value;
+                e.min_req_date = Some(stats.min_date);
+                e.max_req_date = Some(stats.max_date);
+                e.first_req_date = Some(stats.first_date);
+                e.last_req_date = Some(stats.last_date);
+            });
+        }
+    }
+
+    fn write(&self, out_dir: &str) -> Result<()> {
+        write_smap(&self.smap, out_dir)
+    }
+}
+
+impl Aggregator for Brands {
+    fn observe(&mut self, record: &Record, store: (StoreType, StoreLoc)) {
+        if Slot::of(store).is_none() {
+            return;
+        }
+
+        let brand_type = store.into();
+        let entry = self.bmap.entry(brand_type).or_default();
+        entry.brand = brand_type;
+        entry.req_amount += record.at;
+        match record.wid {
+            11751 => entry.req_amount_11751 += record.at,
+            11752 => entry.req_amount_11752 += record.at,
+            11753 => entry.req_amount_11753 += record.at,
+            11754 => entry.req_amount_11754 += record.at,
+            11755 => entry.req_amount_11755 += record.at,
+            11759 => entry.req_amount_11759 += record.at,
+            11795 => entry.req_amount_11795 += record.at,
+            _ => entry.req_amount_other += record.at,
+        }
+        if self.brand_set.insert((record.mid, brand_type)) {
+            entry.sku_in_use += 1;
+            match record.wid {
+                11751 => entry.sku_in_use_11751 += 1,
+                11752 => entry.sku_in_use_11752 += 1,
+                11753 => entry.sku_in_use_11753 += 1,
+                11754 => entry.sku_in_use_11754 += 1,
+                11755 => entry.sku_in_use_11755 += 1,
+                11759 => entry.sku_in_use_11759 += 1,
+                11795 => entry.sku_in_use_11795 += 1,
+                _ => entry.sku_in_use_other += 1,
+            }
+        }
+    }
+
+    fn write(&self, out_dir: &str) -> Result<()> {
+        write_bmap(&self.bmap, out_dir)
+    }
+}
+
+/// Statistics of a daily series of quantities or amounts.
+struct DailyStats {
+    first_date: NaiveDate,
+    last_date: NaiveDate,
+    min_value: f64,
+    max_value: f64,
+    min_date: NaiveDate,
+    max_date: NaiveDate,
+    min_gap: u16,
+    max_gap: u16,
+}
+
+impl DailyStats {
+    /// Computes the statistics of a series with at least one entry.
+    fn of(map: &FxHashMap<NaiveDate, f64>) -> DailyStats {
+        // Transmute `map` into a `vec` for easy in-place sorting.
+        let mut vec = map.iter().map(|(&dt, &v)| (dt, v)).collect::<Vec<_>>();
+        vec.sort_unstable_by_key(|e| e.0);
+
+        // SAFETY: `unwrap`s here is safe because there must be at least one entry in `map`.
+        let &(dt, v) = vec.first().unwrap();
+        let last_dt = vec.last().unwrap().0;
+        let mut min_v = v;
+        let mut max_v = v;
+        let mut min_dt = dt;
+        let mut max_dt = dt;
+        let mut min_gap = 0;
+        let mut max_gap = 0;
+        let mut gap_prev = 0;
+        vec.iter()
+            .zip(vec.iter().skip(1))
+            .for_each(|(&(d1, _v1), &(d2, v2))| {
+                if v2 > max_v {
+                    max_v = v2;
+                    max_dt = d2;
+                } else if v2 < min_v {
+                    min_v = v2;
+                    min_dt = d2;
+                }
+
+                let gap = (d2 - d1).num_days().abs();
+                if gap_prev == 0 {
+                    max_gap = gap;
+                    min_gap = gap;
+                } else if gap > gap_prev {
+                    max_gap = gap;
+                } else if gap < gap_prev {
+                    min_gap = gap;
+                }
+                gap_prev = gap;
+            });
+
+        DailyStats {
+            first_date: dt,
+            last_date: last_dt,
+            min_value: min_v,
+            max_value: max_v,
+            min_date: min_dt,
+            max_date: max_dt,
+            min_gap: min_gap as u16,
+            max_gap: max_gap as u16,
+        }
+    }
+}
+
+/// A function creating a new aggregator.
+type Factory = Box<dyn Fn() -> Box<dyn Aggregator>>;
+
+/// Aggregators selectable by name.
+///
+/// A new registry holds the built-in aggregators `sku` ([`Materials`]), `store` ([`Stores`])
+/// and `brand` ([`Brands`]).
+pub struct Registry {
+    factories: Vec<(String, Factory)>,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry {
+            factories: Vec::new(),
+        };
+        registry.register("sku", || Box::new(Materials::new()));
+        registry.register("store", || Box::new(Stores::new()));
+        registry.register("brand", || Box::new(Brands::new()));
+        registry
+    }
+}
+
+impl Registry {
+    /// Creates a registry of the built-in aggregators.
+    pub fn new() -> Registry {
+        Default::default()
+    }
+
+    /// Registers an aggregator under the given name, replacing an aggregator registered under
+    /// the same name before.
+    pub fn register<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Box<dyn Aggregator> + 'static,
+    {
+        match self.factories.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = Box::new(factory),
+            None => self.factories.push((name.to_owned(), Box::new(factory))),
+        }
+    }
+
+    /// Returns the names of the registered aggregators, in order of registration.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Creates a new aggregator of the given name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no aggregator is registered under the given name.
+    pub fn create(&self, name: &str) -> Result<Box<dyn Aggregator>> {
+        match self.factories.iter().find(|(n, _)| n == name) {
+            Some((_, factory)) => Ok(factory()),
+            None => Err(Error::new(ErrorKind::Other(Cow::Owned(format!(
+                "unknown aggregator `{}`, expected one of {}",
+                name,
+                self.names().collect::<Vec<_>>().join(", ")
+            ))))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::EncodeType;
+    use crate::CsvReaderOptions;
+
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+        [range]
+        range_jmj = ["1000000-1999999"]
+        range_jmj_local = ["1000000-1799999"]
+        range_lkd = ["7000000-7999999"]
+        range_lkd_local = ["7000000-7999999"]
+        range_nws = ["32000000-32999999"]
+        range_nws_local = ["32000000-32999999"]
+        range_outer_warehouse = ["11951-11955"]
+        range_son = ["33000000-33999999"]
+        range_son_local = ["33000000-33999999"]
+        range_tey = ["2000000-2999999"]
+        range_tey_local = ["2020000-2020999", "2600000-2799999"]
+
+        [st]
+        field_at = "amount"
+        field_dt = "date"
+        field_mid = "mid"
+        field_mname = "mname"
+        field_qt = "quantity"
+        field_sid = "sid"
+        field_sname = "sname"
+        field_wid = "wid"
+    "#;
+
+    const RECORDS: &str = "\
+mid,mname,sid,sname,wid,quantity,amount,date
+100,Salt,1000001,Jmj local,11751,5,10,2021/03/01
+100,Salt,1800001,Jmj outer,11751,3,6,2021/03/02
+100,Salt,1000001,Jmj local,11751,2,4,2021/03/04
+100,Salt,2600001,Tey local,11751,-1,-2,2021/03/04
+200,Rice,2000001,Tey outer,11761,4,8,2021/03/01
+200,Rice,7000001,Lkd,11761,6,12,2021/03/03
+200,Rice,11951,Warehouse,11761,10,20,2021/03/05
+300,Oil,32000001,Nws,11751,1,30,2021/03/02
+300,Oil,5,Unknown,11751,2,60,2021/03/02
+300,Oil,1000001,Jmj local,11751,0,0,2021/03/06
+total
+";
+
+    // The records written by the `aggregate` macro the built-in aggregators replaced, for
+    // `RECORDS`.
+    const SKU: &str = "\
+100,Salt,11751,5,1,2021-03-01,2021-03-04,2,1,2021-03-01,2021-03-04,1,0,0,0,0,1,0,0,2,0,0,0,0,1,0,0,7,-1,0,0,0,3,0,0,14,-2,0,0,0,6,0,0
+200,Rice,11761,10,4,2021-03-05,2021-03-01,2,2,2021-03-01,2021-03-05,0,0,1,0,0,1,1,0,0,0,1,0,0,1,1,0,0,0,6,0,0,4,10,0,0,0,12,0,0,8,20,0
+300,Oil,11751,3,3,2021-03-02,2021-03-02,0,0,2021-03-02,2021-03-02,0,0,0,0,1,0,0,1,0,0,0,0,1,0,0,1,0,0,0,0,1,0,0,2,0,0,0,0,30,0,0,60
+";
+    const STORE: &str = "\
+2000001,Tey outer,太二,外区,1,8,8,8,2021-03-01,2021-03-01,0,0,2021-03-01,2021-03-01
+7000001,Lkd,两颗鸡蛋,广深,1,12,12,12,2021-03-03,2021-03-03,0,0,2021-03-03,2021-03-03
+32000001,Nws,那未大叔,广深,1,30,30,30,2021-03-02,2021-03-02,0,0,2021-03-02,2021-03-02
+1000001,Jmj local,九毛九,广深,1,14,10,4,2021-03-01,2021-03-04,3,3,2021-03-01,2021-03-04
+1800001,Jmj outer,九毛九,外区,1,6,6,6,2021-03-02,2021-03-02,0,0,2021-03-02,2021-03-02
+2600001,Tey local,太二,广深,1,-2,-2,-2,2021-03-04,2021-03-04,0,0,2021-03-04,2021-03-04
+11951,Warehouse,外区,外区,1,20,20,20,2021-03-05,2021-03-05,0,0,2021-03-05,2021-03-05
+5,Unknown,其他,未知,1,60,60,60,2021-03-02,2021-03-02,0,0,2021-03-02,2021-03-02
+";
+    const BRAND: &str = "\
+九毛九,14,14,0,0,0,0,0,0,0,1,1,0,0,0,0,0,0,0
+太二,-2,-2,0,0,0,0,0,0,0,1,1,0,0,0,0,0,0,0
+两颗鸡蛋,12,0,0,0,0,0,0,0,12,1,0,0,0,0,0,0,0,1
+那未大叔,30,30,0,0,0,0,0,0,0,1,1,0,0,0,0,0,0,0
+外区门店,14,6,0,0,0,0,0,0,8,2,1,0,0,0,0,0,0,1
+外区,20,0,0,0,0,0,0,0,20,1,0,0,0,0,0,0,0,1
+其他,60,60,0,0,0,0,0,0,0,1,1,0,0,0,0,0,0,0
+";
+
+    /// Returns an empty directory under the system temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dpt-aggregator-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the records of a written file in sorted order, since maps are written in
+    /// arbitrary order.
+    fn sorted_records(text: &str) -> Vec<&str> {
+        let mut lines = text.lines().collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines
+    }
+
+    #[test]
+    fn built_in_aggregators_write_what_the_macro_did() {
+        let dir = temp_dir("builtin");
+        let input = dir.join("st.csv");
+        fs::write(&input, RECORDS).unwrap();
+        let config = CONFIG.parse().unwrap();
+
+        let registry = Registry::new();
+        let mut aggregators = registry
+            .names()
+            .map(|name| registry.create(name))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        aggregate_with(
+            File::open(&input).unwrap(),
+            EncodeType::UTF8,
+            &config,
+            CsvReaderOptions::default(),
+            true,
+            &mut aggregators,
+        )
+        .unwrap();
+        for aggregator in aggregators.iter() {
+            aggregator.write(dir.to_str().unwrap()).unwrap();
+        }
+
+        for (file, expected) in [("sku.csv", SKU), ("store.csv", STORE), ("brand.csv", BRAND)] {
+            let written = fs::read_to_string(dir.join(file)).unwrap();
+            let (_header, records) = written.split_once("\r\n").unwrap();
+            assert_eq!(
+                sorted_records(records),
+                sorted_records(expected),
+                "{}",
+                file
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
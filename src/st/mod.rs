@@ -1,4 +1,8 @@
 //! Performs preset statistic aggregations for ST records.
-pub mod parse;
 pub mod aggregate;
+pub mod aggregation;
+pub mod aggregator;
+pub mod parse;
+pub mod state;
+pub mod trace;
 pub mod writer;
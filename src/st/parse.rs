@@ -1,5 +1,5 @@
 //! Parsing functions, mainly [`parse_header`] and [`parse_record`].
-use crate::CsvReader;
+use crate::CsvRow;
 use crate::{Error, ErrorKind, Result};
 use std::ops::RangeInclusive;
 
@@ -8,6 +8,7 @@ use toml::Value as Config;
 
 /// Represents the indexes of ST record fields.
 #[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub mid: usize,
     pub sid: usize,
@@ -27,6 +28,7 @@ impl Header {
 
 /// Represents the indexes of ST record fields.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct STHeader {
     pub mid: String,
     pub sid: String,
@@ -40,6 +42,7 @@ pub struct STHeader {
 
 /// Represents a ST record.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Record {
     pub mid: u32,
     pub sid: u32,
@@ -59,6 +62,7 @@ impl Record {
 
 /// A list of inclusive range ([`RangeInclusive`]).
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ranges(pub Vec<RangeInclusive<usize>>);
 
 impl IntoIterator for Ranges {
@@ -72,6 +76,7 @@ impl IntoIterator for Ranges {
 
 /// Represents store ranges.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StoreRange {
     pub jmj: Ranges,
     pub tey: Ranges,
@@ -186,17 +191,15 @@ pub fn parse_config_store_ranges(config: &Config) -> Result<StoreRange> {
 /// # Errors
 ///
 /// Returns an error variant of [`ErrorKind::Config`] if the parsing process failed.
-pub fn parse_header(header: &str, config: &Config, reader: &mut CsvReader) -> Result<Header> {
+pub fn parse_header(row: &CsvRow, config: &Config) -> Result<Header> {
     let st_headers = parse_config_st_headers(&config)?;
 
-    let fields = reader.read_line(header).unwrap();
-
     let mut header = Header::new();
-    for (i, v) in fields.iter().enumerate() {
+    for (i, v) in row.iter().enumerate() {
         macro_rules! assign_index_if_match {
             ($($field:ident),*) => {
                 match v {
-                    $(_ if v.as_str() == st_headers.$field.as_str() && header.$field == 0 => header.$field = i,)*
+                    $(_ if v == st_headers.$field.as_str() && header.$field == 0 => header.$field = i,)*
                     _ => (),
                 }
 
@@ -213,15 +216,14 @@ pub fn parse_header(header: &str, config: &Config, reader: &mut CsvReader) -> Re
 /// # Errors
 ///
 /// If the parsing failed, an error variant  of [`ErrorKind::FromStr`] will be returned.
-pub fn parse_record(data: &str, header: Header, reader: &mut CsvReader) -> Result<Option<Record>> {
+pub fn parse_record(row: &CsvRow, header: Header) -> Result<Option<Record>> {
     let mut record = Record::new();
 
-    let fields = reader.read_line(data).unwrap();
     // It cannot be parsed into a `Record` if its length is less than 8.
-    if fields.len() < 8 {
+    if row.len() < 8 {
         return Ok(None);
     }
-    for (i, v) in fields.iter().enumerate() {
+    for (i, v) in row.iter().enumerate() {
         match i {
             _ if i == header.mid => {
                 if v == "" {
@@ -229,18 +231,18 @@ pub fn parse_record(data: &str, header: Header, reader: &mut CsvReader) -> Resul
                 }
                 record.mid = v
                     .parse::<u32>()
-                    .map_err(|_| Error::new(ErrorKind::FromStr(v.clone(), "u32")))?;
+                    .map_err(|_| Error::new(ErrorKind::FromStr(v.to_owned(), "u32")))?;
             }
             _ if i == header.sid => {
                 record.sid = v
                     .parse::<u32>()
-                    .map_err(|_| Error::new(ErrorKind::FromStr(v.clone(), "u32")))?;
+                    .map_err(|_| Error::new(ErrorKind::FromStr(v.to_owned(), "u32")))?;
             }
             _ if i == header.wid => {
                 record.wid = v
                     .trim()
                     .parse::<u16>()
-                    .map_err(|_| Error::new(ErrorKind::FromStr(v.clone(), "u16")))?;
+                    .map_err(|_| Error::new(ErrorKind::FromStr(v.to_owned(), "u16")))?;
             }
             _ if i == header.mname => {
                 record.mname = v.to_string();
@@ -256,8 +258,8 @@ pub fn parse_record(data: &str, header: Header, reader: &mut CsvReader) -> Resul
             }
             _ if i == header.dt => {
                 record.dt = Some(
-                    NaiveDate::parse_from_str(v.as_str(), "%Y/%m/%d")
-                        .map_err(|_| Error::new(ErrorKind::FromStr(v.clone(), "date")))?,
+                    NaiveDate::parse_from_str(v, "%Y/%m/%d")
+                        .map_err(|_| Error::new(ErrorKind::FromStr(v.to_owned(), "date")))?,
                 );
             }
             // Ignore all the fields.
@@ -289,3 +291,46 @@ pub fn parse_csv_number(s: &str) -> Result<f64> {
         .parse::<f64>()
         .map_err(|_| Error::new(ErrorKind::FromStr(s.to_owned(), "f64")))
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_and_headers_round_trip_through_serde() {
+        let record = Record {
+            mid: 100,
+            sid: 1000001,
+            wid: 11751,
+            mname: "盐".to_owned(),
+            sname: "Jmj, local".to_owned(),
+            qt: 2.5,
+            at: -4.0,
+            dt: NaiveDate::from_ymd_opt(2021, 3, 1),
+        };
+        let text = toml::to_string(&record).unwrap();
+        assert!(text.contains("dt = \"2021-03-01\""));
+        let parsed: Record = toml::from_str(&text).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", record));
+
+        // Records without a date leave it out.
+        let undated = Record { dt: None, ..record };
+        let text = toml::to_string(&undated).unwrap();
+        assert!(!text.contains("dt"));
+        let parsed: Record = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.dt, None);
+
+        let header = Header {
+            mid: 0,
+            sid: 2,
+            wid: 4,
+            mname: 1,
+            sname: 3,
+            qt: 5,
+            at: 6,
+            dt: 7,
+        };
+        let parsed: Header = toml::from_str(&toml::to_string(&header).unwrap()).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", header));
+    }
+}
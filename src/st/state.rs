@@ -0,0 +1,698 @@
+//! Persists the intermediate state of an ST aggregation, so new files can be folded into an
+//! earlier aggregation without reading its files again.
+//!
+//! A state is stored as a directory of UTF-8 CSV files, one per table, each with a header row.
+//! The ledger of ingested files identifies each file by the SHA-256 digest of its contents, and
+//! each of its rows carries the [version](LEDGER_VERSION) of this scheme.
+use super::aggregate::*;
+use crate::convert::EncodeType;
+use crate::{CsvReader, CsvReaderOptions, CsvRow, CsvWriter, CsvWriterOptions};
+use crate::{Error, ErrorKind, Result};
+
+use chrono::NaiveDate;
+use fxhash::FxHashSet;
+use sha2::{Digest, Sha256};
+
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const MATERIALS: &str = "materials.csv";
+const STORES: &str = "stores.csv";
+const BRANDS: &str = "brands.csv";
+const REQ_SETS: &str = "req_sets.csv";
+const DAILY_QUANTITIES: &str = "daily_quantities.csv";
+const STORE_MATERIALS: &str = "store_materials.csv";
+const STORE_AMOUNTS: &str = "store_amounts.csv";
+const BRAND_MATERIALS: &str = "brand_materials.csv";
+const LEDGER: &str = "ledger.csv";
+
+/// The version of the ledger, which changes whenever the file hashes are computed differently.
+///
+/// Version 1 hashed files with FxHash, version 2 with SHA-256.
+pub const LEDGER_VERSION: u32 = 2;
+
+/// The SHA-256 digest of the contents of a file, see [`file_hash`].
+pub type FileHash = [u8; 32];
+
+/// The names of the slots of `ISlots` and `FSlots`, in column order.
+macro_rules! slots {
+    ($m:ident!($($args:tt)*)) => {
+        $m!($($args)* local_jmj local_tey local_lkd local_son local_nws outer_store outer_dc other)
+    };
+}
+
+/// The fields of `Brand` besides `brand`, in column order.
+macro_rules! brand_fields {
+    ($m:ident!($($args:tt)*)) => {
+        $m!($($args)*
+            req_amount sku_in_use
+            req_amount_11751 req_amount_11752 req_amount_11753 req_amount_11754
+            req_amount_11755 req_amount_11759 req_amount_11795 req_amount_other
+            sku_in_use_11751 sku_in_use_11752 sku_in_use_11753 sku_in_use_11754
+            sku_in_use_11755 sku_in_use_11759 sku_in_use_11795 sku_in_use_other
+            sku_in_use_alone_11751 sku_in_use_alone_11752 sku_in_use_alone_11753
+            sku_in_use_alone_11754 sku_in_use_alone_11755 sku_in_use_alone_11759
+            sku_in_use_alone_11795 sku_in_use_alone_other)
+    };
+}
+
+/// Pushes the names of the given fields, prefixed, to a header.
+macro_rules! push_names {
+    ($header:ident, $prefix:expr, $($field:ident)*) => {
+        $($header.push(format!("{}{}", $prefix, stringify!($field)));)*
+    };
+}
+
+/// Pushes the values of the given fields of a struct to a row.
+macro_rules! push_values {
+    ($row:ident, $src:expr, $($field:ident)*) => {
+        $($row.push($src.$field.to_string());)*
+    };
+}
+
+/// Parses the given fields of a struct from the next fields of a row.
+macro_rules! parse_values {
+    ($fields:ident, $dst:expr, $($field:ident)*) => {
+        $($dst.$field = parse($fields.next())?;)*
+    };
+}
+
+impl State {
+    /// Loads a state saved with [`State::save`] from the given directory, recovering it first
+    /// if a save failed part-way, see [`State::recover`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file of the state is missing or malformed, or if its ledger was
+    /// written with another [`LEDGER_VERSION`].
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<State> {
+        let dir = dir.as_ref();
+        State::recover(dir)?;
+        let mut state = State::new();
+
+        read_table(dir, MATERIALS, |row| {
+            let mut fields = row.iter();
+            let mut material = Material::new();
+            parse_values!(fields, material, mid wid);
+            material.mname = next(fields.next())?.to_owned();
+            slots!(parse_values!(fields, material.store,));
+            slots!(parse_values!(fields, material.req_times,));
+            slots!(parse_values!(fields, material.quantity,));
+            slots!(parse_values!(fields, material.amount,));
+            state.materials.mmap.insert(material.mid, material);
+            Ok(())
+        })?;
+
+        read_table(dir, STORES, |row| {
+            let mut fields = row.iter();
+            let mut store = Store::new();
+            parse_values!(fields, store, sid);
+            store.sname = next(fields.next())?.to_owned();
+            store.store_type = parse_store_type(next(fields.next())?)?;
+            store.store_loc = parse_store_loc(next(fields.next())?)?;
+            parse_values!(fields, store, sku_in_use amount);
+            state.stores.smap.insert(store.sid, store);
+            Ok(())
+        })?;
+
+        read_table(dir, BRANDS, |row| {
+            let mut fields = row.iter();
+            let mut brand = Brand::new();
+            brand.brand = parse_brand_type(next(fields.next())?)?;
+            brand_fields!(parse_values!(fields, brand,));
+            state.brands.bmap.insert(brand.brand, brand);
+            Ok(())
+        })?;
+
+        read_table(dir, REQ_SETS, |row| {
+            let mut fields = row.iter();
+            let name = next(fields.next())?;
+            let pair = (parse(fields.next())?, parse(fields.next())?);
+            let set = match state.req_set_mut(name) {
+                Some(set) => set,
+                None => return Err(malformed(format!("unknown requisition set `{}`", name))),
+            };
+            set.insert(pair);
+            Ok(())
+        })?;
+
+        read_table(dir, DAILY_QUANTITIES, |row| {
+            let mut fields = row.iter();
+            let mid = parse(fields.next())?;
+            let date = parse_date(next(fields.next())?)?;
+            let qt = parse(fields.next())?;
+            state
+                .materials
+                .daily_req_qt
+                .entry(mid)
+                .or_default()
+                .insert(date, qt);
+            Ok(())
+        })?;
+
+        read_table(dir, STORE_MATERIALS, |row| {
+            let mut fields = row.iter();
+            let sid = parse(fields.next())?;
+            let mid = parse(fields.next())?;
+            state.stores.store_map.entry(sid).or_default().0.insert(mid);
+            Ok(())
+        })?;
+
+        read_table(dir, STORE_AMOUNTS, |row| {
+            let mut fields = row.iter();
+            let sid = parse(fields.next())?;
+            let date = parse_date(next(fields.next())?)?;
+            let at = parse(fields.next())?;
+            state
+                .stores
+                .store_map
+                .entry(sid)
+                .or_default()
+                .1
+                .insert(date, at);
+            Ok(())
+        })?;
+
+        read_table(dir, BRAND_MATERIALS, |row| {
+            let mut fields = row.iter();
+            let mid = parse(fields.next())?;
+            let brand = parse_brand_type(next(fields.next())?)?;
+            state.brands.brand_set.insert((mid, brand));
+            Ok(())
+        })?;
+
+        read_table(dir, LEDGER, |row| {
+            let mut fields = row.iter();
+            let version = next(fields.next())?;
+            if version.parse::<u32>().ok() != Some(LEDGER_VERSION) {
+                return Err(malformed(format!(
+                    "unsupported ledger version `{}`, rebuild the aggregation state",
+                    version
+                )));
+            }
+            let hash = parse_hash(next(fields.next())?)?;
+            let path = PathBuf::from(next(fields.next())?);
+            state.ledger.insert(hash, path);
+            Ok(())
+        })?;
+
+        Ok(state)
+    }
+
+    /// Restores the state saved in the given directory if a save failed after moving it aside,
+    /// and does nothing otherwise.
+    ///
+    /// Call this before checking whether `dir` exists, or a state that is only moved aside looks
+    /// missing.
+    pub fn recover<P: AsRef<Path>>(dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let old = sibling(dir, "old")?;
+        if !dir.exists() && old.is_dir() {
+            fs::rename(&old, dir)?;
+        }
+        Ok(())
+    }
+
+    /// Saves this state to the given directory, creating it if necessary and replacing a state
+    /// saved there before.
+    ///
+    /// The tables are written to a sibling directory first, which then replaces `dir`, so that
+    /// a failure part-way through leaves the state saved before intact.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let temp = sibling(dir, "tmp")?;
+        let old = sibling(dir, "old")?;
+
+        State::recover(dir)?;
+        if temp.exists() {
+            fs::remove_dir_all(&temp)?;
+        }
+        fs::create_dir_all(&temp)?;
+        self.write_tables(&temp)?;
+
+        // A directory cannot be renamed over another one on Windows, so the state saved before
+        // is moved aside first.
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        if dir.exists() {
+            fs::rename(dir, &old)?;
+        }
+        fs::rename(&temp, dir)?;
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the tables of this state to the given directory, which must exist.
+    fn write_tables(&self, dir: &Path) -> Result<()> {
+        let mut header = vec!["mid".to_owned(), "wid".to_owned(), "mname".to_owned()];
+        slots!(push_names!(header, "store.",));
+        slots!(push_names!(header, "req_times.",));
+        slots!(push_names!(header, "quantity.",));
+        slots!(push_names!(header, "amount.",));
+        write_table(
+            dir,
+            MATERIALS,
+            &header,
+            sorted(&self.materials.mmap),
+            |(_, m)| {
+                let mut row = Vec::with_capacity(header.len());
+                push_values!(row, m, mid wid mname);
+                slots!(push_values!(row, m.store,));
+                slots!(push_values!(row, m.req_times,));
+                slots!(push_values!(row, m.quantity,));
+                slots!(push_values!(row, m.amount,));
+                row
+            },
+        )?;
+
+        let header = [
+            "sid",
+            "sname",
+            "store_type",
+            "store_loc",
+            "sku_in_use",
+            "amount",
+        ];
+        write_table(dir, STORES, &header, sorted(&self.stores.smap), |(_, s)| {
+            vec![
+                s.sid.to_string(),
+                s.sname.clone(),
+                format!("{:?}", s.store_type),
+                format!("{:?}", s.store_loc),
+                s.sku_in_use.to_string(),
+                s.amount.to_string(),
+            ]
+        })?;
+
+        let mut header = vec!["brand".to_owned()];
+        brand_fields!(push_names!(header, "",));
+        write_table(dir, BRANDS, &header, sorted(&self.brands.bmap), |(_, b)| {
+            let mut row = vec![format!("{:?}", b.brand)];
+            brand_fields!(push_values!(row, b,));
+            row
+        })?;
+
+        let sets = self.req_sets().flat_map(|(name, set)| {
+            let mut pairs = set.iter().copied().collect::<Vec<_>>();
+            pairs.sort_unstable();
+            pairs.into_iter().map(move |pair| (name, pair))
+        });
+        write_table(
+            dir,
+            REQ_SETS,
+            &["set", "mid", "sid"],
+            sets,
+            |(name, (mid, sid))| vec![name.to_owned(), mid.to_string(), sid.to_string()],
+        )?;
+
+        let daily = sorted(&self.materials.daily_req_qt)
+            .flat_map(|(mid, map)| sorted(map).map(move |(date, qt)| (*mid, *date, *qt)));
+        write_table(
+            dir,
+            DAILY_QUANTITIES,
+            &["mid", "date", "quantity"],
+            daily,
+            |(mid, date, qt)| {
+                vec![
+                    mid.to_string(),
+                    date.format(DATE_FORMAT).to_string(),
+                    qt.to_string(),
+                ]
+            },
+        )?;
+
+        let materials = sorted(&self.stores.store_map)
+            .flat_map(|(sid, (mids, _))| sorted_set(mids).into_iter().map(move |mid| (*sid, mid)));
+        write_table(
+            dir,
+            STORE_MATERIALS,
+            &["sid", "mid"],
+            materials,
+            |(sid, mid)| vec![sid.to_string(), mid.to_string()],
+        )?;
+
+        let amounts = sorted(&self.stores.store_map)
+            .flat_map(|(sid, (_, map))| sorted(map).map(move |(date, at)| (*sid, *date, *at)));
+        write_table(
+            dir,
+            STORE_AMOUNTS,
+            &["sid", "date", "amount"],
+            amounts,
+            |(sid, date, at)| {
+                vec![
+                    sid.to_string(),
+                    date.format(DATE_FORMAT).to_string(),
+                    at.to_string(),
+                ]
+            },
+        )?;
+
+        let brands = sorted_set(&self.brands.brand_set);
+        write_table(
+            dir,
+            BRAND_MATERIALS,
+            &["mid", "brand"],
+            brands,
+            |(mid, brand)| vec![mid.to_string(), format!("{:?}", brand)],
+        )?;
+
+        write_table(
+            dir,
+            LEDGER,
+            &["version", "hash", "path"],
+            self.ledger(),
+            |(hash, path)| {
+                vec![
+                    LEDGER_VERSION.to_string(),
+                    to_hex(hash),
+                    path.display().to_string(),
+                ]
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the requisition sets with their names in the saved state.
+    fn req_sets(&self) -> impl Iterator<Item = (&'static str, &FxHashSet<(u32, u32)>)> {
+        Slot::ALL
+            .iter()
+            .map(move |&slot| (slot_name(slot), &self.materials.req_sets[slot as usize]))
+    }
+
+    fn req_set_mut(&mut self, name: &str) -> Option<&mut FxHashSet<(u32, u32)>> {
+        let slot = Slot::ALL.iter().find(|&&slot| slot_name(slot) == name)?;
+        Some(&mut self.materials.req_sets[*slot as usize])
+    }
+}
+
+/// Returns the path next to `dir` whose name is that of `dir` with the given extension appended.
+fn sibling(dir: &Path, ext: &str) -> Result<PathBuf> {
+    let mut name = match dir.file_name() {
+        Some(name) => name.to_os_string(),
+        None => return Err(Error::new(ErrorKind::Access(format!("{}", dir.display())))),
+    };
+    name.push(".");
+    name.push(ext);
+    Ok(dir.with_file_name(name))
+}
+
+/// Returns the name of the requisition set of a slot in the saved state.
+fn slot_name(slot: Slot) -> &'static str {
+    match slot {
+        Slot::LocalJmj => "jmj",
+        Slot::LocalTey => "tey",
+        Slot::LocalLkd => "lkd",
+        Slot::LocalSon => "son",
+        Slot::LocalNws => "nws",
+        Slot::OuterStore => "os",
+        Slot::OuterDc => "dc",
+        Slot::Other => "oth",
+    }
+}
+
+/// Returns the SHA-256 digest of the contents of the given file, which identifies the file in
+/// the ledger of a [`State`] whatever its path.
+pub fn file_hash<P: AsRef<Path>>(path: P) -> Result<FileHash> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn to_hex(hash: &FileHash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hash(field: &str) -> Result<FileHash> {
+    let invalid = || malformed(format!("`{}` is not a SHA-256 digest", field));
+    if field.len() != 64 || !field.is_ascii() {
+        return Err(invalid());
+    }
+    let mut hash = FileHash::default();
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&field[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(hash)
+}
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+fn write_table<T, I, F, S>(dir: &Path, name: &str, header: &[S], rows: I, mut f: F) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    F: FnMut(T) -> Vec<String>,
+    S: AsRef<str>,
+{
+    let file = BufWriter::new(File::create(dir.join(name))?);
+    let mut wtr = CsvWriter::with_options(file, EncodeType::UTF8, CsvWriterOptions::default());
+    wtr.write_row(header)?;
+    for row in rows {
+        wtr.write_row(f(row))?;
+    }
+    wtr.flush()
+}
+
+fn read_table<F>(dir: &Path, name: &str, mut f: F) -> Result<()>
+where
+    F: FnMut(&CsvRow) -> Result<()>,
+{
+    let path = dir.join(name);
+    if !path.is_file() {
+        return Err(Error::new(ErrorKind::Access(format!("{}", path.display()))));
+    }
+    let file = BufReader::new(File::open(&path)?);
+    let mut rdr = CsvReader::with_options(file, EncodeType::UTF8, CsvReaderOptions::default());
+    rdr.read_header()?;
+    while let Some(row) = rdr.read_row()? {
+        if let Err(e) = f(row) {
+            return Err(Error::new(ErrorKind::Csv(
+                format!("`{}`: {}", path.display(), e),
+                row.position(),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the entries of a map ordered by key, so saved states are reproducible.
+fn sorted<K: Ord, V>(map: &fxhash::FxHashMap<K, V>) -> impl Iterator<Item = (&K, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    entries.into_iter()
+}
+
+fn sorted_set<T: Ord + Copy>(set: &FxHashSet<T>) -> Vec<T> {
+    let mut items = set.iter().copied().collect::<Vec<_>>();
+    items.sort_unstable();
+    items
+}
+
+fn next(field: Option<&str>) -> Result<&str> {
+    field.ok_or_else(|| malformed("missing field".to_owned()))
+}
+
+fn parse<T>(field: Option<&str>) -> Result<T>
+where
+    T: FromStr,
+    T::Err: ToString,
+{
+    let field = next(field)?;
+    field
+        .parse()
+        .map_err(|e: T::Err| malformed(format!("`{}`: {}", field, e.to_string())))
+}
+
+fn parse_date(field: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(field, DATE_FORMAT)
+        .map_err(|e| malformed(format!("`{}`: {}", field, e)))
+}
+
+/// Defines a function parsing the debug name of each variant of an enum.
+macro_rules! parse_enum {
+    ($name:ident, $ty:ident, $($variant:ident)*) => {
+        fn $name(field: &str) -> Result<$ty> {
+            match field {
+                $(stringify!($variant) => Ok($ty::$variant),)*
+                _ => Err(malformed(format!("unknown {} `{}`", stringify!($ty), field))),
+            }
+        }
+    };
+}
+
+parse_enum!(parse_store_type, StoreType, Jmj Tey Lkd Son Nws Oth Dc);
+parse_enum!(parse_store_loc, StoreLoc, Local Outer Unknown);
+parse_enum!(parse_brand_type, BrandType, Jmj Tey Lkd Son Nws Os Dc Oth);
+
+fn malformed(msg: String) -> Error {
+    Error::new(ErrorKind::Other(Cow::Owned(msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::st::parse::Record;
+
+    /// Returns an empty directory under the system temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dpt-state-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(mid: u32, sid: u32, qt: f64, at: f64, day: u32) -> Record {
+        Record {
+            mid,
+            sid,
+            wid: 7,
+            mname: format!("material, \"{}\"", mid),
+            sname: format!("store {}", sid),
+            qt,
+            at,
+            dt: NaiveDate::from_ymd_opt(2021, 3, day),
+        }
+    }
+
+    fn state() -> State {
+        let mut state = State::new();
+        state.observe(
+            &record(1, 11, 2.0, 20.0, 1),
+            (StoreType::Jmj, StoreLoc::Local),
+        );
+        state.observe(
+            &record(1, 12, 1.5, 15.0, 2),
+            (StoreType::Dc, StoreLoc::Outer),
+        );
+        state.observe(
+            &record(2, 11, 3.0, 9.0, 2),
+            (StoreType::Oth, StoreLoc::Unknown),
+        );
+        state.mark_ingested("data/ST 2021-03.csv", [7; 32]);
+        state
+    }
+
+    /// Returns the names and contents of the files of a saved state.
+    fn files(dir: &Path) -> Vec<(String, String)> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, fs::read_to_string(&path).unwrap())
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let root = temp_dir("round-trip");
+        let (first, second) = (root.join("first"), root.join("second"));
+
+        let state = state();
+        state.save(&first).unwrap();
+        let loaded = State::load(&first).unwrap();
+        loaded.save(&second).unwrap();
+        assert_eq!(files(&first), files(&second));
+        assert_eq!(files(&first).len(), 9);
+
+        let (mmap, smap, _) = loaded.maps();
+        assert_eq!(mmap[&1].mname, "material, \"1\"");
+        assert_eq!(mmap[&1].quantity.sum(), 3.5);
+        assert_eq!(smap[&11].amount, 29.0);
+        assert_eq!(
+            loaded.ingested(&[7; 32]),
+            Some(Path::new("data/ST 2021-03.csv"))
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn save_replaces_the_previous_state() {
+        let root = temp_dir("replace");
+        let dir = root.join("state");
+
+        State::new().save(&dir).unwrap();
+        // Leftovers of a save that failed before swapping the directories.
+        fs::create_dir_all(root.join("state.tmp")).unwrap();
+        fs::write(root.join("state.tmp").join("junk.csv"), "junk").unwrap();
+        state().save(&dir).unwrap();
+
+        assert_eq!(State::load(&dir).unwrap().ledger().count(), 1);
+        assert!(!root.join("state.tmp").exists());
+        assert!(!root.join("state.old").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn load_recovers_the_state_moved_aside() {
+        let root = temp_dir("recover");
+        let dir = root.join("state");
+
+        // A save that failed between moving the old state aside and moving the new one in.
+        state().save(&dir).unwrap();
+        fs::rename(&dir, root.join("state.old")).unwrap();
+        let loaded = State::load(&dir).unwrap();
+        assert_eq!(
+            loaded.ingested(&[7; 32]),
+            Some(Path::new("data/ST 2021-03.csv"))
+        );
+        assert!(dir.is_dir());
+        assert!(!root.join("state.old").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hashes_file_contents_with_sha256() {
+        let root = temp_dir("hash");
+        let path = root.join("abc.csv");
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            to_hex(&file_hash(&path).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(parse_hash(&to_hex(&[7; 32])).unwrap(), [7; 32]);
+        assert!(parse_hash("abc").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_missing_and_malformed_tables() {
+        let root = temp_dir("malformed");
+        let dir = root.join("state");
+        assert!(State::load(&dir).is_err());
+
+        state().save(&dir).unwrap();
+        fs::write(dir.join(STORES), "sid\r\nabc\r\n").unwrap();
+        assert!(State::load(&dir).is_err());
+
+        // A ledger of version 1, whose hashes were computed with FxHash.
+        state().save(&dir).unwrap();
+        fs::write(dir.join(LEDGER), "hash,path\r\n00000000deadbeef,a.csv\r\n").unwrap();
+        assert!(State::load(&dir).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+//! Tracks which input lines contributed to aggregated materials and stores.
+use fxhash::FxHashMap;
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// The input lines a material or a store was aggregated from, as line ranges per file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    lines: BTreeMap<PathBuf, Vec<RangeInclusive<u64>>>,
+}
+
+impl Provenance {
+    /// Adds the given (one-based, inclusive) line range of a file, merging it with the last
+    /// range of that file if they are adjacent.
+    pub fn add(&mut self, path: &Path, lines: RangeInclusive<u64>) {
+        let ranges = match self.lines.get_mut(path) {
+            Some(ranges) => ranges,
+            None => self.lines.entry(path.to_path_buf()).or_default(),
+        };
+        match ranges.last_mut() {
+            Some(last) if *last.end() + 1 >= *lines.start() && *last.start() <= *lines.start() => {
+                *last = *last.start()..=(*last.end()).max(*lines.end());
+            }
+            _ => ranges.push(lines),
+        }
+    }
+
+    /// Returns an iterator over the files and their line ranges, ordered by path.
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &[RangeInclusive<u64>])> {
+        self.lines
+            .iter()
+            .map(|(path, ranges)| (path.as_path(), ranges.as_slice()))
+    }
+
+    /// Returns the total number of lines.
+    pub fn len(&self) -> u64 {
+        self.lines
+            .values()
+            .flatten()
+            .map(|r| r.end() - r.start() + 1)
+            .sum()
+    }
+
+    /// Returns `true` if no line has been added.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Returns `true` if the given line of a file has been added.
+    pub fn contains(&self, path: &Path, line: u64) -> bool {
+        self.lines
+            .get(path)
+            .is_some_and(|ranges| ranges.iter().any(|r| r.contains(&line)))
+    }
+}
+
+/// The provenance of each aggregated material and store, keyed by material and store ids.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    pub materials: FxHashMap<u32, Provenance>,
+    pub stores: FxHashMap<u32, Provenance>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(provenance: &Provenance) -> Vec<(&Path, Vec<RangeInclusive<u64>>)> {
+        provenance
+            .iter()
+            .map(|(path, ranges)| (path, ranges.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_adjacent_and_overlapping_ranges() {
+        let path = Path::new("a.csv");
+        let mut provenance = Provenance::default();
+        provenance.add(path, 2..=2);
+        provenance.add(path, 3..=3);
+        provenance.add(path, 3..=5);
+        provenance.add(path, 4..=4);
+        assert_eq!(ranges(&provenance), vec![(path, vec![2..=5])]);
+        assert_eq!(provenance.len(), 4);
+    }
+
+    #[test]
+    fn keeps_separate_ranges_apart() {
+        let path = Path::new("a.csv");
+        let mut provenance = Provenance::default();
+        provenance.add(path, 2..=3);
+        provenance.add(path, 5..=5);
+        // Only the last range is merged into, so an earlier line starts a new range.
+        provenance.add(path, 1..=1);
+        assert_eq!(ranges(&provenance), vec![(path, vec![2..=3, 5..=5, 1..=1])]);
+        assert_eq!(provenance.len(), 4);
+        assert!(provenance.contains(path, 3));
+        assert!(!provenance.contains(path, 4));
+    }
+
+    #[test]
+    fn orders_files_by_path() {
+        let (a, b) = (Path::new("a.csv"), Path::new("b.csv"));
+        let mut provenance = Provenance::default();
+        assert!(provenance.is_empty());
+        provenance.add(b, 2..=2);
+        provenance.add(a, 3..=4);
+        provenance.add(b, 3..=3);
+        assert_eq!(
+            ranges(&provenance),
+            vec![(a, vec![3..=4]), (b, vec![2..=3])]
+        );
+        assert!(!provenance.contains(a, 2));
+        assert!(!provenance.contains(Path::new("c.csv"), 2));
+        assert_eq!(provenance.len(), 4);
+    }
+}
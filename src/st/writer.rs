@@ -8,14 +8,14 @@ use std::io::LineWriter;
 
 /// Write aggregation result to files.
 pub fn write_aggregation_result(maps: (MMap, SMap, BMap), out_dir: &str) -> Result<()> {
-    write_mmap(maps.0, out_dir)?;
-    write_smap(maps.1, out_dir)?;
-    write_bmap(maps.2, out_dir)?;
+    write_mmap(&maps.0, out_dir)?;
+    write_smap(&maps.1, out_dir)?;
+    write_bmap(&maps.2, out_dir)?;
     Ok(())
 }
 
 /// `MMap` writer.
-pub fn write_mmap(mmap: MMap, out_dir: &str) -> Result<()> {
+pub fn write_mmap(mmap: &MMap, out_dir: &str) -> Result<()> {
     // Set up open options.
     let mut file = OpenOptions::new()
         .write(true)
@@ -132,7 +132,7 @@ pub fn write_mmap(mmap: MMap, out_dir: &str) -> Result<()> {
 }
 
 /// `SMap` writer.
-pub fn write_smap(smap: SMap, out_dir: &str) -> Result<()> {
+pub fn write_smap(smap: &SMap, out_dir: &str) -> Result<()> {
     // Set up open options.
     let mut file = OpenOptions::new()
         .write(true)
@@ -191,7 +191,7 @@ pub fn write_smap(smap: SMap, out_dir: &str) -> Result<()> {
 }
 
 /// `BMap` writer.
-pub fn write_bmap(bmap: BMap, out_dir: &str) -> Result<()> {
+pub fn write_bmap(bmap: &BMap, out_dir: &str) -> Result<()> {
     // Set up open options.
     let mut file = OpenOptions::new()
         .write(true)
@@ -229,7 +229,7 @@ pub fn write_bmap(bmap: BMap, out_dir: &str) -> Result<()> {
 
     // Write records.
     // Transform `bmap` into a sorted vector.
-    let mut vec = bmap.into_iter().map(|x| x.1).collect::<Vec<_>>();
+    let mut vec = bmap.values().collect::<Vec<_>>();
     vec.sort_unstable_by(|a, b| a.brand.cmp(&b.brand));
     for v in vec {
         let record = format!(
@@ -0,0 +1,106 @@
+use crate::convert::{encode, EncodeType};
+use crate::reader::Terminator;
+use crate::Result;
+
+use std::io::Write;
+
+static STRING_INITIAL_CAPACITY: usize = 256usize;
+
+/// A CSV writer that quotes fields when necessary.
+///
+/// A field is enclosed if it contains the delimiter, the text enclosure or a line break, and
+/// text enclosures inside an enclosed field are doubled.
+pub struct CsvWriter<W: Write> {
+    wtr: W,
+    encoding: EncodeType,
+    options: CsvWriterOptions,
+    line: String,
+    buf: Vec<u8>,
+}
+
+/// CSV writer options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvWriterOptions {
+    pub delimiter: char,
+    pub text_enclosure: char,
+    pub terminator: Terminator,
+}
+
+impl Default for CsvWriterOptions {
+    fn default() -> CsvWriterOptions {
+        CsvWriterOptions {
+            delimiter: ',',
+            text_enclosure: '"',
+            terminator: Terminator::CRLF,
+        }
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a `CsvWriter` that writes UTF-8 encoded, comma separated records.
+    pub fn new(wtr: W) -> CsvWriter<W> {
+        CsvWriter::with_options(wtr, EncodeType::UTF8, Default::default())
+    }
+
+    /// Creates a `CsvWriter` with the given encoding and options.
+    pub fn with_options(wtr: W, encoding: EncodeType, options: CsvWriterOptions) -> CsvWriter<W> {
+        CsvWriter {
+            wtr,
+            encoding,
+            options,
+            line: String::with_capacity(STRING_INITIAL_CAPACITY),
+            buf: Vec::with_capacity(STRING_INITIAL_CAPACITY),
+        }
+    }
+
+    /// Writes a single record.
+    pub fn write_row<I, S>(&mut self, fields: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let delimiter = self.options.delimiter;
+        let text_enclosure = self.options.text_enclosure;
+
+        self.line.clear();
+        for (i, field) in fields.into_iter().enumerate() {
+            let field = field.as_ref();
+            if i > 0 {
+                self.line.push(delimiter);
+            }
+
+            let needs_enclosure = field
+                .chars()
+                .any(|c| c == delimiter || c == text_enclosure || c == '\r' || c == '\n');
+            if needs_enclosure {
+                self.line.push(text_enclosure);
+                for c in field.chars() {
+                    if c == text_enclosure {
+                        self.line.push(text_enclosure);
+                    }
+                    self.line.push(c);
+                }
+                self.line.push(text_enclosure);
+            } else {
+                self.line.push_str(field);
+            }
+        }
+        self.line.push_str(self.options.terminator.as_str());
+
+        encode(&self.line, self.encoding, &mut self.buf)?;
+        self.wtr.write_all(&self.buf)?;
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+
+    /// Unwraps this `CsvWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
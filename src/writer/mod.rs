@@ -0,0 +1,2 @@
+mod csv_writer;
+pub use self::csv_writer::{CsvWriter, CsvWriterOptions};